@@ -14,3 +14,13 @@ impl From<Triangle> for AnyTriangle {
             .unwrap_or_else(|| AnyTriangle::Triangle(triangle))
     }
 }
+
+impl AnyTriangle {
+    #[inline]
+    pub fn as_arrays(&self) -> [[f32; 3]; 3] {
+        match self {
+            AnyTriangle::Triangle(t) => t.as_arrays(),
+            AnyTriangle::AxiallyAlignedTriangle(t) => t.as_arrays(),
+        }
+    }
+}
@@ -52,7 +52,8 @@ impl Sphere {
         }
         let t1 = (-b - discriminant.sqrt()) / (2.0 * a);
         let t2 = (-b + discriminant.sqrt()) / (2.0 * a);
-        let t = if t1 <= t2 { t1 } else { t2 };
+        // The far side is hit when the ray starts inside the sphere.
+        let t = if t1 >= 0.0 { t1 } else { t2 };
 
         let normal = (p + t * ray.direction) / self.radius;
         Some(SphereIntersection { t, normal })
@@ -126,6 +127,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn intersect_ray_from_inside() {
+        let sphere = Sphere {
+            center: Vec3::new(1.0, 0.0, 0.0),
+            radius: 1.0,
+        };
+        let ray = Ray::between(Vec3::new(1.0, 0.0, 0.0), Vec3::new(3.0, 0.0, 0.0));
+
+        let actual = sphere.intersect_ray(&ray);
+
+        assert_eq!(
+            actual,
+            Some(SphereIntersection {
+                t: 0.5,
+                normal: Vec3::new(1.0, 0.0, 0.0),
+            })
+        );
+    }
+
     #[test]
     fn intersect_ray_not_intersecting() {
         let sphere = Sphere {
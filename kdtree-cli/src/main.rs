@@ -15,7 +15,7 @@ use std::{
     time::Instant,
 };
 use time::Duration;
-use wavefront::obj;
+use wavefront::{ParseMode, obj};
 
 #[derive(Clone, Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -161,23 +161,29 @@ fn statistics(geometries: &[impl Geometry], tree: &KdNode) -> KdTreeStatistics {
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
     eprintln!("Reading {:?}...", &args.input);
-    let obj = obj::obj(&mut BufReader::new(File::open(args.input)?))?;
+    let obj = obj::obj(
+        &mut BufReader::new(File::open(&args.input)?),
+        ParseMode::Lenient,
+    )
+    .map_err(|error| error.with_file(&args.input))?;
+    for warning in &obj.warnings {
+        eprintln!("  Warning: {warning}");
+    }
     let geometries = obj
         .chunks
         .iter()
         .flat_map(|chunk| {
-            chunk.faces.iter().map(|face| {
-                assert!(
-                    face.points.len() == 3,
-                    "Only tringular faces supported but found {} vertices.",
-                    face.points.len()
-                );
-                AnyTriangle::from(Triangle {
-                    v0: obj.index_vertex(&face.points[0]).into(),
-                    v1: obj.index_vertex(&face.points[1]).into(),
-                    v2: obj.index_vertex(&face.points[2]).into(),
+            chunk
+                .faces
+                .iter()
+                .flat_map(|face| obj.triangulate(face))
+                .map(|[p0, p1, p2]| {
+                    AnyTriangle::from(Triangle {
+                        v0: obj.index_vertex(p0).into(),
+                        v1: obj.index_vertex(p1).into(),
+                        v2: obj.index_vertex(p2).into(),
+                    })
                 })
-            })
         })
         .collect::<Vec<_>>();
     eprintln!("  Geometries: {}", geometries.len());
@@ -3,6 +3,7 @@ use kdtree::sah::SahCost;
 use ray_tester::kdtree_ray_tester;
 use reducer::kdtree_reduce;
 use size::Size;
+use tracing::sampling::RussianRoulette;
 
 mod checked_intersection;
 mod ray_bouncer;
@@ -34,6 +35,9 @@ enum Commands {
         /// Max number of bounces to test
         #[arg(short, long, default_value_t = 10)]
         bounces: u32,
+        /// Number of bounces before paths may be terminated by Russian roulette
+        #[arg(long, default_value_t = RussianRoulette::default().min_bounces)]
+        russian_roulette_bounces: u32,
 
         /// SAH kd-tree traverse cost
         #[arg(long, default_value_t = SahCost::default().traverse_cost)]
@@ -72,6 +76,7 @@ fn main() -> std::io::Result<()> {
             output,
             size,
             bounces,
+            russian_roulette_bounces,
             traverse_cost,
             intersect_cost,
             empty_factor,
@@ -80,6 +85,9 @@ fn main() -> std::io::Result<()> {
             output,
             size,
             bounces,
+            RussianRoulette {
+                min_bounces: russian_roulette_bounces,
+            },
             SahCost {
                 traverse_cost,
                 intersect_cost,
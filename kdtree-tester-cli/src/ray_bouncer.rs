@@ -5,26 +5,27 @@ use geometry::{
     ray::Ray,
     triangle::TriangleIntersection,
 };
-use glam::{UVec2, Vec2};
+use glam::{UVec2, Vec2, Vec3};
 use kdtree::KdNode;
 use rand::{SeedableRng, rngs::SmallRng};
 use std::ops::RangeInclusive;
 use tracing::{
     camera::Pinhole,
-    light::Light,
+    light_sampler::{LightSampler, ShadingPoint},
     material::{Material, Surface},
     properties::TriangleProperties,
-    sampling::uniform_sample_unit_square,
+    sampling::{RussianRoulette, concentric_sample_unit_disk, uniform_sample_unit_square},
 };
 
 pub struct RayBouncer {
     pub geometries: Vec<AnyTriangle>,
     pub properties: Vec<TriangleProperties>,
     pub materials: Vec<Material>,
-    pub lights: Vec<Light>,
+    pub lights: LightSampler,
     pub kdtree: KdNode,
     pub camera: Pinhole,
     pub bounces: u32,
+    pub russian_roulette: RussianRoulette,
     pub size: UVec2,
 }
 
@@ -59,6 +60,7 @@ impl RayBouncer {
         mut rng: SmallRng,
         ray: &Ray,
         accumulated_bounces: u32,
+        mut transport: Vec3,
     ) -> Option<CheckedIntersection<TriangleIntersection>> {
         if accumulated_bounces >= self.bounces {
             return None;
@@ -71,7 +73,7 @@ impl RayBouncer {
         let IndexedIntersection { index, inner } = intersection.reference?;
         let properties = &self.properties[index as usize];
 
-        let wi = -ray.direction;
+        let wi = -ray.direction.normalize();
         let n = properties.compute_normal(&inner);
         let uv = properties.compute_texcoord(&inner);
         let material = &self.materials[properties.material];
@@ -81,12 +83,16 @@ impl RayBouncer {
         let point_above = point + offset;
         let point_below = point - offset;
 
-        let incoming_fails = self
+        let selected = self
             .lights
-            .iter()
+            .sample(ShadingPoint { point, normal: n }, &mut rng)
+            .map(|(light, _)| light);
+        let incoming_fails = selected
+            .into_iter()
+            .chain(self.lights.infinite())
             .filter_map(|light| {
-                let (shadow_ray, t_range) = light.sample_shadow_ray(point_above, &mut rng);
-                let shadow = self.checked_ray_intersect(&shadow_ray, t_range);
+                let sample = light.sample(point_above, &mut rng);
+                let shadow = self.checked_ray_intersect(&sample.shadow_ray, sample.t_range);
                 (!shadow.is_valid()).then_some(shadow)
             })
             .collect::<Vec<_>>();
@@ -95,6 +101,20 @@ impl RayBouncer {
         }
 
         let sample = material.sample(&Surface { wi, n, uv }, &mut rng);
+        if sample.pdf == 0.0 {
+            return None;
+        }
+        if sample.is_delta {
+            transport *= sample.bsdf / sample.pdf;
+        } else {
+            transport *= sample.bsdf * (sample.wo.dot(n).abs() / sample.pdf);
+        }
+        if !self
+            .russian_roulette
+            .survive(accumulated_bounces + 1, &mut transport, &mut rng)
+        {
+            return None;
+        }
         let next_ray = Ray::new(
             if sample.wo.dot(n) >= 0.0 {
                 point_above
@@ -104,7 +124,7 @@ impl RayBouncer {
             sample.wo,
         );
 
-        self.bounce(rng, &next_ray, accumulated_bounces + 1)
+        self.bounce(rng, &next_ray, accumulated_bounces + 1, transport)
     }
 
     pub fn bounce_pixel(
@@ -115,7 +135,9 @@ impl RayBouncer {
         let mut rng = SmallRng::seed_from_u64(u64::from(y * self.size.y + x));
         let pixel_center = Vec2::new(x as f32, y as f32) + uniform_sample_unit_square(&mut rng);
         let scene_direction = pixel_center / self.size.as_vec2();
-        let ray = self.camera.ray(scene_direction);
-        self.bounce(rng, &ray, 0)
+        let ray = self
+            .camera
+            .ray(scene_direction, concentric_sample_unit_disk(&mut rng));
+        self.bounce(rng, &ray, 0, Vec3::ONE)
     }
 }
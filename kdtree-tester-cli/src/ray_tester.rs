@@ -5,8 +5,15 @@ use std::{
     io::{BufWriter, Write},
     path::PathBuf,
 };
-use tracing::{camera::Pinhole, light::Light, material::Material, properties::from_wavefront};
-use wavefront::read_obj_and_mtl_with_print_logging;
+use tracing::{
+    camera::Pinhole,
+    light::{Light, TriangleLight},
+    light_sampler::{LightSampler, LightSelection},
+    material::Material,
+    properties::{DEFAULT_CREASE_ANGLE, from_wavefront},
+    sampling::RussianRoulette,
+};
+use wavefront::{ParseMode, read_obj_and_mtl_with_print_logging};
 
 use crate::{ray_bouncer::RayBouncer, size::Size};
 
@@ -15,10 +22,11 @@ pub(crate) fn kdtree_ray_tester(
     output: Option<PathBuf>,
     size: Size,
     bounces: u32,
+    russian_roulette: RussianRoulette,
     sah: SahCost,
 ) -> std::io::Result<()> {
-    let (obj, mtl, mtl_path) = read_obj_and_mtl_with_print_logging(&input).unwrap();
-    let (geometries, properties) = from_wavefront(&obj, &mtl);
+    let (obj, mtl, mtl_path) = read_obj_and_mtl_with_print_logging(&input, ParseMode::Lenient)?;
+    let (geometries, properties) = from_wavefront(&obj, &mtl, DEFAULT_CREASE_ANGLE);
 
     println!("Building kdtree...");
     let kdtree = build_kdtree(&geometries, &sah);
@@ -26,19 +34,27 @@ pub(crate) fn kdtree_ray_tester(
     println!("Testing up to {} rays...", size.x * size.y * bounces);
     let camera = Pinhole::new(mtl.cameras[0].clone().into(), size.as_uvec2());
     let image_directory = mtl_path.parent().unwrap();
+    let materials: Vec<Material> = mtl
+        .materials
+        .iter()
+        .map(|m| Material::load_from_mtl(image_directory, m))
+        .collect();
+    let mut lights: Vec<Light> = mtl.lights.iter().map(Light::from).collect();
+    lights.extend(
+        TriangleLight::from_mesh(&geometries, &properties, &materials)
+            .into_iter()
+            .map(Light::from),
+    );
     let bouncer = RayBouncer {
         geometries,
         properties,
-        materials: mtl
-            .materials
-            .iter()
-            .map(|m| Material::load_from_mtl(image_directory, m))
-            .collect(),
-        lights: mtl.lights.iter().map(Light::from).collect(),
+        materials,
+        lights: LightSampler::new(lights, LightSelection::default()),
         kdtree,
         camera,
         size: size.as_uvec2(),
         bounces,
+        russian_roulette,
     };
 
     let xs = 0..size.x;
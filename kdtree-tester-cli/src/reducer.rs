@@ -14,7 +14,7 @@ use geometry::{
 };
 use kdtree::{KdNode, build::build_kdtree, format::write_tree_json, sah::SahCost};
 use tracing::measure;
-use wavefront::obj;
+use wavefront::{ParseMode, obj};
 
 use crate::checked_intersection::CheckedIntersection;
 
@@ -122,7 +122,11 @@ pub(crate) fn kdtree_reduce(
     eprintln!("    Actual: {:?}", &intersection.kdtree);
 
     eprintln!("Loading {}...", input.display());
-    let obj = obj::obj(&mut BufReader::new(File::open(input)?))?;
+    let obj = obj::obj(&mut BufReader::new(File::open(&input)?), ParseMode::Lenient)
+        .map_err(|error| error.with_file(&input))?;
+    for warning in &obj.warnings {
+        eprintln!("  Warning: {warning}");
+    }
     eprintln!("  Chunks: {}", obj.chunks.len());
     eprintln!("  Vertices: {}", obj.vertices.len());
     eprintln!("  Normals: {}", obj.normals.len());
@@ -133,18 +137,17 @@ pub(crate) fn kdtree_reduce(
         .chunks
         .iter()
         .flat_map(|chunk| {
-            chunk.faces.iter().map(|face| {
-                assert!(
-                    face.points.len() == 3,
-                    "Only tringular faces supported but found {} vertices.",
-                    face.points.len()
-                );
-                AnyTriangle::from(Triangle {
-                    v0: obj.index_vertex(&face.points[0]).into(),
-                    v1: obj.index_vertex(&face.points[1]).into(),
-                    v2: obj.index_vertex(&face.points[2]).into(),
+            chunk
+                .faces
+                .iter()
+                .flat_map(|face| obj.triangulate(face))
+                .map(|[p0, p1, p2]| {
+                    AnyTriangle::from(Triangle {
+                        v0: obj.index_vertex(p0).into(),
+                        v1: obj.index_vertex(p1).into(),
+                        v2: obj.index_vertex(p2).into(),
+                    })
                 })
-            })
         })
         .collect::<Vec<_>>();
     eprintln!("  Geometries: {}", geometries.len());
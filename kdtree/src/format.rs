@@ -11,10 +11,7 @@ where
         "{:?}",
         triangles
             .iter()
-            .map(|t| match t {
-                AnyTriangle::Triangle(t) => t.as_arrays(),
-                AnyTriangle::AxiallyAlignedTriangle(t) => t.as_arrays(),
-            })
+            .map(AnyTriangle::as_arrays)
             .collect::<Vec<_>>()
     )
 }
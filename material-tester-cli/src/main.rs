@@ -9,15 +9,19 @@ use std::{
     sync::mpsc::{self, Receiver},
     thread,
 };
-use time::Duration;
 use tracing::{
     camera::{Camera, Pinhole},
     collections::SphereCollection,
+    filter::PixelFilter,
     light::{DirectionalLight, Light},
+    light_sampler::{LightSampler, LightSelection},
     material::{Material, albedo::AlbedoSource},
     pathtracer::Pathtracer,
     properties::SphereProperties,
-    worker::render_parallel_iterations,
+    sampler::SamplerKind,
+    sampling::RussianRoulette,
+    tonemap::DisplayTransform,
+    worker::{Accumulation, Progress, StopTarget, render_parallel_iterations},
 };
 
 #[derive(Clone, Copy, Debug)]
@@ -74,13 +78,13 @@ struct Args {
     threads: u32,
 }
 
-fn printer_thread(threads: u32, iterations: u32, rx: &Receiver<Duration>) {
+fn printer_thread(threads: u32, iterations: u32, rx: &Receiver<Progress>) {
     let mut total = 0.0;
     let mut total_squared = 0.0;
     let mut completed = 0;
     loop {
-        if let Ok(duration) = rx.recv() {
-            let seconds = duration.as_seconds_f64();
+        if let Ok(progress) = rx.recv() {
+            let seconds = progress.duration.as_seconds_f64();
             total += seconds;
             total_squared += seconds * seconds;
             completed += 1;
@@ -133,6 +137,8 @@ fn setup_scene(args: &Args) -> (Pinhole, Pathtracer<SphereCollection>) {
         schlick_f0: Vec3::ZERO,
         transmission: 0.0,
         ior: 1.0,
+        emittance: Vec3::ZERO,
+        roughness: 0.0,
     };
     let materials = (0..spheres.len())
         .map(|i| material(i as f32 * 1.0 / (spheres.len() - 1) as f32))
@@ -152,9 +158,13 @@ fn setup_scene(args: &Args) -> (Pinhole, Pathtracer<SphereCollection>) {
     };
     let pathtracer = Pathtracer {
         max_bounces: args.max_bounces,
+        russian_roulette: RussianRoulette::default(),
+        filter: PixelFilter::default(),
+        sampler: SamplerKind::default(),
+        seed: 0,
         geometry_collection,
-        lights: lights.map(Light::from).to_vec(),
-        environment: Vec3::new(0.8, 0.8, 0.8),
+        lights: LightSampler::new(lights.map(Light::from).to_vec(), LightSelection::default()),
+        environment: Vec3::new(0.8, 0.8, 0.8).into(),
     };
     (pinhole, pathtracer)
 }
@@ -172,19 +182,26 @@ fn main() {
     thread::scope(|s| {
         let (tx, rx) = mpsc::channel();
         let printer = s.spawn(move || printer_thread(args.threads, total_iterations, &rx));
-        let (duration, image) = render_parallel_iterations(
+        let (duration, accumulation) = render_parallel_iterations(
             &pathtracer,
             &pinhole,
-            args.size.as_uvec2(),
             args.threads,
-            args.iterations_per_thread,
+            &StopTarget {
+                iterations: Some(total_iterations),
+                ..StopTarget::default()
+            },
+            None,
+            Accumulation::new(args.size.as_uvec2()),
+            None,
             tx,
         );
         printer.join().unwrap();
         println!("Total time: {duration:.2}");
 
         println!("Writing {}...", args.output.display());
-        image
+        accumulation
+            .buffer
+            .to_rgb_image(&DisplayTransform::default())
             .save_with_format(&args.output, ImageFormat::Png)
             .unwrap();
     });
@@ -1,20 +1,37 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use glam::{UVec2, Vec3};
 use image::ImageFormat;
 use kdtree::{build::build_kdtree, sah::SahCost};
 use std::{
+    ffi::OsStr,
     fmt::Display,
     io::Write,
+    path::Path,
     str::FromStr,
     sync::mpsc::{self, Receiver},
     thread,
 };
 use time::Duration;
 use tracing::{
-    camera::Pinhole, collections::TriangleCollection, light::Light, material::Material,
-    pathtracer::Pathtracer, properties::from_wavefront, worker::render_parallel_iterations,
+    adaptive::AdaptiveSampling,
+    camera::Pinhole,
+    checkpoint::{Checkpoint, scene_hash},
+    collections::TriangleCollection,
+    environment::{Environment, EnvironmentMap},
+    filter::PixelFilter,
+    image_buffer::ImageBuffer,
+    light::{Light, TriangleLight},
+    light_sampler::{LightSampler, LightSelection},
+    material::Material,
+    pathtracer::Pathtracer,
+    properties::{DEFAULT_CREASE_ANGLE, from_wavefront},
+    sampler::SamplerKind,
+    sampling::RussianRoulette,
+    sky::Sky,
+    tonemap::{DisplayTransform, ToneMapping},
+    worker::{Accumulation, CheckpointSchedule, Progress, StopTarget, render_parallel_iterations},
 };
-use wavefront::read_obj_and_mtl_with_print_logging;
+use wavefront::{ParseMode, mtl, read_obj_and_mtl_with_print_logging};
 
 #[derive(Clone, Copy, Debug)]
 struct Size {
@@ -50,6 +67,39 @@ impl Display for Size {
     }
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum FilterArg {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    BlackmanHarris,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SamplerArg {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum LightSelectionArg {
+    Uniform,
+    Power,
+    Tree,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ToneMappingArg {
+    Clamp,
+    Reinhard,
+    ExtendedReinhard,
+    Aces,
+    Agx,
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
@@ -57,7 +107,15 @@ struct Args {
     #[arg(short = 'i', long, required = true)]
     input: std::path::PathBuf,
 
-    /// PNG output path
+    /// Fail on unknown or malformed statements in the scene files instead of skipping them with a
+    /// warning
+    #[arg(long)]
+    strict: bool,
+    /// Largest angle in degrees between faces in a smoothing group that is smoothed over when
+    /// generating missing vertex normals
+    #[arg(long, default_value_t = DEFAULT_CREASE_ANGLE)]
+    crease_angle: f32,
+    /// Output path, the format is chosen from the extension (png, exr or hdr)
     #[arg(short, long, required = true)]
     output: std::path::PathBuf,
     /// Image size in pixels
@@ -66,12 +124,82 @@ struct Args {
     /// Max number of bounces
     #[arg(short, long, default_value_t = 10)]
     max_bounces: u8,
-    /// Iterations to execute per thread
-    #[arg(short = 'n', long, default_value_t = 4)]
-    iterations_per_thread: u32,
+    /// Number of bounces before paths may be terminated by Russian roulette
+    #[arg(long, default_value_t = RussianRoulette::default().min_bounces)]
+    russian_roulette_bounces: u32,
+    /// Pixel reconstruction filter
+    #[arg(long, value_enum, default_value_t = FilterArg::Box)]
+    filter: FilterArg,
+    /// Pixel filter radius, defaults to the radius of the chosen filter
+    #[arg(long)]
+    filter_radius: Option<f32>,
+    /// Equirectangular environment map, for example a Radiance HDR file, replaces the sky of the
+    /// scene or the constant grey background
+    #[arg(long)]
+    environment: Option<std::path::PathBuf>,
+    /// Rotation of the environment map around the up axis in degrees
+    #[arg(long, default_value_t = 0.0)]
+    environment_rotation: f32,
+    /// Radiance scale of the environment map
+    #[arg(long, default_value_t = 1.0)]
+    environment_intensity: f32,
+    /// How the one light sampled at each path vertex is chosen
+    #[arg(long, value_enum, default_value_t = LightSelectionArg::Power)]
+    light_selection: LightSelectionArg,
+    /// Sampler generating the random numbers of each pixel sample
+    #[arg(long, value_enum, default_value_t = SamplerArg::Independent)]
+    sampler: SamplerArg,
+    /// Strata per axis of the stratified sampler
+    #[arg(long, default_value_t = 4)]
+    strata: u32,
+    /// Seed of the sampler, renders of the same number of iterations with the same seed are
+    /// identical for any number of threads
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Iterations to execute per thread, defaults to 4 when no other stopping criterion is given
+    #[arg(short = 'n', long)]
+    iterations_per_thread: Option<u32>,
+    /// Wall-clock time budget, for example 90s, 10m or 2h
+    #[arg(long, value_parser = parse_duration)]
+    time: Option<std::time::Duration>,
+    /// Stop when the estimated mean relative error of the pixels is below this value
+    #[arg(long)]
+    max_relative_error: Option<f32>,
     /// Number of threads
     #[arg(short, long, default_value_t = 1)]
     threads: u32,
+    /// Spend iterations on the noisiest tiles once the uniform iterations are done
+    #[arg(long)]
+    adaptive: bool,
+    /// Adaptive sampling tile width and height in pixels
+    #[arg(long, default_value_t = AdaptiveSampling::default().tile_size)]
+    adaptive_tile_size: u32,
+    /// Iterations sampling every pixel before adaptive sampling starts
+    #[arg(long, default_value_t = AdaptiveSampling::default().uniform_iterations)]
+    adaptive_uniform_iterations: u32,
+    /// Fraction of the tiles rendered by each adaptive iteration
+    #[arg(long, default_value_t = AdaptiveSampling::default().fraction)]
+    adaptive_fraction: f32,
+
+    /// Tone mapping operator for PNG output
+    #[arg(long, value_enum, default_value_t = ToneMappingArg::Clamp)]
+    tone_mapping: ToneMappingArg,
+    /// Luminance mapped to white by the extended Reinhard operator
+    #[arg(long, default_value_t = 4.0)]
+    white_point: f32,
+    /// Periodically write the accumulated render to this file, defaults to the resumed file
+    #[arg(long)]
+    checkpoint: Option<std::path::PathBuf>,
+    /// Seconds between checkpoints
+    #[arg(long, default_value_t = 60)]
+    checkpoint_interval: u64,
+    /// Continue a render from a checkpoint file until a stopping criterion is reached
+    #[arg(long)]
+    resume: Option<std::path::PathBuf>,
+
+    /// Exposure adjustment in stops (EV) for PNG output
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    exposure: f32,
 
     /// SAH kd-tree traverse cost
     #[arg(long, default_value_t = SahCost::default().traverse_cost)]
@@ -84,7 +212,39 @@ struct Args {
     empty_factor: f32,
 }
 
-fn printer_thread(threads: u32, iterations: u32, rx: &Receiver<Duration>) {
+fn parse_duration(s: &str) -> Result<std::time::Duration, String> {
+    let (value, unit) = s.split_at(s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len()));
+    let value: f64 = value
+        .parse()
+        .map_err(|e| format!("invalid duration {s:?}: {e}"))?;
+    let seconds = match unit {
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => {
+            return Err(format!(
+                "unknown duration unit {unit:?}, expected s, m or h"
+            ));
+        }
+    };
+    std::time::Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+fn describe_target(target: &StopTarget) -> String {
+    let mut targets = Vec::new();
+    if let Some(iterations) = target.iterations {
+        targets.push(format!("{iterations} total iteration(s)"));
+    }
+    if let Some(time) = target.time {
+        targets.push(format!("{:.2}", Duration::seconds_f64(time.as_secs_f64())));
+    }
+    if let Some(relative_error) = target.relative_error {
+        targets.push(format!("relative error {relative_error}"));
+    }
+    targets.join(" or ")
+}
+
+fn printer_thread(target: &StopTarget, start_iterations: u32, rx: &Receiver<Progress>) {
     // ANSI escape codes
     const CSI_ERASE_IN_LINE: &str = "\x1B[1K";
     const CSI_CURSOR_HORIZONTAL_ABSOLUTE: &str = "\x1B[1G";
@@ -93,24 +253,37 @@ fn printer_thread(threads: u32, iterations: u32, rx: &Receiver<Duration>) {
     let mut total_squared = 0.0;
     let mut completed = 0;
     loop {
-        if let Ok(duration) = rx.recv() {
-            let seconds = duration.as_seconds_f64();
+        if let Ok(progress) = rx.recv() {
+            let seconds = progress.duration.as_seconds_f64();
             total += seconds;
             total_squared += seconds * seconds;
             completed += 1;
 
             let mean = total / f64::from(completed);
             let sdev = ((total_squared / f64::from(completed)) - mean * mean).sqrt();
-            let eta = (f64::from(iterations - completed) * mean) / f64::from(threads);
+            let fraction = f64::from(target.fraction(start_iterations, &progress));
+            let eta = if fraction > 0.0 {
+                progress.elapsed * (1.0 / fraction - 1.0)
+            } else {
+                Duration::ZERO
+            };
+            let iterations = match target.iterations {
+                Some(total) => format!("{}/{}", progress.iterations, total),
+                None => progress.iterations.to_string(),
+            };
+            let error = progress
+                .relative_error
+                .map(|e| format!(", error: {e:.4}"))
+                .unwrap_or_default();
             print!(
-                "{}{}[{}/{}] mean: {:.2}, sdev: {:.2}, eta: {:.2}",
+                "{}{}[{}] mean: {:.2}, sdev: {:.2}{}, eta: {:.2}",
                 CSI_ERASE_IN_LINE,
                 CSI_CURSOR_HORIZONTAL_ABSOLUTE,
-                completed,
                 iterations,
                 time::Duration::seconds_f64(mean),
                 time::Duration::seconds_f64(sdev),
-                time::Duration::seconds_f64(eta)
+                error,
+                eta
             );
             std::io::stdout().flush().unwrap();
         } else {
@@ -120,9 +293,33 @@ fn printer_thread(threads: u32, iterations: u32, rx: &Receiver<Duration>) {
     }
 }
 
-fn setup_scene(args: &Args) -> (Pinhole, Pathtracer<TriangleCollection>) {
-    let (obj, mtl, mtl_path) = read_obj_and_mtl_with_print_logging(&args.input).unwrap();
-    let (triangles, properties) = from_wavefront(&obj, &mtl);
+fn setup_scene(args: &Args) -> (Pinhole, Pathtracer<TriangleCollection>, u64) {
+    let mode = if args.strict {
+        ParseMode::Strict
+    } else {
+        ParseMode::Lenient
+    };
+    let (obj, mtl, mtl_path) = read_obj_and_mtl_with_print_logging(&args.input, mode)
+        .unwrap_or_else(|error| {
+            eprintln!("Error: {error}");
+            std::process::exit(1);
+        });
+    let (triangles, properties) = from_wavefront(&obj, &mtl, args.crease_angle);
+    let scene_hash = scene_hash([
+        std::fs::read(&args.input).unwrap().as_slice(),
+        std::fs::read(&mtl_path).unwrap().as_slice(),
+        &args.size.x.to_le_bytes(),
+        &args.size.y.to_le_bytes(),
+        &args.max_bounces.to_le_bytes(),
+        &args.russian_roulette_bounces.to_le_bytes(),
+        &args.crease_angle.to_le_bytes(),
+        format!("{:?}", args.pixel_filter()).as_bytes(),
+        format!(
+            "{:?} {} {}",
+            args.environment, args.environment_rotation, args.environment_intensity
+        )
+        .as_bytes(),
+    ]);
 
     println!("Building kdtree...");
     let kdtree = build_kdtree(
@@ -136,12 +333,17 @@ fn setup_scene(args: &Args) -> (Pinhole, Pathtracer<TriangleCollection>) {
 
     let camera = Pinhole::new(mtl.cameras[0].clone().into(), args.size.as_uvec2());
     let image_directory = mtl_path.parent().unwrap();
-    let materials = mtl
+    let materials: Vec<Material> = mtl
         .materials
         .iter()
         .map(|m| Material::load_from_mtl(image_directory, m))
         .collect();
-    let lights = mtl.lights.iter().map(Light::from).collect();
+    let mut lights: Vec<Light> = mtl.lights.iter().map(Light::from).collect();
+    lights.extend(
+        TriangleLight::from_mesh(&triangles, &properties, &materials)
+            .into_iter()
+            .map(Light::from),
+    );
     let geometry_collection = TriangleCollection {
         triangles,
         properties,
@@ -150,41 +352,189 @@ fn setup_scene(args: &Args) -> (Pinhole, Pathtracer<TriangleCollection>) {
     };
     let pathtracer = Pathtracer {
         max_bounces: args.max_bounces,
+        russian_roulette: RussianRoulette {
+            min_bounces: args.russian_roulette_bounces,
+        },
+        filter: args.pixel_filter(),
+        sampler: args.sampler_kind(),
+        seed: args.seed,
         geometry_collection,
-        lights,
-        environment: Vec3::new(0.8, 0.8, 0.8),
+        lights: LightSampler::new(lights, args.light_selection()),
+        environment: args.environment(mtl.sky.as_ref()),
     };
 
-    (camera, pathtracer)
+    (camera, pathtracer, scene_hash)
+}
+
+impl Args {
+    /// The environment map argument takes precedence over the sky in the scene.
+    fn environment(&self, sky: Option<&mtl::Sky>) -> Environment {
+        if let Some(path) = &self.environment {
+            println!("Loading {}...", path.display());
+            return EnvironmentMap::load(
+                path,
+                self.environment_rotation,
+                self.environment_intensity,
+            )
+            .unwrap()
+            .into();
+        }
+        sky.map_or(Vec3::new(0.8, 0.8, 0.8).into(), |sky| Sky::from(sky).into())
+    }
+
+    const fn light_selection(&self) -> LightSelection {
+        match self.light_selection {
+            LightSelectionArg::Uniform => LightSelection::Uniform,
+            LightSelectionArg::Power => LightSelection::Power,
+            LightSelectionArg::Tree => LightSelection::Tree,
+        }
+    }
+
+    const fn sampler_kind(&self) -> SamplerKind {
+        match self.sampler {
+            SamplerArg::Independent => SamplerKind::Independent,
+            SamplerArg::Stratified => SamplerKind::Stratified {
+                strata: self.strata,
+            },
+            SamplerArg::Halton => SamplerKind::Halton,
+            SamplerArg::Sobol => SamplerKind::Sobol,
+        }
+    }
+
+    fn pixel_filter(&self) -> PixelFilter {
+        let filter = match self.filter {
+            FilterArg::Box => PixelFilter::default(),
+            FilterArg::Tent => PixelFilter::tent(),
+            FilterArg::Gaussian => PixelFilter::gaussian(),
+            FilterArg::Mitchell => PixelFilter::mitchell(),
+            FilterArg::BlackmanHarris => PixelFilter::blackman_harris(),
+        };
+        self.filter_radius
+            .map_or(filter, |radius| filter.with_radius(radius))
+    }
+
+    fn adaptive_sampling(&self) -> Option<AdaptiveSampling> {
+        self.adaptive.then_some(AdaptiveSampling {
+            tile_size: self.adaptive_tile_size,
+            uniform_iterations: self.adaptive_uniform_iterations,
+            fraction: self.adaptive_fraction,
+        })
+    }
+
+    fn stop_target(&self) -> StopTarget {
+        let iterations_per_thread = match self.iterations_per_thread {
+            None if self.time.is_none() && self.max_relative_error.is_none() => Some(4),
+            n => n,
+        };
+        StopTarget {
+            iterations: iterations_per_thread.map(|n| self.threads * n),
+            time: self.time,
+            relative_error: self.max_relative_error,
+        }
+    }
+
+    const fn display_transform(&self) -> DisplayTransform {
+        let tone_mapping = match self.tone_mapping {
+            ToneMappingArg::Clamp => ToneMapping::Clamp,
+            ToneMappingArg::Reinhard => ToneMapping::Reinhard,
+            ToneMappingArg::ExtendedReinhard => ToneMapping::ExtendedReinhard {
+                white: self.white_point,
+            },
+            ToneMappingArg::Aces => ToneMapping::Aces,
+            ToneMappingArg::Agx => ToneMapping::AgX,
+        };
+        DisplayTransform {
+            exposure: self.exposure,
+            tone_mapping,
+        }
+    }
+}
+
+fn write_image(path: &Path, buffer: &ImageBuffer, display: &DisplayTransform) {
+    let extension = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("exr") => buffer.write_exr(path).unwrap(),
+        Some("hdr") => buffer.write_hdr(path).unwrap(),
+        _ => buffer
+            .to_rgb_image(display)
+            .save_with_format(path, ImageFormat::Png)
+            .unwrap(),
+    }
+}
+
+fn load_start(args: &Args, scene_hash: u64) -> Accumulation {
+    let Some(path) = &args.resume else {
+        return Accumulation::new(args.size.as_uvec2());
+    };
+    println!("Resuming {}...", path.display());
+    let checkpoint = Checkpoint::read(path).unwrap();
+    if checkpoint.scene_hash != scene_hash {
+        eprintln!(
+            "Checkpoint {} was rendered with a different scene or settings.",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+    checkpoint.accumulation
 }
 
 fn main() {
     let args = Args::parse();
-    let (camera, pathtracer) = setup_scene(&args);
+    let (camera, pathtracer, scene_hash) = setup_scene(&args);
+    let start = load_start(&args, scene_hash);
+    let checkpoint_path = args.checkpoint.as_ref().or(args.resume.as_ref());
+    let write_checkpoint = |accumulation: &Accumulation| {
+        if let Some(path) = checkpoint_path {
+            let checkpoint = Checkpoint {
+                scene_hash,
+                accumulation: accumulation.clone(),
+            };
+            checkpoint.write(path).unwrap();
+        }
+    };
 
-    let total_iterations = args.threads * args.iterations_per_thread;
+    let target = args.stop_target();
+    let start_iterations = start.iterations;
     println!(
-        "Rendering {} px image with {} thread(s) and {} total iteration(s)...",
-        args.size, args.threads, total_iterations,
+        "Rendering {} px image with {} thread(s) from iteration {} until {}...",
+        args.size,
+        args.threads,
+        start_iterations,
+        describe_target(&target),
     );
 
     thread::scope(|s| {
         let (tx, rx) = mpsc::channel();
-        let printer = s.spawn(move || printer_thread(args.threads, total_iterations, &rx));
-        let (duration, image) = render_parallel_iterations(
+        let printer = s.spawn(move || printer_thread(&target, start_iterations, &rx));
+        let mut periodic_checkpoint = write_checkpoint;
+        let (duration, accumulation) = render_parallel_iterations(
             &pathtracer,
             &camera,
-            args.size.as_uvec2(),
             args.threads,
-            args.iterations_per_thread,
+            &target,
+            args.adaptive_sampling().as_ref(),
+            start,
+            checkpoint_path.map(|_| CheckpointSchedule {
+                interval: std::time::Duration::from_secs(args.checkpoint_interval),
+                write: &mut periodic_checkpoint,
+            }),
             tx,
         );
         printer.join().unwrap();
-        println!("Total time: {duration:.2}");
+        println!(
+            "Total time: {duration:.2}, {} iteration(s)",
+            accumulation.iterations
+        );
+        write_checkpoint(&accumulation);
 
         println!("Writing {}...", args.output.display());
-        image
-            .save_with_format(&args.output, ImageFormat::Png)
-            .unwrap();
+        write_image(
+            &args.output,
+            &accumulation.buffer,
+            &args.display_transform(),
+        );
     });
 }
@@ -4,10 +4,19 @@ use kdtree::{build::build_kdtree, sah::SahCost};
 use miniquad::conf::Conf;
 use stage::Stage;
 use tracing::{
-    camera::Camera, collections::TriangleCollection, light::Light, material::Material,
-    pathtracer::Pathtracer, properties::from_wavefront,
+    camera::Camera,
+    collections::TriangleCollection,
+    filter::PixelFilter,
+    light::{Light, TriangleLight},
+    light_sampler::{LightSampler, LightSelection},
+    material::Material,
+    pathtracer::Pathtracer,
+    properties::{DEFAULT_CREASE_ANGLE, from_wavefront},
+    sampler::SamplerKind,
+    sampling::RussianRoulette,
+    sky::Sky,
 };
-use wavefront::read_obj_and_mtl_with_print_logging;
+use wavefront::{ParseMode, read_obj_and_mtl_with_print_logging};
 
 mod stage;
 mod worker;
@@ -29,8 +38,12 @@ struct Args {
 }
 
 fn setup_scene(args: &Args) -> (Camera, Pathtracer<TriangleCollection>) {
-    let (obj, mtl, mtl_path) = read_obj_and_mtl_with_print_logging(&args.input).unwrap();
-    let (triangles, properties) = from_wavefront(&obj, &mtl);
+    let (obj, mtl, mtl_path) = read_obj_and_mtl_with_print_logging(&args.input, ParseMode::Lenient)
+        .unwrap_or_else(|error| {
+            eprintln!("Error: {error}");
+            std::process::exit(1);
+        });
+    let (triangles, properties) = from_wavefront(&obj, &mtl, DEFAULT_CREASE_ANGLE);
 
     println!("Building kdtree...");
     let kdtree = build_kdtree(
@@ -43,12 +56,17 @@ fn setup_scene(args: &Args) -> (Camera, Pathtracer<TriangleCollection>) {
     );
 
     let image_directory = mtl_path.parent().unwrap();
-    let materials = mtl
+    let materials: Vec<Material> = mtl
         .materials
         .iter()
         .map(|m| Material::load_from_mtl(image_directory, m))
         .collect();
-    let lights = mtl.lights.iter().map(Light::from).collect();
+    let mut lights: Vec<Light> = mtl.lights.iter().map(Light::from).collect();
+    lights.extend(
+        TriangleLight::from_mesh(&triangles, &properties, &materials)
+            .into_iter()
+            .map(Light::from),
+    );
     let geometry_collection = TriangleCollection {
         triangles,
         properties,
@@ -57,9 +75,16 @@ fn setup_scene(args: &Args) -> (Camera, Pathtracer<TriangleCollection>) {
     };
     let pathtracer = Pathtracer {
         max_bounces: 16,
+        russian_roulette: RussianRoulette::default(),
+        filter: PixelFilter::default(),
+        sampler: SamplerKind::default(),
+        seed: 0,
         geometry_collection,
-        lights,
-        environment: Vec3::new(0.8, 0.8, 0.8),
+        lights: LightSampler::new(lights, LightSelection::default()),
+        environment: mtl
+            .sky
+            .as_ref()
+            .map_or(Vec3::new(0.8, 0.8, 0.8).into(), |sky| Sky::from(sky).into()),
     };
 
     (mtl.cameras[0].clone().into(), pathtracer)
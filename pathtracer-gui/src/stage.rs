@@ -10,9 +10,10 @@ use tracing::{
     camera::{Camera, Pinhole},
     collections::GeometryCollection,
     pathtracer::Pathtracer,
+    tonemap::{DisplayTransform, ToneMapping},
 };
 
-use crate::worker::Worker;
+use crate::worker::{RenderResult, Worker};
 
 #[repr(C)]
 struct Vec2 {
@@ -33,6 +34,8 @@ struct InputState {
     rotate_x: (bool, bool),
     rotate_y: (bool, bool),
     rotate_z: (bool, bool),
+    aperture: (bool, bool),
+    focus: (bool, bool),
 }
 
 impl InputState {
@@ -55,6 +58,16 @@ impl InputState {
         let rotation = Vec3::new(f(self.rotate_x), f(self.rotate_y), f(self.rotate_z));
         duration.as_secs_f32() * speed * rotation
     }
+
+    fn lens(&self, speed: &glam::Vec2, duration: Duration) -> glam::Vec2 {
+        let f = |(a, b)| match (a, b) {
+            (true, false) => -1.0,
+            (false, true) => 1.0,
+            _ => 0.0,
+        };
+        let lens = glam::Vec2::new(f(self.aperture), f(self.focus));
+        duration.as_secs_f32() * speed * lens
+    }
 }
 
 pub struct Stage {
@@ -71,6 +84,9 @@ pub struct Stage {
 
     last_update: Instant,
     input: InputState,
+
+    display: DisplayTransform,
+    last_result: Option<RenderResult>,
 }
 
 impl Stage {
@@ -151,6 +167,8 @@ impl Stage {
             camera,
             last_update: Instant::now(),
             input: InputState::default(),
+            display: DisplayTransform::default(),
+            last_result: None,
         }
     }
 
@@ -169,15 +187,21 @@ impl Stage {
             std::f32::consts::FRAC_PI_4,
             std::f32::consts::FRAC_PI_4,
         );
+        const LENS_SPEED: glam::Vec2 = glam::Vec2::new(0.05, 1.0);
         let now = Instant::now();
         let duration = now - self.last_update;
         let translation = self.input.translation(&TRANSLATION_SPEED, duration);
         let rotation = self.input.rotation(&ROTATION_SPEED, duration);
-        if translation != Vec3::ZERO || rotation != Vec3::ZERO {
+        let lens = self.input.lens(&LENS_SPEED, duration);
+        if translation != Vec3::ZERO || rotation != Vec3::ZERO || lens != glam::Vec2::ZERO {
             self.camera = self
                 .camera
                 .add_translation(translation.x, translation.y, translation.z)
-                .add_yaw_pitch_roll(rotation.x, rotation.y, rotation.z);
+                .add_yaw_pitch_roll(rotation.x, rotation.y, rotation.z)
+                .with_thin_lens(
+                    (self.camera.aperture_radius + lens.x).max(0.0),
+                    (self.camera.focus_distance + lens.y).max(0.01),
+                );
             self.send_pinhole();
         }
         self.last_update = now;
@@ -189,27 +213,44 @@ impl Stage {
                 "Received {:?} @ {} rendered in {:.2}.",
                 result.buffer.size, result.iterations, result.duration,
             );
-            let texture_size = self.ctx.texture_size(self.texture).into();
-            if result.buffer.size == texture_size {
-                self.ctx
-                    .texture_update(self.texture, &result.buffer.to_rgb8(result.iterations));
-            } else {
-                self.ctx.delete_texture(self.texture);
-                let width = result.buffer.size.x;
-                let height = result.buffer.size.y;
-                self.texture = self.ctx.new_texture_from_data_and_format(
-                    &result.buffer.to_rgb8(result.iterations),
-                    miniquad::TextureParams {
-                        format: miniquad::TextureFormat::RGB8,
-                        width,
-                        height,
-                        ..Default::default()
-                    },
-                );
-                self.bindings.images = vec![self.texture];
-            }
+            self.last_result = Some(result);
+            self.upload_texture();
+        }
+    }
+
+    fn upload_texture(&mut self) {
+        let Some(result) = &self.last_result else {
+            return;
+        };
+        let pixels = result.buffer.to_rgb8(&self.display);
+        let texture_size = self.ctx.texture_size(self.texture).into();
+        if result.buffer.size == texture_size {
+            self.ctx.texture_update(self.texture, &pixels);
+        } else {
+            self.ctx.delete_texture(self.texture);
+            let width = result.buffer.size.x;
+            let height = result.buffer.size.y;
+            self.texture = self.ctx.new_texture_from_data_and_format(
+                &pixels,
+                miniquad::TextureParams {
+                    format: miniquad::TextureFormat::RGB8,
+                    width,
+                    height,
+                    ..Default::default()
+                },
+            );
+            self.bindings.images = vec![self.texture];
         }
     }
+
+    fn set_display(&mut self, display: DisplayTransform) {
+        self.display = display;
+        eprintln!(
+            "Tone mapping {:?} at {:+.1} EV.",
+            display.tone_mapping, display.exposure
+        );
+        self.upload_texture();
+    }
 }
 
 impl EventHandler for Stage {
@@ -233,6 +274,32 @@ impl EventHandler for Stage {
             KeyCode::C => self.input.rotate_y.1 = true,
             KeyCode::G => self.input.rotate_z.0 = true,
             KeyCode::R => self.input.rotate_z.1 = true,
+
+            KeyCode::LeftBracket => self.input.aperture.0 = true,
+            KeyCode::RightBracket => self.input.aperture.1 = true,
+            KeyCode::Minus => self.input.focus.0 = true,
+            KeyCode::Equal => self.input.focus.1 = true,
+
+            KeyCode::Key1 | KeyCode::Key2 | KeyCode::Key3 | KeyCode::Key4 | KeyCode::Key5 => {
+                let tone_mapping = match keycode {
+                    KeyCode::Key1 => ToneMapping::Clamp,
+                    KeyCode::Key2 => ToneMapping::Reinhard,
+                    KeyCode::Key3 => ToneMapping::ExtendedReinhard { white: 4.0 },
+                    KeyCode::Key4 => ToneMapping::Aces,
+                    _ => ToneMapping::AgX,
+                };
+                self.set_display(DisplayTransform {
+                    tone_mapping,
+                    ..self.display
+                });
+            }
+            KeyCode::Key9 | KeyCode::Key0 => {
+                let step = if keycode == KeyCode::Key9 { -0.5 } else { 0.5 };
+                self.set_display(DisplayTransform {
+                    exposure: self.display.exposure + step,
+                    ..self.display
+                });
+            }
             _ => (),
         }
     }
@@ -252,6 +319,11 @@ impl EventHandler for Stage {
             KeyCode::C => self.input.rotate_y.1 = false,
             KeyCode::G => self.input.rotate_z.0 = false,
             KeyCode::R => self.input.rotate_z.1 = false,
+
+            KeyCode::LeftBracket => self.input.aperture.0 = false,
+            KeyCode::RightBracket => self.input.aperture.1 = false,
+            KeyCode::Minus => self.input.focus.0 = false,
+            KeyCode::Equal => self.input.focus.1 = false,
             _ => (),
         }
     }
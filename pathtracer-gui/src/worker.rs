@@ -53,13 +53,19 @@ fn worker_loop(
                 UVec2::new(64, 64 * pinhole.size.y / pinhole.size.x),
             );
             let (duration, buffer) = measure(|| {
-                render_parallel_subdivided(&pathtracer, &pinhole_small, pinhole_small.size / 3)
+                render_parallel_subdivided(&pathtracer, &pinhole_small, pinhole_small.size / 3, 0)
             });
             let _ = tx.send(RenderResult::new(1, duration, buffer));
             iteration = 1;
         } else {
-            let (duration, buffer) =
-                measure(|| render_parallel_subdivided(&pathtracer, &pinhole, pinhole.size / 4));
+            let (duration, buffer) = measure(|| {
+                render_parallel_subdivided(
+                    &pathtracer,
+                    &pinhole,
+                    pinhole.size / 4,
+                    iteration.into(),
+                )
+            });
             combined_buffer += buffer;
             let _ = tx.send(RenderResult::new(
                 iteration,
@@ -0,0 +1,121 @@
+use glam::UVec2;
+
+use crate::image_buffer::ImageBuffer;
+
+/// Rectangle of pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub start: UVec2,
+    pub size: UVec2,
+}
+
+/// Split an image into tiles of at most `tile_size` pixels, clipped at the right and bottom edges.
+pub fn tiles(size: UVec2, tile_size: u32) -> impl Iterator<Item = Tile> {
+    let tile_size = tile_size.max(1);
+    (0..size.y).step_by(tile_size as usize).flat_map(move |y| {
+        (0..size.x).step_by(tile_size as usize).map(move |x| {
+            let start = UVec2::new(x, y);
+            Tile {
+                start,
+                size: (start + tile_size).min(size) - start,
+            }
+        })
+    })
+}
+
+/// Spend the iterations after an initial uniform phase on the tiles with the highest estimated
+/// relative error.
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSampling {
+    /// Width and height of the tiles in pixels.
+    pub tile_size: u32,
+    /// Number of iterations that sample every pixel before the error estimate is used.
+    pub uniform_iterations: u32,
+    /// Fraction of the tiles rendered by each adaptive iteration.
+    pub fraction: f32,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            tile_size: 16,
+            uniform_iterations: 8,
+            fraction: 0.25,
+        }
+    }
+}
+
+impl AdaptiveSampling {
+    /// Tiles to render in the next iteration given the samples accumulated so far.
+    pub fn select_tiles(&self, buffer: &ImageBuffer, iterations: u32) -> Vec<Tile> {
+        let tiles = tiles(buffer.size, self.tile_size);
+        if iterations < self.uniform_iterations {
+            return tiles.collect();
+        }
+        let mut errors: Vec<(f32, Tile)> = tiles
+            .map(|tile| (tile_relative_error(buffer, tile), tile))
+            .collect();
+        errors.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let count = (errors.len() as f32 * self.fraction).ceil() as usize;
+        errors
+            .into_iter()
+            .take(count.max(1))
+            .map(|(_, tile)| tile)
+            .collect()
+    }
+}
+
+fn tile_relative_error(buffer: &ImageBuffer, tile: Tile) -> f32 {
+    let sum: f32 = (0..tile.size.y)
+        .flat_map(|y| (0..tile.size.x).map(move |x| tile.start + UVec2::new(x, y)))
+        .map(|pixel| buffer.pixel_relative_error(pixel))
+        .sum();
+    sum / (tile.size.x * tile.size.y) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    #[test]
+    fn tiles_cover_image_once() {
+        let size = UVec2::new(37, 20);
+        let mut covered = vec![0; (size.x * size.y) as usize];
+        for tile in tiles(size, 16) {
+            assert!(tile.size.cmpgt(UVec2::ZERO).all());
+            for y in tile.start.y..tile.start.y + tile.size.y {
+                for x in tile.start.x..tile.start.x + tile.size.x {
+                    covered[(y * size.x + x) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|c| *c == 1));
+    }
+
+    #[test]
+    fn selects_noisiest_tiles_after_uniform_iterations() {
+        let adaptive = AdaptiveSampling {
+            tile_size: 2,
+            uniform_iterations: 2,
+            fraction: 0.25,
+        };
+        let mut buffer = ImageBuffer::new(UVec2::new(4, 4));
+        for i in 0..4 {
+            for pixel in buffer.coordinates() {
+                let noisy = pixel.x >= 2 && pixel.y < 2 && i % 2 == 0;
+                let value = if noisy { Vec3::ONE } else { Vec3::splat(0.5) };
+                buffer.add_sample(pixel, value);
+            }
+        }
+
+        assert_eq!(adaptive.select_tiles(&buffer, 1).len(), 4);
+        assert_eq!(
+            adaptive.select_tiles(&buffer, 4),
+            [Tile {
+                start: UVec2::new(2, 0),
+                size: UVec2::new(2, 2),
+            }]
+        );
+    }
+}
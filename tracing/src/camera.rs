@@ -9,6 +9,10 @@ pub struct Camera {
     pub up: Vec3,
     pub right: Vec3,
     pub fov_degrees: f32,
+    /// Thin lens radius, zero gives a pinhole camera with everything in focus.
+    pub aperture_radius: f32,
+    /// Distance along the view direction to the plane in focus.
+    pub focus_distance: f32,
 }
 
 impl Camera {
@@ -20,16 +24,23 @@ impl Camera {
             up: up.normalize(),
             right: direction.cross(up).normalize(),
             fov_degrees,
+            aperture_radius: 0.0,
+            focus_distance: (target - position).length(),
+        }
+    }
+
+    pub fn with_thin_lens(&self, aperture_radius: f32, focus_distance: f32) -> Self {
+        Self {
+            aperture_radius,
+            focus_distance,
+            ..self.clone()
         }
     }
 
     pub fn add_translation(&self, right: f32, up: f32, forward: f32) -> Self {
         Self {
             position: self.position + right * self.right + up * self.up + forward * self.direction,
-            direction: self.direction,
-            up: self.up,
-            right: self.right,
-            fov_degrees: self.fov_degrees,
+            ..self.clone()
         }
     }
 
@@ -38,23 +49,28 @@ impl Camera {
         let quat_pitch = Quat::from_axis_angle(self.right, pitch);
         let quat_roll = Quat::from_axis_angle(self.direction, roll);
         Self {
-            position: self.position,
             direction: (quat_yaw * quat_pitch) * self.direction,
             up: (quat_pitch * quat_roll) * self.up,
             right: (quat_yaw * quat_roll) * self.right,
-            fov_degrees: self.fov_degrees,
+            ..self.clone()
         }
     }
 }
 
 impl From<mtl::Camera> for Camera {
     fn from(value: mtl::Camera) -> Self {
-        Self::new(
+        let camera = Self::new(
             value.position.into(),
             value.target.into(),
             value.up.into(),
             value.fov,
-        )
+        );
+        let focus_distance = if value.focus > 0.0 {
+            value.focus
+        } else {
+            camera.focus_distance
+        };
+        camera.with_thin_lens(value.aperture, focus_distance)
     }
 }
 
@@ -84,34 +100,48 @@ impl Pinhole {
         }
     }
 
+    /// Ray through the normalized image coordinate `v` and the unit disk lens sample `lens`.
     #[inline]
-    pub fn ray(&self, v: Vec2) -> Ray {
-        Ray::new(
-            self.camera.position,
-            self.plane + v.x * self.dx + v.y * self.dy,
-        )
+    pub fn ray(&self, v: Vec2, lens: Vec2) -> Ray {
+        let direction = self.plane + v.x * self.dx + v.y * self.dy;
+        if self.camera.aperture_radius <= 0.0 {
+            return Ray::new(self.camera.position, direction);
+        }
+        let focus_scale = self.camera.focus_distance / direction.dot(self.camera.direction);
+        let focus_point = self.camera.position + focus_scale * direction;
+        let lens_offset =
+            self.camera.aperture_radius * (lens.x * self.camera.right + lens.y * self.camera.up);
+        Ray::between(self.camera.position + lens_offset, focus_point)
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use nalgebra::vector;
-
-//     #[test]
-//     fn ray_in_image_plane_center() {
-//         let camera = Camera::new(
-//             &vector![1.0, 0.0, 0.0],
-//             &vector![-1.0, 0.0, 0.0],
-//             &vector![0.0, 1.0, 0.0],
-//             1.0,
-//         );
-//         let pinhole = Pinhole::new(&camera, 1.0);
-
-//         let actual = pinhole.ray(0.5, 0.5);
-
-//         let direction_error = (actual.direction - vector![-1.0, 0.0, 0.0]).norm();
-//         assert_eq!(actual.origin, vector![1.0, 0.0, 0.0]);
-//         assert!(direction_error <= 0.1, "direction_error = {}", direction_error);
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn ray_in_image_plane_center() {
+        let camera = Camera::new(Vec3::X, -Vec3::X, Vec3::Y, 45.0);
+        let pinhole = Pinhole::new(camera, UVec2::new(1, 1));
+
+        let actual = pinhole.ray(Vec2::new(0.5, 0.5), Vec2::ZERO);
+
+        assert_eq!(actual.origin, Vec3::X);
+        assert_relative_eq!(actual.direction.normalize(), -Vec3::X, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn thin_lens_rays_converge_at_focus_distance() {
+        let camera = Camera::new(Vec3::X, -Vec3::X, Vec3::Y, 45.0).with_thin_lens(0.5, 3.0);
+        let pinhole = Pinhole::new(camera, UVec2::new(1, 1));
+        let v = Vec2::new(0.2, 0.7);
+
+        let a = pinhole.ray(v, Vec2::new(1.0, 0.0));
+        let b = pinhole.ray(v, Vec2::new(0.0, -1.0));
+
+        assert_ne!(a.origin, b.origin);
+        assert_relative_eq!(a.param(1.0), b.param(1.0), epsilon = 1e-6);
+        assert_relative_eq!(a.param(1.0).x, -2.0, epsilon = 1e-6);
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use glam::{UVec2, Vec3};
+
+use crate::{image_buffer::ImageBuffer, worker::Accumulation};
+
+const MAGIC: [u8; 4] = *b"PTCP";
+const VERSION: u32 = 5;
+
+/// FNV-1a hash of the inputs that determine the rendered image, used to refuse resuming a
+/// checkpoint of a different scene.
+pub fn scene_hash<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    parts.into_iter().fold(OFFSET_BASIS, |hash, part| {
+        // Include the length so that moving bytes between parts changes the hash.
+        (part.len() as u64)
+            .to_le_bytes()
+            .iter()
+            .chain(part)
+            .fold(hash, |hash, byte| {
+                (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+            })
+    })
+}
+
+/// Accumulated linear radiance of a progressive render, written to disk so it can be resumed.
+pub struct Checkpoint {
+    pub scene_hash: u64,
+    pub accumulation: Accumulation,
+}
+
+impl Checkpoint {
+    /// Write the checkpoint to a temporary file next to `path` and then move it in place, so
+    /// that a crash while writing does not destroy the previous checkpoint.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&temporary)?);
+            let buffer = &self.accumulation.buffer;
+            writer.write_all(&MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            writer.write_all(&self.scene_hash.to_le_bytes())?;
+            writer.write_all(&self.accumulation.iterations.to_le_bytes())?;
+            writer.write_all(&buffer.size.x.to_le_bytes())?;
+            writer.write_all(&buffer.size.y.to_le_bytes())?;
+            for pixel in buffer.pixels() {
+                for component in pixel.to_array() {
+                    writer.write_all(&component.to_le_bytes())?;
+                }
+            }
+            for weight in buffer.weights() {
+                writer.write_all(&weight.to_le_bytes())?;
+            }
+            for first_moment in buffer.first_moments() {
+                writer.write_all(&first_moment.to_le_bytes())?;
+            }
+            for second_moment in buffer.second_moments() {
+                writer.write_all(&second_moment.to_le_bytes())?;
+            }
+            for samples in buffer.samples() {
+                writer.write_all(&samples.to_le_bytes())?;
+            }
+            writer.flush()?;
+        }
+        std::fs::rename(&temporary, path)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut u32_bytes = [0u8; 4];
+        let mut read_u32 = |reader: &mut BufReader<File>| -> Result<u32, Error> {
+            reader.read_exact(&mut u32_bytes)?;
+            Ok(u32::from_le_bytes(u32_bytes))
+        };
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a checkpoint file"));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported checkpoint version {version}"),
+            ));
+        }
+        let mut u64_bytes = [0u8; 8];
+        reader.read_exact(&mut u64_bytes)?;
+        let scene_hash = u64::from_le_bytes(u64_bytes);
+        let iterations = read_u32(&mut reader)?;
+        let size = UVec2::new(read_u32(&mut reader)?, read_u32(&mut reader)?);
+
+        let count = (size.x * size.y) as usize;
+        let mut bytes = vec![0u8; count * 12];
+        reader.read_exact(&mut bytes)?;
+        let pixels = bytes
+            .chunks_exact(12)
+            .map(|chunk| {
+                let component =
+                    |i: usize| f32::from_le_bytes(chunk[i * 4..i * 4 + 4].try_into().unwrap());
+                Vec3::new(component(0), component(1), component(2))
+            })
+            .collect();
+        let read_f32s = |reader: &mut BufReader<File>| -> Result<Vec<f32>, Error> {
+            let mut bytes = vec![0u8; count * 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect())
+        };
+        let weights = read_f32s(&mut reader)?;
+        let first_moments = read_f32s(&mut reader)?;
+        let second_moments = read_f32s(&mut reader)?;
+        let mut bytes = vec![0u8; count * 4];
+        reader.read_exact(&mut bytes)?;
+        let samples = bytes
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            scene_hash,
+            accumulation: Accumulation {
+                buffer: ImageBuffer::from_raw(
+                    size,
+                    pixels,
+                    weights,
+                    first_moments,
+                    second_moments,
+                    samples,
+                ),
+                iterations,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::PixelFilter;
+    use glam::Vec2;
+
+    #[test]
+    fn scene_hash_depends_on_part_boundaries() {
+        assert_ne!(
+            scene_hash([b"ab".as_slice(), b"c".as_slice()]),
+            scene_hash([b"a".as_slice(), b"bc".as_slice()])
+        );
+        assert_eq!(
+            scene_hash([b"ab".as_slice(), b"c".as_slice()]),
+            scene_hash([b"ab".as_slice(), b"c".as_slice()])
+        );
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let mut buffer = ImageBuffer::new(UVec2::new(3, 2));
+        buffer.add_sample(UVec2::new(2, 1), Vec3::new(1.0, 2.5, -0.0));
+        buffer.add_sample(UVec2::new(0, 1), Vec3::new(1e10, 0.1, 3.0));
+        buffer.add_sample(UVec2::new(1, 0), Vec3::ONE);
+        buffer.splat(&PixelFilter::tent(), Vec2::new(1.3, 0.8), Vec3::splat(0.5));
+        let expected = Checkpoint {
+            scene_hash: 0x0123_4567_89ab_cdef,
+            accumulation: Accumulation {
+                buffer,
+                iterations: 17,
+            },
+        };
+        let path = std::env::temp_dir().join(format!(
+            "tracing_checkpoint_round_trip_{}.bin",
+            std::process::id()
+        ));
+
+        expected.write(&path).unwrap();
+        let actual = Checkpoint::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(actual.scene_hash, expected.scene_hash);
+        assert_eq!(actual.accumulation.iterations, 17);
+        assert_eq!(actual.accumulation.buffer.size, UVec2::new(3, 2));
+        assert_eq!(
+            actual.accumulation.buffer.pixels(),
+            expected.accumulation.buffer.pixels()
+        );
+        assert_eq!(
+            actual.accumulation.buffer.weights(),
+            expected.accumulation.buffer.weights()
+        );
+        assert_eq!(
+            actual.accumulation.buffer.first_moments(),
+            expected.accumulation.buffer.first_moments()
+        );
+        assert_eq!(
+            actual.accumulation.buffer.second_moments(),
+            expected.accumulation.buffer.second_moments()
+        );
+        assert_eq!(
+            actual.accumulation.buffer.samples(),
+            expected.accumulation.buffer.samples()
+        );
+    }
+}
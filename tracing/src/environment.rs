@@ -0,0 +1,252 @@
+use std::{
+    f32::consts::{PI, TAU},
+    path::Path,
+};
+
+use geometry::ray::Ray;
+use glam::{UVec2, Vec2, Vec3};
+use image::{ConvertColorOptions, ImageError, metadata::Cicp};
+
+use crate::{
+    light::LightSample, material::luminance, sampler::Sampler, sampling::Distribution1D, sky::Sky,
+};
+
+/// Radiance arriving from infinitely far away, seen by rays that escape the scene.
+#[derive(Clone, Debug)]
+pub enum Environment {
+    Constant(Vec3),
+    Map(EnvironmentMap),
+    Sky(Sky),
+}
+
+impl From<Vec3> for Environment {
+    fn from(value: Vec3) -> Self {
+        Self::Constant(value)
+    }
+}
+
+impl From<EnvironmentMap> for Environment {
+    fn from(value: EnvironmentMap) -> Self {
+        Self::Map(value)
+    }
+}
+
+impl From<Sky> for Environment {
+    fn from(value: Sky) -> Self {
+        Self::Sky(value)
+    }
+}
+
+impl Environment {
+    /// Radiance arriving from `direction`, which must be normalized.
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Self::Constant(radiance) => *radiance,
+            Self::Map(map) => map.radiance(direction),
+            Self::Sky(sky) => sky.radiance(direction),
+        }
+    }
+
+    /// Sample a direction towards the environment from `point`, `None` when the environment is
+    /// only reached by BSDF sampling.
+    pub fn sample(&self, point: Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        match self {
+            Self::Constant(_) => None,
+            Self::Map(map) => Some(map.sample(point, sampler)),
+            Self::Sky(sky) => Some(sky.sample(point, sampler)),
+        }
+    }
+
+    /// Solid angle PDF of [`Environment::sample`] producing the normalized `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Self::Constant(_) => 0.0,
+            Self::Map(map) => map.pdf(direction),
+            Self::Sky(sky) => sky.pdf(direction),
+        }
+    }
+}
+
+/// Equirectangular environment map with +y up, importance sampled by the luminance of its pixels.
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap {
+    size: UVec2,
+    pixels: Vec<Vec3>,
+    /// Rotation around the y-axis in radians.
+    rotation: f32,
+    intensity: f32,
+    /// Marginal distribution over the rows.
+    rows: Distribution1D,
+    /// Conditional distribution over the pixels of each row.
+    columns: Vec<Distribution1D>,
+}
+
+impl EnvironmentMap {
+    pub fn new(size: UVec2, pixels: Vec<Vec3>, rotation_degrees: f32, intensity: f32) -> Self {
+        assert_eq!(pixels.len(), (size.x * size.y) as usize);
+        let columns: Vec<Distribution1D> = pixels
+            .chunks(size.x as usize)
+            .enumerate()
+            .map(|(y, row)| {
+                // Rows near the poles cover a smaller solid angle.
+                let sin_theta = (PI * (y as f32 + 0.5) / size.y as f32).sin();
+                Distribution1D::new(row.iter().map(|p| luminance(*p) * sin_theta).collect())
+            })
+            .collect();
+        let rows = Distribution1D::new(columns.iter().map(Distribution1D::integral).collect());
+        Self {
+            size,
+            pixels,
+            rotation: rotation_degrees.to_radians(),
+            intensity,
+            rows,
+            columns,
+        }
+    }
+
+    /// Load a linear radiance map such as Radiance HDR, other images are converted from sRGB.
+    pub fn load(path: &Path, rotation_degrees: f32, intensity: f32) -> Result<Self, ImageError> {
+        let mut image = image::open(path)?;
+        image.convert_color_space(
+            Cicp::SRGB_LINEAR,
+            ConvertColorOptions::default(),
+            image::ColorType::Rgba32F,
+        )?;
+        let image = image.into_rgb32f();
+        let size = UVec2::new(image.width(), image.height());
+        let pixels = image.pixels().map(|p| Vec3::from(p.0)).collect();
+        Ok(Self::new(size, pixels, rotation_degrees, intensity))
+    }
+
+    fn to_uv(&self, direction: Vec3) -> Vec2 {
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+        let phi = (direction.z.atan2(direction.x) - self.rotation).rem_euclid(TAU);
+        Vec2::new(phi / TAU, theta / PI)
+    }
+
+    fn to_direction(&self, uv: Vec2) -> Vec3 {
+        let (sin_theta, cos_theta) = (PI * uv.y).sin_cos();
+        let (sin_phi, cos_phi) = (TAU * uv.x + self.rotation).sin_cos();
+        Vec3::new(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi)
+    }
+
+    fn pixel(&self, uv: Vec2) -> UVec2 {
+        (uv * self.size.as_vec2()).as_uvec2().min(self.size - 1)
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let pixel = self.pixel(self.to_uv(direction));
+        self.pixels[(pixel.y * self.size.x + pixel.x) as usize] * self.intensity
+    }
+
+    /// Converts the PDF over the image to solid angle, the equirectangular mapping stretches
+    /// `2 * pi * pi * sin(theta)` times.
+    fn solid_angle_pdf(uv_pdf: f32, uv: Vec2) -> f32 {
+        let sin_theta = (PI * uv.y).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        uv_pdf / (2.0 * PI * PI * sin_theta)
+    }
+
+    /// Sample a direction proportionally to the luminance of the map, see [`EnvironmentMap::pdf`].
+    pub(crate) fn sample_direction(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let u = sampler.get_2d();
+        let (v, _, row) = self.rows.sample_continuous(u.y);
+        let (u, _, _) = self.columns[row].sample_continuous(u.x);
+        self.to_direction(Vec2::new(u, v))
+    }
+
+    pub fn sample(&self, point: Vec3, sampler: &mut dyn Sampler) -> LightSample {
+        let direction = self.sample_direction(sampler);
+        LightSample {
+            is_delta: false,
+            // Computed from the direction to match [`EnvironmentMap::pdf`] exactly, as needed
+            // by multiple importance sampling.
+            pdf: self.pdf(direction),
+            radiance: self.radiance(direction),
+            shadow_ray: Ray::new(point, direction),
+            t_range: 0.0..=f32::MAX,
+        }
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let uv = self.to_uv(direction);
+        let row = self.pixel(uv).y as usize;
+        let uv_pdf = self.rows.pdf(uv.y) * self.columns[row].pdf(uv.x);
+        Self::solid_angle_pdf(uv_pdf, uv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use rand::{SeedableRng, rngs::SmallRng};
+
+    use super::*;
+
+    fn map_with_bright_pixel(rotation_degrees: f32) -> EnvironmentMap {
+        let size = UVec2::new(16, 8);
+        let mut pixels = vec![Vec3::splat(0.1); (size.x * size.y) as usize];
+        pixels[(2 * size.x + 5) as usize] = Vec3::splat(100.0);
+        EnvironmentMap::new(size, pixels, rotation_degrees, 2.0)
+    }
+
+    #[test]
+    fn directions_round_trip() {
+        let map = map_with_bright_pixel(30.0);
+        for uv in [
+            Vec2::new(0.1, 0.2),
+            Vec2::new(0.7, 0.5),
+            Vec2::new(0.99, 0.9),
+        ] {
+            assert_relative_eq!(map.to_uv(map.to_direction(uv)), uv, epsilon = 1e-5);
+        }
+        assert_relative_eq!(
+            map.to_direction(Vec2::new(0.0, 0.0)),
+            Vec3::Y,
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    fn rotation_turns_around_y() {
+        let map = map_with_bright_pixel(90.0);
+        let unrotated = map_with_bright_pixel(0.0);
+        let direction = Vec3::new(0.6, 0.8, 0.0);
+        let rotated = Vec3::new(0.0, 0.8, 0.6);
+        assert_eq!(map.radiance(rotated), unrotated.radiance(direction));
+    }
+
+    #[test]
+    fn sample_pdf_matches_pdf_and_radiance() {
+        let map = map_with_bright_pixel(45.0);
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let sample = map.sample(Vec3::ZERO, &mut rng);
+            let direction = sample.shadow_ray.direction;
+            assert_relative_eq!(direction.length(), 1.0, epsilon = 1e-5);
+            assert_eq!(sample.pdf, map.pdf(direction));
+            assert_eq!(sample.radiance, map.radiance(direction));
+            if sample.radiance == Vec3::splat(200.0) {
+                bright += 1;
+            }
+        }
+        assert!(bright > 800, "{bright}");
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let map = map_with_bright_pixel(10.0);
+        let steps = 512;
+        let integral: f32 = (0..steps * steps)
+            .map(|i| {
+                let uv = (Vec2::new((i % steps) as f32, (i / steps) as f32) + 0.5) / steps as f32;
+                let sin_theta = (PI * uv.y).sin();
+                map.pdf(map.to_direction(uv)) * 2.0 * PI * PI * sin_theta / (steps * steps) as f32
+            })
+            .sum();
+        assert_relative_eq!(integral, 1.0, epsilon = 1e-2);
+    }
+}
@@ -0,0 +1,178 @@
+use std::f32::consts::TAU;
+
+use glam::Vec2;
+
+/// Separable pixel reconstruction filter, samples are splatted to every pixel whose center is
+/// within the filter radius.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFilter {
+    Box {
+        radius: f32,
+    },
+    Tent {
+        radius: f32,
+    },
+    Gaussian {
+        radius: f32,
+        alpha: f32,
+    },
+    /// Mitchell-Netravali cubic with the parameters `b` and `c`.
+    Mitchell {
+        radius: f32,
+        b: f32,
+        c: f32,
+    },
+    /// Four term Blackman-Harris window.
+    BlackmanHarris {
+        radius: f32,
+    },
+}
+
+impl Default for PixelFilter {
+    fn default() -> Self {
+        Self::Box { radius: 0.5 }
+    }
+}
+
+impl PixelFilter {
+    pub const fn tent() -> Self {
+        Self::Tent { radius: 1.0 }
+    }
+
+    pub const fn gaussian() -> Self {
+        Self::Gaussian {
+            radius: 1.5,
+            alpha: 2.0,
+        }
+    }
+
+    pub const fn mitchell() -> Self {
+        Self::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    pub const fn blackman_harris() -> Self {
+        Self::BlackmanHarris { radius: 1.5 }
+    }
+
+    pub const fn radius(&self) -> f32 {
+        match *self {
+            Self::Box { radius }
+            | Self::Tent { radius }
+            | Self::Gaussian { radius, .. }
+            | Self::Mitchell { radius, .. }
+            | Self::BlackmanHarris { radius } => radius,
+        }
+    }
+
+    pub const fn with_radius(self, radius: f32) -> Self {
+        match self {
+            Self::Box { .. } => Self::Box { radius },
+            Self::Tent { .. } => Self::Tent { radius },
+            Self::Gaussian { alpha, .. } => Self::Gaussian { radius, alpha },
+            Self::Mitchell { b, c, .. } => Self::Mitchell { radius, b, c },
+            Self::BlackmanHarris { .. } => Self::BlackmanHarris { radius },
+        }
+    }
+
+    /// Weight of a sample at `offset` pixels from a pixel center. Not normalized, the image is
+    /// divided by the sum of weights.
+    pub fn evaluate(&self, offset: Vec2) -> f32 {
+        self.evaluate_1d(offset.x) * self.evaluate_1d(offset.y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        match *self {
+            // Half-open so that a sample on a pixel edge only lands in one pixel.
+            Self::Box { radius } => {
+                if -radius < x && x <= radius {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            _ => self.evaluate_symmetric(x.abs()),
+        }
+    }
+
+    fn evaluate_symmetric(&self, x: f32) -> f32 {
+        match *self {
+            Self::Box { .. } => unreachable!(),
+            Self::Tent { radius } => (radius - x).max(0.0),
+            Self::Gaussian { radius, alpha } => {
+                ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Self::Mitchell { radius, b, c } => {
+                if x >= radius {
+                    return 0.0;
+                }
+                let x = 2.0 * x / radius;
+                let (x2, x3) = (x * x, x * x * x);
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x2
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x3
+                        + (6.0 * b + 30.0 * c) * x2
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                }
+            }
+            Self::BlackmanHarris { radius } => {
+                if x >= radius {
+                    return 0.0;
+                }
+                const A: [f32; 4] = [0.358_75, 0.488_29, 0.141_28, 0.011_68];
+                let t = TAU * x / (2.0 * radius);
+                A[0] + A[1] * t.cos() + A[2] * (2.0 * t).cos() + A[3] * (3.0 * t).cos()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [PixelFilter; 5] = [
+        PixelFilter::Box { radius: 0.5 },
+        PixelFilter::tent(),
+        PixelFilter::gaussian(),
+        PixelFilter::mitchell(),
+        PixelFilter::blackman_harris(),
+    ];
+
+    #[test]
+    fn filters_peak_at_center_and_vanish_at_radius() {
+        for filter in FILTERS {
+            let center = filter.evaluate(Vec2::ZERO);
+            let radius = filter.radius();
+            assert!(center > 0.0, "{filter:?}");
+            for i in 1..100 {
+                let offset = Vec2::new(i as f32 / 100.0 * radius, 0.0);
+                assert!(filter.evaluate(offset) <= center, "{filter:?} at {offset}");
+                assert_eq!(
+                    filter.evaluate(offset * 0.99),
+                    filter.evaluate(-offset * 0.99)
+                );
+            }
+            assert!(
+                filter.evaluate(Vec2::new(-radius, 0.0)).abs() < 1e-4,
+                "{filter:?}"
+            );
+            assert_eq!(filter.evaluate(Vec2::new(0.0, radius * 1.01)), 0.0);
+        }
+    }
+
+    #[test]
+    fn mitchell_has_negative_lobes() {
+        let filter = PixelFilter::mitchell();
+        assert!(filter.evaluate(Vec2::new(1.5, 0.0)) < 0.0);
+    }
+}
@@ -1,15 +1,32 @@
-use std::ops::{Add, AddAssign, Index, IndexMut};
+use std::{
+    ops::{Add, AddAssign, Index},
+    path::Path,
+};
 
-use glam::{UVec2, Vec3};
+use exr::prelude::{
+    AnyChannel, AnyChannels, FlatSamples, Image as ExrImage, SmallVec, WritableImage,
+};
+use glam::{UVec2, Vec2, Vec3};
+use image::{ImageFormat, Rgb32FImage, RgbImage};
+
+use crate::{filter::PixelFilter, material::luminance, tonemap::DisplayTransform};
+
+/// Offset added to the pixel mean when computing relative errors, avoids dark pixels dominating.
+const RELATIVE_ERROR_EPSILON: f32 = 1.0e-3;
 
 #[derive(Clone)]
 pub struct ImageBuffer {
     pub size: UVec2,
+    /// Sum of the filter weighted samples splatted to each pixel.
     pixels: Vec<Vec3>,
-}
-
-fn gamma_correct(x: Vec3) -> Vec3 {
-    x.powf(1.0 / 2.2).min(Vec3::ONE)
+    /// Sum of the filter weights of each pixel.
+    weights: Vec<f32>,
+    /// Sum of the luminance of the samples taken in each pixel.
+    first_moments: Vec<f32>,
+    /// Sum of the squared luminance of the samples taken in each pixel.
+    second_moments: Vec<f32>,
+    /// Number of samples taken in each pixel.
+    samples: Vec<u32>,
 }
 
 impl ImageBuffer {
@@ -18,30 +35,207 @@ impl ImageBuffer {
         Self {
             size,
             pixels: [Vec3::ZERO].repeat((size.x * size.y) as usize),
+            weights: [0.0].repeat((size.x * size.y) as usize),
+            first_moments: [0.0].repeat((size.x * size.y) as usize),
+            second_moments: [0.0].repeat((size.x * size.y) as usize),
+            samples: [0].repeat((size.x * size.y) as usize),
         }
     }
 
+    pub fn from_raw(
+        size: UVec2,
+        pixels: Vec<Vec3>,
+        weights: Vec<f32>,
+        first_moments: Vec<f32>,
+        second_moments: Vec<f32>,
+        samples: Vec<u32>,
+    ) -> Self {
+        assert_eq!(pixels.len(), (size.x * size.y) as usize);
+        assert_eq!(weights.len(), pixels.len());
+        assert_eq!(first_moments.len(), pixels.len());
+        assert_eq!(second_moments.len(), pixels.len());
+        assert_eq!(samples.len(), pixels.len());
+        Self {
+            size,
+            pixels,
+            weights,
+            first_moments,
+            second_moments,
+            samples,
+        }
+    }
+
+    #[inline]
+    pub fn pixels(&self) -> &[Vec3] {
+        &self.pixels
+    }
+
+    #[inline]
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    #[inline]
+    pub fn first_moments(&self) -> &[f32] {
+        &self.first_moments
+    }
+
+    #[inline]
+    pub fn second_moments(&self) -> &[f32] {
+        &self.second_moments
+    }
+
+    #[inline]
+    pub fn samples(&self) -> &[u32] {
+        &self.samples
+    }
+
+    #[inline]
+    fn add_statistics(&mut self, index: usize, value: Vec3) {
+        self.first_moments[index] += luminance(value);
+        self.second_moments[index] += luminance(value) * luminance(value);
+        self.samples[index] += 1;
+    }
+
+    /// Add a sample to a single pixel with unit weight, equivalent to splatting with a box filter.
+    #[inline]
+    pub fn add_sample(&mut self, pixel: UVec2, value: Vec3) {
+        let index = self.index(pixel);
+        self.pixels[index] += value;
+        self.weights[index] += 1.0;
+        self.add_statistics(index, value);
+    }
+
+    /// Add a sample taken at the continuous image `position` to all pixels within the filter
+    /// radius, weighted by the filter.
+    pub fn splat(&mut self, filter: &PixelFilter, position: Vec2, value: Vec3) {
+        let pixel = position.floor().as_uvec2().min(self.size - 1);
+        let index = self.index(pixel);
+        self.add_statistics(index, value);
+
+        // Pixel centers are at half-integer coordinates.
+        let radius = filter.radius();
+        let min = (position - radius - 0.5).ceil().max(Vec2::ZERO).as_uvec2();
+        let max = (position + radius - 0.5)
+            .floor()
+            .min((self.size - 1).as_vec2());
+        if max.x < 0.0 || max.y < 0.0 {
+            return;
+        }
+        let max = max.as_uvec2();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let pixel = UVec2::new(x, y);
+                let weight = filter.evaluate(pixel.as_vec2() + 0.5 - position);
+                if weight != 0.0 {
+                    let index = self.index(pixel);
+                    self.pixels[index] += weight * value;
+                    self.weights[index] += weight;
+                }
+            }
+        }
+    }
+
+    /// Filtered radiance of a pixel, zero when no samples have been splatted to it.
+    ///
+    /// Filters with negative lobes can give negative radiance, which is clamped to zero, and with
+    /// few samples even a weight of zero or below. Such pixels are black until more samples land
+    /// near their centers.
+    #[inline]
+    fn mean(&self, index: usize) -> Vec3 {
+        let weight = self.weights[index];
+        if weight > 0.0 {
+            (self.pixels[index] / weight).max(Vec3::ZERO)
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    fn index_relative_error(&self, index: usize) -> f32 {
+        let samples = self.samples[index];
+        if samples < 2 {
+            return f32::INFINITY;
+        }
+        let n = samples as f32;
+        let mean = self.first_moments[index] / n;
+        let variance = (self.second_moments[index] / n - mean * mean).max(0.0) * n / (n - 1.0);
+        (variance / n).sqrt() / (mean.max(0.0) + RELATIVE_ERROR_EPSILON)
+    }
+
+    /// Estimated relative standard error of the mean luminance of the samples taken in a pixel,
+    /// infinite when the pixel has fewer than two samples.
+    pub fn pixel_relative_error(&self, pixel: UVec2) -> f32 {
+        self.index_relative_error(self.index(pixel))
+    }
+
+    /// Mean over all pixels of [`ImageBuffer::pixel_relative_error`]. Needs at least two samples
+    /// in every pixel.
+    pub fn relative_error(&self) -> Option<f32> {
+        let sum: f32 = (0..self.pixels.len())
+            .map(|index| self.index_relative_error(index))
+            .sum();
+        sum.is_finite().then(|| sum / self.pixels.len() as f32)
+    }
+
     #[inline]
     const fn index(&self, idx: UVec2) -> usize {
         (self.size.x * idx.y + idx.x) as usize
     }
 
-    pub fn to_rgb8(self, iterations: u16) -> Vec<u8> {
-        let iterations_inv = 1.0 / f32::from(iterations);
-        self.pixels
-            .into_iter()
-            .flat_map(|p| -> [u8; 3] {
-                let color = (gamma_correct(p * iterations_inv) * 255.0).round();
+    pub fn to_rgb8(&self, display: &DisplayTransform) -> Vec<u8> {
+        (0..self.pixels.len())
+            .flat_map(|index| -> [u8; 3] {
+                let color = (display.apply(self.mean(index)) * 255.0).round();
                 [color.x as u8, color.y as u8, color.z as u8]
             })
             .collect()
     }
 
+    pub fn to_rgb_image(&self, display: &DisplayTransform) -> RgbImage {
+        RgbImage::from_raw(self.size.x, self.size.y, self.to_rgb8(display)).unwrap()
+    }
+
+    /// Mean linear radiance of each pixel, without gamma correction or clamping.
+    pub fn to_rgb32f(&self) -> Rgb32FImage {
+        let pixels = (0..self.pixels.len())
+            .flat_map(|index| self.mean(index).to_array())
+            .collect();
+        Rgb32FImage::from_raw(self.size.x, self.size.y, pixels).unwrap()
+    }
+
+    /// Write the linear radiance as a Radiance RGBE image.
+    pub fn write_hdr(&self, path: &Path) -> image::ImageResult<()> {
+        self.to_rgb32f().save_with_format(path, ImageFormat::Hdr)
+    }
+
+    /// Write the linear radiance as 32-bit float OpenEXR image. The sample count of each pixel
+    /// is stored in the auxiliary `samples.Y` channel.
+    pub fn write_exr(&self, path: &Path) -> exr::error::Result<()> {
+        let channel = |name: &str, component: usize| {
+            let samples = (0..self.pixels.len())
+                .map(|index| self.mean(index)[component])
+                .collect();
+            AnyChannel::new(name, FlatSamples::F32(samples))
+        };
+        let samples = AnyChannel::new(
+            "samples.Y",
+            FlatSamples::F32(self.samples.iter().map(|n| *n as f32).collect()),
+        );
+        let channels = AnyChannels::sort(SmallVec::from_vec(vec![
+            channel("R", 0),
+            channel("G", 1),
+            channel("B", 2),
+            samples,
+        ]));
+        ExrImage::from_channels((self.size.x as usize, self.size.y as usize), channels)
+            .write()
+            .to_file(path)
+    }
+
     #[inline]
-    pub fn into_rgba_iter(self, iterations: u16) -> impl Iterator<Item = [u8; 4]> {
-        let iterations_inv = 1.0 / f32::from(iterations);
-        self.pixels.into_iter().map(move |p| -> [u8; 4] {
-            let p = (gamma_correct(p * iterations_inv) * 255.0).round();
+    pub fn into_rgba_iter(self, display: DisplayTransform) -> impl Iterator<Item = [u8; 4]> {
+        (0..self.pixels.len()).map(move |index| -> [u8; 4] {
+            let p = (display.apply(self.mean(index)) * 255.0).round();
             [p.x as u8, p.y as u8, p.z as u8, u8::MAX]
         })
     }
@@ -52,11 +246,6 @@ impl ImageBuffer {
         let size_y = self.size.y;
         (0..size_y).flat_map(move |y| (0..size_x).map(move |x| UVec2::new(x, y)))
     }
-
-    #[inline]
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (UVec2, &mut Vec3)> {
-        self.coordinates().zip(self.pixels.iter_mut())
-    }
 }
 
 impl Index<UVec2> for ImageBuffer {
@@ -68,14 +257,6 @@ impl Index<UVec2> for ImageBuffer {
     }
 }
 
-impl IndexMut<UVec2> for ImageBuffer {
-    #[inline]
-    fn index_mut(&mut self, index: UVec2) -> &mut Self::Output {
-        let index = self.index(index);
-        &mut self.pixels[index]
-    }
-}
-
 impl Add for ImageBuffer {
     type Output = Self;
 
@@ -90,6 +271,30 @@ impl Add for ImageBuffer {
                 .zip(rhs.pixels)
                 .map(|(a, b)| a + b)
                 .collect::<Vec<_>>(),
+            weights: self
+                .weights
+                .into_iter()
+                .zip(rhs.weights)
+                .map(|(a, b)| a + b)
+                .collect::<Vec<_>>(),
+            first_moments: self
+                .first_moments
+                .into_iter()
+                .zip(rhs.first_moments)
+                .map(|(a, b)| a + b)
+                .collect::<Vec<_>>(),
+            second_moments: self
+                .second_moments
+                .into_iter()
+                .zip(rhs.second_moments)
+                .map(|(a, b)| a + b)
+                .collect::<Vec<_>>(),
+            samples: self
+                .samples
+                .into_iter()
+                .zip(rhs.samples)
+                .map(|(a, b)| a + b)
+                .collect::<Vec<_>>(),
         }
     }
 }
@@ -102,5 +307,179 @@ impl AddAssign for ImageBuffer {
             .iter_mut()
             .zip(rhs.pixels)
             .for_each(|(a, b)| *a += b);
+        self.weights
+            .iter_mut()
+            .zip(rhs.weights)
+            .for_each(|(a, b)| *a += b);
+        self.first_moments
+            .iter_mut()
+            .zip(rhs.first_moments)
+            .for_each(|(a, b)| *a += b);
+        self.second_moments
+            .iter_mut()
+            .zip(rhs.second_moments)
+            .for_each(|(a, b)| *a += b);
+        self.samples
+            .iter_mut()
+            .zip(rhs.samples)
+            .for_each(|(a, b)| *a += b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exr::prelude::{ReadChannels, ReadLayers};
+
+    fn buffer() -> ImageBuffer {
+        let mut buffer = ImageBuffer::new(UVec2::new(2, 1));
+        buffer.add_sample(UVec2::new(0, 0), Vec3::new(3.0, 6.0, 12.0));
+        buffer.add_sample(UVec2::new(0, 0), Vec3::new(1.0, 2.0, 4.0));
+        buffer.add_sample(UVec2::new(1, 0), Vec3::new(0.25, 0.5, 0.75));
+        buffer
+    }
+
+    #[test]
+    fn to_rgb32f_is_linear_and_unclamped() {
+        let image = buffer().to_rgb32f();
+
+        assert_eq!(image.get_pixel(0, 0).0, [2.0, 4.0, 8.0]);
+        assert_eq!(image.get_pixel(1, 0).0, [0.25, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn mean_uses_per_pixel_sample_counts() {
+        let mut buffer = buffer();
+        buffer += buffer.clone();
+
+        assert_eq!(buffer.samples(), [4, 2]);
+        let image = buffer.to_rgb32f();
+        assert_eq!(image.get_pixel(0, 0).0, [2.0, 4.0, 8.0]);
+        assert_eq!(image.get_pixel(1, 0).0, [0.25, 0.5, 0.75]);
+        assert_eq!(
+            ImageBuffer::new(UVec2::ONE).to_rgb32f().get_pixel(0, 0).0,
+            [0.0; 3]
+        );
+    }
+
+    #[test]
+    fn write_exr_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "tracing_image_buffer_round_trip_{}.exr",
+            std::process::id()
+        ));
+        buffer().write_exr(&path).unwrap();
+
+        let image = exr::prelude::read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .first_valid_layer()
+            .all_attributes()
+            .from_file(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let channel = |name: &str| {
+            let channel = image
+                .layer_data
+                .channel_data
+                .list
+                .iter()
+                .find(|c| c.name == *name)
+                .unwrap();
+            channel.sample_data.values_as_f32().collect::<Vec<_>>()
+        };
+        assert_eq!(channel("R"), [2.0, 0.25]);
+        assert_eq!(channel("G"), [4.0, 0.5]);
+        assert_eq!(channel("B"), [8.0, 0.75]);
+        assert_eq!(channel("samples.Y"), [2.0, 1.0]);
+    }
+
+    #[test]
+    fn box_splat_equals_add_sample() {
+        let filter = PixelFilter::default();
+        let mut splatted = ImageBuffer::new(UVec2::new(3, 3));
+        let mut added = ImageBuffer::new(UVec2::new(3, 3));
+        for (position, value) in [
+            (Vec2::new(1.2, 1.7), Vec3::ONE),
+            (Vec2::new(2.9, 0.0), Vec3::X),
+        ] {
+            splatted.splat(&filter, position, value);
+            added.add_sample(position.as_uvec2(), value);
+        }
+        assert_eq!(splatted.pixels(), added.pixels());
+        assert_eq!(splatted.weights(), added.weights());
+        assert_eq!(splatted.samples(), added.samples());
+    }
+
+    #[test]
+    fn splat_reaches_neighbours_within_radius() {
+        let mut buffer = ImageBuffer::new(UVec2::new(4, 4));
+        buffer.splat(&PixelFilter::tent(), Vec2::new(1.2, 1.5), Vec3::ONE);
+        buffer.splat(&PixelFilter::tent(), Vec2::new(0.1, 3.9), Vec3::ONE);
+
+        let nonzero = buffer.weights().iter().filter(|w| **w > 0.0).count();
+        assert_eq!(nonzero, 2 + 1);
+        assert_eq!(buffer.samples().iter().sum::<u32>(), 2);
+        // The mean of a constant signal is independent of the weights.
+        assert_eq!(buffer.to_rgb32f().get_pixel(1, 1).0, [1.0; 3]);
+        assert_eq!(buffer.to_rgb32f().get_pixel(3, 3).0, [0.0; 3]);
+    }
+
+    #[test]
+    fn negative_filter_lobes_give_black_pixels() {
+        let mut buffer = ImageBuffer::new(UVec2::new(4, 1));
+        let mitchell = PixelFilter::mitchell();
+        // Pixel 2 only sees the sample through the negative lobe.
+        buffer.splat(&mitchell, Vec2::new(1.0, 0.5), Vec3::ONE);
+        assert!(buffer.weights()[2] < 0.0);
+        assert_eq!(buffer.to_rgb32f().get_pixel(2, 0).0, [0.0; 3]);
+        // A dark sample at its center makes the weight positive but the sum stays negative.
+        buffer.splat(&mitchell, Vec2::new(2.5, 0.5), Vec3::ZERO);
+        assert!(buffer.weights()[2] > 0.0);
+        assert_eq!(buffer.to_rgb32f().get_pixel(2, 0).0, [0.0; 3]);
+    }
+
+    #[test]
+    fn relative_error_ignores_samples_splatted_from_neighbours() {
+        let mut buffer = ImageBuffer::new(UVec2::new(2, 1));
+        for _ in 0..4 {
+            buffer.splat(&PixelFilter::tent(), Vec2::new(0.9, 0.5), Vec3::ONE);
+            buffer.splat(&PixelFilter::tent(), Vec2::new(1.1, 0.5), Vec3::ZERO);
+        }
+        // The filtered means are blurred, but each pixel's own samples are constant.
+        assert!(buffer.to_rgb32f().get_pixel(0, 0).0[0] < 1.0);
+        assert!(buffer.relative_error().unwrap() < 1e-3);
+    }
+
+    #[test]
+    fn relative_error_of_constant_samples_is_zero() {
+        let mut buffer = ImageBuffer::new(UVec2::new(2, 2));
+        for _ in 0..4 {
+            for pixel in buffer.coordinates() {
+                buffer.add_sample(pixel, Vec3::splat(0.5));
+            }
+        }
+        assert!(buffer.relative_error().unwrap() < 1e-3);
+        buffer.add_sample(UVec2::ZERO, Vec3::ONE);
+        assert!(buffer.pixel_relative_error(UVec2::ZERO) > 0.1);
+        assert_eq!(ImageBuffer::new(UVec2::ONE).relative_error(), None);
+    }
+
+    #[test]
+    fn relative_error_decreases_with_samples() {
+        let mut buffer = ImageBuffer::new(UVec2::new(1, 1));
+        let mut errors = Vec::new();
+        for i in 0..64 {
+            let value = if i % 2 == 0 { Vec3::ZERO } else { Vec3::ONE };
+            buffer.add_sample(UVec2::ZERO, value);
+            if i % 16 == 15 {
+                errors.push(buffer.relative_error().unwrap());
+            }
+        }
+        assert!(errors.windows(2).all(|w| w[1] < w[0]), "{errors:?}");
+        // Bernoulli with p = 0.5 has relative standard error 1 / sqrt(n).
+        assert!((errors[3] - 1.0 / 8.0).abs() < 0.01, "{errors:?}");
     }
 }
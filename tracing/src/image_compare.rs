@@ -0,0 +1,346 @@
+use std::f32::consts::PI;
+
+use glam::{Mat3, UVec2, Vec3};
+use image::RgbImage;
+
+/// Pixels per degree of visual angle the perceptual metric assumes, a 0.7 m viewing distance
+/// to a 4K monitor.
+const PIXELS_PER_DEGREE: f32 = 67.0;
+
+const WHITE: Vec3 = Vec3::new(0.950_489, 1.0, 1.088_84);
+
+/// Difference between a rendered image and a reference, on display values in [0, 1].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageDifference {
+    /// Root mean square error over all channels.
+    pub rmse: f32,
+    /// Mean of the per-pixel perceptual error, see [`Comparison::flip`].
+    pub flip: f32,
+    /// Largest error of any channel of any pixel.
+    pub max_error: f32,
+}
+
+impl ImageDifference {
+    pub fn is_within(&self, tolerance: &ImageDifference) -> bool {
+        self.rmse <= tolerance.rmse
+            && self.flip <= tolerance.flip
+            && self.max_error <= tolerance.max_error
+    }
+}
+
+pub struct Comparison {
+    pub size: UVec2,
+    pub difference: ImageDifference,
+    /// Per-pixel perceptual error in [0, 1]. Follows the LDR-FLIP color and feature pipelines
+    /// (Andersson et al. 2020) with a simplified spatial filter at the image borders.
+    pub flip: Vec<f32>,
+}
+
+impl Comparison {
+    /// The per-pixel perceptual error mapped to an approximation of the magma color map.
+    pub fn heatmap(&self) -> RgbImage {
+        const STOPS: [Vec3; 5] = [
+            Vec3::new(0.0, 0.0, 4.0),
+            Vec3::new(81.0, 18.0, 124.0),
+            Vec3::new(183.0, 55.0, 121.0),
+            Vec3::new(252.0, 137.0, 97.0),
+            Vec3::new(252.0, 253.0, 191.0),
+        ];
+        let pixels = self.flip.iter().flat_map(|error| {
+            let x = error.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+            let i = (x as usize).min(STOPS.len() - 2);
+            let color = STOPS[i].lerp(STOPS[i + 1], x - i as f32).round();
+            [color.x as u8, color.y as u8, color.z as u8]
+        });
+        RgbImage::from_raw(self.size.x, self.size.y, pixels.collect()).unwrap()
+    }
+}
+
+pub fn compare(reference: &RgbImage, test: &RgbImage) -> Comparison {
+    assert_eq!(reference.dimensions(), test.dimensions());
+    let size = UVec2::from(reference.dimensions());
+    let reference = to_vec3(reference);
+    let test = to_vec3(test);
+
+    let squared_sum: f32 = reference
+        .iter()
+        .zip(&test)
+        .map(|(r, t)| (*r - *t).length_squared())
+        .sum();
+    let rmse = (squared_sum / (3 * reference.len()) as f32).sqrt();
+    let max_error = reference
+        .iter()
+        .zip(&test)
+        .map(|(r, t)| (*r - *t).abs().max_element())
+        .fold(0.0, f32::max);
+    let flip = flip(size, &reference, &test);
+    let mean_flip = flip.iter().sum::<f32>() / flip.len() as f32;
+
+    Comparison {
+        size,
+        difference: ImageDifference {
+            rmse,
+            flip: mean_flip,
+            max_error,
+        },
+        flip,
+    }
+}
+
+fn to_vec3(image: &RgbImage) -> Vec<Vec3> {
+    image
+        .pixels()
+        .map(|p| Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32) / 255.0)
+        .collect()
+}
+
+fn flip(size: UVec2, reference: &[Vec3], test: &[Vec3]) -> Vec<f32> {
+    let reference: Vec<Vec3> = reference.iter().map(|c| srgb_to_ycxcz(*c)).collect();
+    let test: Vec<Vec3> = test.iter().map(|c| srgb_to_ycxcz(*c)).collect();
+
+    let csf = csf_kernel();
+    let color_reference = convolve(size, &reference, &csf);
+    let color_test = convolve(size, &test, &csf);
+
+    let (edge, point) = feature_kernels();
+    let features = |image: &[Vec3]| {
+        let achromatic: Vec<f32> = image.iter().map(|c| (c.x + 16.0) / 116.0).collect();
+        let magnitude = |kernel: &Kernel<f32>| -> Vec<f32> {
+            let x = convolve(size, &achromatic, kernel);
+            let y = convolve(size, &achromatic, &kernel.transposed());
+            x.iter().zip(&y).map(|(x, y)| x.hypot(*y)).collect()
+        };
+        (magnitude(&edge), magnitude(&point))
+    };
+    let (edges_reference, points_reference) = features(&reference);
+    let (edges_test, points_test) = features(&test);
+
+    let max_color_error = hyab(
+        hunt_adjust(linear_rgb_to_lab(Vec3::Y)),
+        hunt_adjust(linear_rgb_to_lab(Vec3::Z)),
+    )
+    .powf(0.7);
+
+    (0..reference.len())
+        .map(|i| {
+            let lab = |c: Vec3| hunt_adjust(linear_rgb_to_lab(ycxcz_to_linear_rgb(c)));
+            let color_error = hyab(lab(color_reference[i]), lab(color_test[i])).powf(0.7);
+            let color_error = compress(color_error, max_color_error);
+            let feature_error = (edges_reference[i] - edges_test[i])
+                .abs()
+                .max((points_reference[i] - points_test[i]).abs());
+            let feature_error = (feature_error / 2.0f32.sqrt()).sqrt();
+            color_error.powf(1.0 - feature_error)
+        })
+        .collect()
+}
+
+/// Map color errors to [0, 1], spending most of the range on small differences.
+fn compress(error: f32, max_error: f32) -> f32 {
+    const CUTOFF: f32 = 0.4;
+    const TARGET: f32 = 0.95;
+    let cutoff = CUTOFF * max_error;
+    if error < cutoff {
+        TARGET / cutoff * error
+    } else {
+        (TARGET + (error - cutoff) / (max_error - cutoff) * (1.0 - TARGET)).min(1.0)
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+const RGB_TO_XYZ: Mat3 = Mat3::from_cols(
+    Vec3::new(0.412_456_4, 0.212_672_9, 0.019_333_9),
+    Vec3::new(0.357_576_1, 0.715_152_2, 0.119_192),
+    Vec3::new(0.180_437_5, 0.072_175, 0.950_304_1),
+);
+
+fn srgb_to_ycxcz(c: Vec3) -> Vec3 {
+    let xyz = RGB_TO_XYZ * c.map(srgb_to_linear) / WHITE;
+    Vec3::new(
+        116.0 * xyz.y - 16.0,
+        500.0 * (xyz.x - xyz.y),
+        200.0 * (xyz.y - xyz.z),
+    )
+}
+
+fn ycxcz_to_linear_rgb(c: Vec3) -> Vec3 {
+    let y = (c.x + 16.0) / 116.0;
+    let xyz = Vec3::new(y + c.y / 500.0, y, y - c.z / 200.0) * WHITE;
+    (RGB_TO_XYZ.inverse() * xyz).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+fn linear_rgb_to_lab(c: Vec3) -> Vec3 {
+    const DELTA: f32 = 6.0 / 29.0;
+    let f = |t: f32| {
+        if t > DELTA.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let xyz = (RGB_TO_XYZ * c / WHITE).map(f);
+    Vec3::new(
+        116.0 * xyz.y - 16.0,
+        500.0 * (xyz.x - xyz.y),
+        200.0 * (xyz.y - xyz.z),
+    )
+}
+
+/// Chroma appears weaker at low lightness.
+fn hunt_adjust(lab: Vec3) -> Vec3 {
+    Vec3::new(lab.x, 0.01 * lab.x * lab.y, 0.01 * lab.x * lab.z)
+}
+
+fn hyab(a: Vec3, b: Vec3) -> f32 {
+    let d = a - b;
+    d.x.abs() + d.y.hypot(d.z)
+}
+
+struct Kernel<T> {
+    radius: i32,
+    weights: Vec<T>,
+}
+
+impl<T: Copy> Kernel<T> {
+    fn from_fn(radius: i32, f: impl Fn(i32, i32) -> T) -> Self {
+        let weights = (-radius..=radius)
+            .flat_map(|y| (-radius..=radius).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+        Self { radius, weights }
+    }
+
+    fn get(&self, x: i32, y: i32) -> T {
+        let width = 2 * self.radius + 1;
+        self.weights[((y + self.radius) * width + x + self.radius) as usize]
+    }
+
+    fn transposed(&self) -> Self {
+        Self::from_fn(self.radius, |x, y| self.get(y, x))
+    }
+}
+
+/// Contrast sensitivity of the Y, Cx and Cz channels as sums of Gaussians in visual degrees.
+fn csf_kernel() -> Kernel<Vec3> {
+    const PARAMETERS: [(f32, f32, f32, f32); 3] = [
+        (1.0, 0.0047, 0.0, 1.0e-5),
+        (1.0, 0.0053, 0.0, 1.0e-5),
+        (34.1, 0.04, 13.5, 0.025),
+    ];
+    let gaussian = |a: f32, b: f32, x2: f32| a * (PI / b).sqrt() * (-PI * PI * x2 / b).exp();
+    let max_b = 0.04f32;
+    let radius = (3.0 * (max_b / (2.0 * PI * PI)).sqrt() * PIXELS_PER_DEGREE).ceil() as i32;
+    let mut kernel = Kernel::from_fn(radius, |x, y| {
+        let x2 = (x * x + y * y) as f32 / (PIXELS_PER_DEGREE * PIXELS_PER_DEGREE);
+        Vec3::from_array(
+            PARAMETERS.map(|(a1, b1, a2, b2)| gaussian(a1, b1, x2) + gaussian(a2, b2, x2)),
+        )
+    });
+    let sum: Vec3 = kernel.weights.iter().sum();
+    kernel.weights.iter_mut().for_each(|w| *w /= sum);
+    kernel
+}
+
+/// First and second derivative of a Gaussian along x, detecting edges and points.
+fn feature_kernels() -> (Kernel<f32>, Kernel<f32>) {
+    let sigma = 0.5 * 0.082 * PIXELS_PER_DEGREE;
+    let radius = (3.0 * sigma).ceil() as i32;
+    let gaussian = |x: i32, y: i32| (-((x * x + y * y) as f32) / (2.0 * sigma * sigma)).exp();
+    let edge = Kernel::from_fn(radius, |x, y| -(x as f32) * gaussian(x, y));
+    let point = Kernel::from_fn(radius, |x, y| {
+        ((x * x) as f32 / (sigma * sigma) - 1.0) * gaussian(x, y)
+    });
+    (normalize_signed(edge), normalize_signed(point))
+}
+
+/// Scale the positive weights to sum to one and the negative weights to sum to minus one.
+fn normalize_signed(mut kernel: Kernel<f32>) -> Kernel<f32> {
+    let positive: f32 = kernel.weights.iter().filter(|w| **w > 0.0).sum();
+    let negative: f32 = -kernel.weights.iter().filter(|w| **w < 0.0).sum::<f32>();
+    for w in &mut kernel.weights {
+        *w /= if *w > 0.0 { positive } else { negative };
+    }
+    kernel
+}
+
+/// Convolution with the image edges clamped.
+fn convolve<T, W>(size: UVec2, image: &[T], kernel: &Kernel<W>) -> Vec<T>
+where
+    T: Copy + Default + std::ops::Add<Output = T> + std::ops::Mul<W, Output = T>,
+    W: Copy,
+{
+    let (width, height) = (size.x as i32, size.y as i32);
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let mut sum = T::default();
+            for ky in -kernel.radius..=kernel.radius {
+                for kx in -kernel.radius..=kernel.radius {
+                    let sx = (x + kx).clamp(0, width - 1);
+                    let sy = (y + ky).clamp(0, height - 1);
+                    sum = sum + image[(sy * width + sx) as usize] * kernel.get(kx, ky);
+                }
+            }
+            sum
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn checkerboard(size: u32, a: [u8; 3], b: [u8; 3]) -> RgbImage {
+        RgbImage::from_fn(size, size, |x, y| {
+            if (x / 4 + y / 4) % 2 == 0 {
+                Rgb(a)
+            } else {
+                Rgb(b)
+            }
+        })
+    }
+
+    #[test]
+    fn identical_images_have_no_difference() {
+        let image = checkerboard(16, [200, 30, 30], [10, 10, 80]);
+        let comparison = compare(&image, &image);
+        assert_eq!(
+            comparison.difference,
+            ImageDifference {
+                rmse: 0.0,
+                flip: 0.0,
+                max_error: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn difference_grows_with_change() {
+        let reference = checkerboard(16, [128, 128, 128], [64, 64, 64]);
+        let small = checkerboard(16, [132, 128, 128], [64, 64, 64]);
+        let large = checkerboard(16, [255, 0, 128], [64, 64, 64]);
+        let small = compare(&reference, &small).difference;
+        let large = compare(&reference, &large).difference;
+        assert!(small.rmse > 0.0 && small.rmse < large.rmse);
+        assert!(small.flip > 0.0 && small.flip < large.flip);
+        assert!(small.max_error < large.max_error);
+        assert!(large.flip <= 1.0);
+    }
+
+    #[test]
+    fn flip_detects_lost_edges() {
+        let reference = checkerboard(16, [160, 160, 160], [96, 96, 96]);
+        let flat = RgbImage::from_pixel(16, 16, Rgb([128, 128, 128]));
+        let comparison = compare(&reference, &flat);
+        assert!(comparison.difference.flip > 0.1);
+        assert_eq!(comparison.heatmap().dimensions(), (16, 16));
+    }
+}
@@ -1,11 +1,20 @@
+pub mod adaptive;
 pub mod camera;
+pub mod checkpoint;
 pub mod collections;
+pub mod environment;
+pub mod filter;
 pub mod image_buffer;
+pub mod image_compare;
 pub mod light;
+pub mod light_sampler;
 pub mod material;
 pub mod measure;
 pub mod pathtracer;
 pub mod properties;
 pub mod raylogger;
+pub mod sampler;
 pub mod sampling;
+pub mod sky;
+pub mod tonemap;
 pub mod worker;
@@ -1,11 +1,60 @@
-use std::ops::RangeInclusive;
+use std::{
+    f32::consts::{PI, TAU},
+    ops::RangeInclusive,
+};
 
-use geometry::ray::Ray;
+use geometry::{
+    aabb::Aabb,
+    any_triangle::AnyTriangle,
+    ray::Ray,
+    sphere::Sphere,
+    triangle::{Triangle, TriangleNormals},
+};
 use glam::Vec3;
-use rand::rngs::SmallRng;
 use wavefront::mtl::{self};
 
-use crate::sampling::uniform_sample_unit_sphere;
+use crate::{
+    light_sampler::LightBounds,
+    material::{Material, luminance},
+    properties::TriangleProperties,
+    sampler::Sampler,
+    sampling::{uniform_cone_pdf, uniform_sample_cone, uniform_sample_triangle},
+};
+
+/// Shortens shadow rays towards surface lights to avoid hitting the light geometry itself.
+const SHADOW_RAY_EPSILON: f32 = 1.0e-4;
+
+#[derive(Debug)]
+pub struct LightSample {
+    pub is_delta: bool,
+    /// Solid angle PDF for the shadow ray direction, always 1.0 for delta lights.
+    pub pdf: f32,
+    /// Incident radiance at the shaded point, or irradiance for delta lights.
+    pub radiance: Vec3,
+    pub shadow_ray: Ray,
+    pub t_range: RangeInclusive<f32>,
+}
+
+impl LightSample {
+    fn delta(point: Vec3, target: Vec3, radiance: Vec3, t_range: RangeInclusive<f32>) -> Self {
+        Self {
+            is_delta: true,
+            pdf: 1.0,
+            radiance,
+            shadow_ray: Ray::between(point, target),
+            t_range,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LightIntersection {
+    pub t: f32,
+    /// Zero when the side of the light facing the ray does not emit.
+    pub radiance: Vec3,
+    /// Solid angle PDF of sampling the ray direction from the ray origin.
+    pub pdf: f32,
+}
 
 #[derive(Clone, Debug)]
 pub struct PointLight {
@@ -14,12 +63,23 @@ pub struct PointLight {
 }
 
 impl PointLight {
-    fn emitted(&self, point: &Vec3) -> Vec3 {
+    fn emitted(&self, point: Vec3) -> Vec3 {
         self.intensity / (self.center - point).length_squared()
     }
 
-    const fn sample(&self) -> (Vec3, RangeInclusive<f32>) {
-        (self.center, 0.0..=1.0)
+    fn sample(&self, point: Vec3) -> LightSample {
+        LightSample::delta(point, self.center, self.emitted(point), 0.0..=1.0)
+    }
+
+    fn bounds(&self) -> LightBounds {
+        LightBounds {
+            aabb: Aabb::from_extents(self.center, self.center),
+            phi: 4.0 * PI * luminance(self.intensity),
+            w: Vec3::Y,
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        }
     }
 }
 
@@ -30,10 +90,244 @@ pub struct SphericalLight {
 }
 
 impl SphericalLight {
+    /// Radiance leaving the sphere surface, chosen so that the irradiance far away from the light
+    /// matches a point light with the same intensity.
     #[inline]
-    fn sample(&self, rng: &mut SmallRng) -> (Vec3, RangeInclusive<f32>) {
-        let target = self.point.center + uniform_sample_unit_sphere(rng) * self.radius;
-        (target, 0.0..=1.0)
+    fn radiance(&self) -> Vec3 {
+        self.point.intensity / (PI * self.radius * self.radius)
+    }
+
+    /// Squared sine of the half angle of the cone of directions from `point` towards the sphere,
+    /// `None` when the point is inside the sphere.
+    #[inline]
+    fn sin2_theta_max(&self, point: Vec3) -> Option<f32> {
+        let sin2_theta_max =
+            self.radius * self.radius / (self.point.center - point).length_squared();
+        (sin2_theta_max < 1.0).then_some(sin2_theta_max)
+    }
+
+    /// Samples the cone of directions towards the sphere, every sample hits the visible cap.
+    fn sample(&self, point: Vec3, sampler: &mut dyn Sampler) -> LightSample {
+        let Some(sin2_theta_max) = self.sin2_theta_max(point) else {
+            // The outside of the sphere, which is what emits light, is not visible from inside.
+            return LightSample {
+                is_delta: false,
+                pdf: 0.0,
+                radiance: Vec3::ZERO,
+                shadow_ray: Ray::between(point, self.point.center),
+                t_range: 0.0..=1.0,
+            };
+        };
+        let to_center = self.point.center - point;
+        let distance = to_center.length();
+        let axis = to_center / distance;
+        let local = uniform_sample_cone(sampler, sin2_theta_max);
+        let (tangent, bitangent) = axis.any_orthonormal_pair();
+        let direction = local.x * tangent + local.y * bitangent + local.z * axis;
+        // Distance to the first intersection of the direction and the sphere.
+        let sin2_theta = local.x * local.x + local.y * local.y;
+        let t = distance * local.z
+            - (self.radius * self.radius - distance * distance * sin2_theta)
+                .max(0.0)
+                .sqrt();
+        LightSample {
+            is_delta: false,
+            pdf: uniform_cone_pdf(sin2_theta_max),
+            radiance: self.radiance(),
+            shadow_ray: Ray::between(point, point + t * direction),
+            t_range: 0.0..=1.0,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<LightIntersection> {
+        let sphere = Sphere::new(self.point.center, self.radius);
+        let intersection = sphere.intersect_ray(ray)?;
+        if intersection.t <= 0.0 {
+            return None;
+        }
+        let (radiance, pdf) = self
+            .sin2_theta_max(ray.origin)
+            .map_or((Vec3::ZERO, 0.0), |sin2_theta_max| {
+                (self.radiance(), uniform_cone_pdf(sin2_theta_max))
+            });
+        Some(LightIntersection {
+            t: intersection.t,
+            radiance,
+            pdf,
+        })
+    }
+
+    fn bounds(&self) -> LightBounds {
+        LightBounds {
+            aabb: Aabb::from_extents(
+                self.point.center - self.radius,
+                self.point.center + self.radius,
+            ),
+            ..self.point.bounds()
+        }
+    }
+}
+
+/// Parallelogram area light spanned by `edge1` and `edge2` from `corner`.
+#[derive(Clone, Debug)]
+pub struct QuadLight {
+    pub corner: Vec3,
+    pub edge1: Vec3,
+    pub edge2: Vec3,
+    pub intensity: Vec3,
+    /// Only emit towards `edge1 x edge2`.
+    pub one_sided: bool,
+}
+
+impl QuadLight {
+    #[inline]
+    fn area(&self) -> f32 {
+        self.edge1.cross(self.edge2).length()
+    }
+
+    /// Radiance leaving the surface, chosen so that the intensity along the normal matches a
+    /// point light with the same intensity.
+    #[inline]
+    fn radiance(&self) -> Vec3 {
+        self.intensity / self.area()
+    }
+
+    /// Solid angle PDF of sampling the point at `direction` from the shaded point, zero when the
+    /// emitting side is not facing it.
+    fn solid_angle_pdf(&self, direction: Vec3) -> f32 {
+        let normal = self.edge1.cross(self.edge2).normalize();
+        let distance_squared = direction.length_squared();
+        let cos_theta_light = -normal.dot(direction) / distance_squared.sqrt();
+        let cos_theta_light = if self.one_sided {
+            cos_theta_light
+        } else {
+            cos_theta_light.abs()
+        };
+        if cos_theta_light <= 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cos_theta_light * self.area())
+    }
+
+    fn sample(&self, point: Vec3, sampler: &mut dyn Sampler) -> LightSample {
+        let uv = sampler.get_2d();
+        let target = self.corner + uv.x * self.edge1 + uv.y * self.edge2;
+        let shadow_ray = Ray::between(point, target);
+        let pdf = self.solid_angle_pdf(shadow_ray.direction);
+        LightSample {
+            is_delta: false,
+            pdf,
+            radiance: if pdf > 0.0 {
+                self.radiance()
+            } else {
+                Vec3::ZERO
+            },
+            shadow_ray,
+            t_range: 0.0..=1.0,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<LightIntersection> {
+        let normal = self.edge1.cross(self.edge2);
+        let denominator = normal.dot(ray.direction);
+        if denominator == 0.0 {
+            return None;
+        }
+        let t = normal.dot(self.corner - ray.origin) / denominator;
+        if t <= 0.0 {
+            return None;
+        }
+        // Coordinates of the hit along the edges, which are orthogonal to the opposite edge.
+        let offset = ray.param(t) - self.corner;
+        let u = offset.cross(self.edge2).dot(normal) / normal.length_squared();
+        let v = self.edge1.cross(offset).dot(normal) / normal.length_squared();
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        let pdf = self.solid_angle_pdf(ray.direction * t);
+        Some(LightIntersection {
+            t,
+            radiance: if pdf > 0.0 {
+                self.radiance()
+            } else {
+                Vec3::ZERO
+            },
+            pdf,
+        })
+    }
+
+    fn bounds(&self) -> LightBounds {
+        let corners = [
+            self.corner,
+            self.corner + self.edge1,
+            self.corner + self.edge2,
+            self.corner + self.edge1 + self.edge2,
+        ];
+        let sides = if self.one_sided { 1.0 } else { 2.0 };
+        LightBounds {
+            aabb: Aabb::from_extents(
+                corners.into_iter().reduce(Vec3::min).unwrap(),
+                corners.into_iter().reduce(Vec3::max).unwrap(),
+            ),
+            phi: sides * PI * luminance(self.intensity),
+            w: self.edge1.cross(self.edge2).normalize(),
+            cos_theta_o: 1.0,
+            cos_theta_e: 0.0,
+            two_sided: !self.one_sided,
+        }
+    }
+}
+
+/// Point light emitting in a cone around `direction` with a smooth falloff between the inner and
+/// outer angle.
+#[derive(Clone, Debug)]
+pub struct SpotLight {
+    pub point: PointLight,
+    pub direction: Vec3,
+    pub cos_inner_angle: f32,
+    pub cos_outer_angle: f32,
+}
+
+impl SpotLight {
+    fn falloff(&self, cos_theta: f32) -> f32 {
+        if self.cos_inner_angle <= self.cos_outer_angle {
+            return if cos_theta >= self.cos_outer_angle {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        let x = ((cos_theta - self.cos_outer_angle)
+            / (self.cos_inner_angle - self.cos_outer_angle))
+            .clamp(0.0, 1.0);
+        x * x * (3.0 - 2.0 * x)
+    }
+
+    fn sample(&self, point: Vec3) -> LightSample {
+        let cos_theta = (point - self.point.center).normalize().dot(self.direction);
+        LightSample::delta(
+            point,
+            self.point.center,
+            self.point.emitted(point) * self.falloff(cos_theta),
+            0.0..=1.0,
+        )
+    }
+
+    fn bounds(&self) -> LightBounds {
+        let theta_inner = self.cos_inner_angle.clamp(-1.0, 1.0).acos();
+        let theta_outer = self.cos_outer_angle.clamp(-1.0, 1.0).acos();
+        // Power with the smooth falloff approximated as linear in the cosine.
+        let solid_angle = TAU
+            * ((1.0 - self.cos_inner_angle)
+                + (self.cos_inner_angle - self.cos_outer_angle).max(0.0) / 2.0);
+        LightBounds {
+            aabb: Aabb::from_extents(self.point.center, self.point.center),
+            phi: luminance(self.point.intensity) * solid_angle,
+            w: self.direction,
+            cos_theta_o: self.cos_inner_angle,
+            cos_theta_e: (theta_outer - theta_inner).max(0.0).cos(),
+            two_sided: false,
+        }
     }
 }
 
@@ -44,8 +338,109 @@ pub struct DirectionalLight {
 }
 
 impl DirectionalLight {
-    fn sample(&self, point: Vec3) -> (Vec3, RangeInclusive<f32>) {
-        (point - self.direction, 0.0f32..=f32::MAX)
+    fn sample(&self, point: Vec3) -> LightSample {
+        LightSample::delta(
+            point,
+            point - self.direction,
+            self.intensity,
+            0.0f32..=f32::MAX,
+        )
+    }
+}
+
+/// Triangle of the scene geometry with an emissive material, only the front side emits light.
+#[derive(Clone, Debug)]
+pub struct TriangleLight {
+    /// Index of the triangle in the scene geometry.
+    pub(crate) index: u32,
+    triangle: Triangle,
+    normals: TriangleNormals,
+    radiance: Vec3,
+}
+
+impl TriangleLight {
+    /// One light for every triangle with an emissive material.
+    pub fn from_mesh(
+        triangles: &[AnyTriangle],
+        properties: &[TriangleProperties],
+        materials: &[Material],
+    ) -> Vec<Self> {
+        triangles
+            .iter()
+            .zip(properties)
+            .enumerate()
+            .filter_map(|(index, (triangle, properties))| {
+                let radiance = materials[properties.material].emittance;
+                (radiance != Vec3::ZERO).then(|| Self {
+                    index: index as u32,
+                    triangle: Triangle::from(triangle.as_arrays()),
+                    normals: properties.normals.clone(),
+                    radiance,
+                })
+            })
+            .collect()
+    }
+
+    #[inline]
+    fn area(&self) -> f32 {
+        0.5 * self.triangle.base0().cross(self.triangle.base1()).length()
+    }
+
+    #[inline]
+    fn area_to_solid_angle_pdf(&self, direction: Vec3) -> f32 {
+        let normal = self.triangle.base0().cross(self.triangle.base1());
+        let area = 0.5 * normal.length();
+        let cos_theta_light = normal.normalize().dot(direction.normalize()).abs();
+        direction.length_squared() / (cos_theta_light * area)
+    }
+
+    fn sample(&self, point: Vec3, sampler: &mut dyn Sampler) -> LightSample {
+        let uv = uniform_sample_triangle(sampler);
+        let target = self.triangle.v0 + uv.x * self.triangle.base0() + uv.y * self.triangle.base1();
+        let shadow_ray = Ray::between(point, target);
+        let t_range = 0.0..=1.0 - SHADOW_RAY_EPSILON;
+        let normal = self.normals.lerp(uv.x, uv.y);
+        if normal.dot(shadow_ray.direction) >= 0.0 {
+            // Only the front side of the triangle emits light.
+            return LightSample {
+                is_delta: false,
+                pdf: 0.0,
+                radiance: Vec3::ZERO,
+                shadow_ray,
+                t_range,
+            };
+        }
+        LightSample {
+            is_delta: false,
+            pdf: self.area_to_solid_angle_pdf(shadow_ray.direction),
+            radiance: self.radiance,
+            shadow_ray,
+            t_range,
+        }
+    }
+
+    /// Solid angle PDF of sampling the point on the triangle hit by `ray` at `t`.
+    #[inline]
+    pub(crate) fn pdf(&self, ray: &Ray, t: f32) -> f32 {
+        self.area_to_solid_angle_pdf(ray.direction * t)
+    }
+
+    fn bounds(&self) -> LightBounds {
+        let [n0, n1, n2] = [self.normals.n0, self.normals.n1, self.normals.n2];
+        let w = (n0 + n1 + n2).try_normalize().unwrap_or_else(|| {
+            self.triangle
+                .base0()
+                .cross(self.triangle.base1())
+                .normalize()
+        });
+        LightBounds {
+            aabb: Aabb::from_extents(self.triangle.min(), self.triangle.max()),
+            phi: PI * luminance(self.radiance) * self.area(),
+            w,
+            cos_theta_o: n0.dot(w).min(n1.dot(w)).min(n2.dot(w)).clamp(-1.0, 1.0),
+            cos_theta_e: 0.0,
+            two_sided: false,
+        }
     }
 }
 
@@ -53,38 +448,90 @@ impl DirectionalLight {
 pub enum Light {
     PointLight(PointLight),
     SphericalLight(SphericalLight),
+    QuadLight(QuadLight),
+    SpotLight(SpotLight),
     DirectionalLight(DirectionalLight),
+    TriangleLight(TriangleLight),
 }
 
 impl Light {
+    pub fn sample(&self, point: Vec3, sampler: &mut dyn Sampler) -> LightSample {
+        match self {
+            Self::PointLight(light) => light.sample(point),
+            Self::SphericalLight(light) => light.sample(point, sampler),
+            Self::QuadLight(light) => light.sample(point, sampler),
+            Self::SpotLight(light) => light.sample(point),
+            Self::DirectionalLight(light) => light.sample(point),
+            Self::TriangleLight(light) => light.sample(point, sampler),
+        }
+    }
+
+    /// Intersect a ray with the light surface, delta lights can never be hit. Light surfaces are
+    /// opaque, including the sides that do not emit.
+    ///
+    /// Triangle lights are part of the scene geometry and are intersected through it instead.
     #[inline]
-    pub fn emitted(&self, point: &Vec3) -> Vec3 {
+    pub fn intersect(&self, ray: &Ray) -> Option<LightIntersection> {
         match self {
-            Self::PointLight(light) => light.emitted(point),
-            Self::SphericalLight(light) => light.point.emitted(point),
-            Self::DirectionalLight(light) => light.intensity,
+            Self::PointLight(_)
+            | Self::SpotLight(_)
+            | Self::DirectionalLight(_)
+            | Self::TriangleLight(_) => None,
+            Self::SphericalLight(light) => light.intersect(ray),
+            Self::QuadLight(light) => light.intersect(ray),
         }
     }
 
-    pub fn sample_shadow_ray(&self, point: Vec3, rng: &mut SmallRng) -> (Ray, RangeInclusive<f32>) {
-        let (target, t_range) = match self {
-            Self::PointLight(light) => light.sample(),
-            Self::SphericalLight(light) => light.sample(rng),
-            Self::DirectionalLight(light) => light.sample(point),
-        };
-        (Ray::between(point, target), t_range)
+    /// Bounds of the light and its emission used to select among lights, `None` for lights
+    /// infinitely far away.
+    pub(crate) fn bounds(&self) -> Option<LightBounds> {
+        match self {
+            Self::PointLight(light) => Some(light.bounds()),
+            Self::SphericalLight(light) => Some(light.bounds()),
+            Self::QuadLight(light) => Some(light.bounds()),
+            Self::SpotLight(light) => Some(light.bounds()),
+            Self::DirectionalLight(_) => None,
+            Self::TriangleLight(light) => Some(light.bounds()),
+        }
     }
 }
 
 impl From<&mtl::Light> for Light {
     fn from(value: &mtl::Light) -> Self {
-        Self::SphericalLight(SphericalLight {
-            point: PointLight {
-                center: value.position.into(),
-                intensity: Vec3::from(value.color) * value.intensity,
-            },
-            radius: value.radius,
-        })
+        let point = PointLight {
+            center: value.position.into(),
+            intensity: Vec3::from(value.color) * value.intensity,
+        };
+        match value.kind {
+            mtl::LightKind::Sphere => Self::SphericalLight(SphericalLight {
+                point,
+                radius: value.radius,
+            }),
+            mtl::LightKind::Quad => {
+                let edge1 = Vec3::from(value.edge1);
+                let edge2 = Vec3::from(value.edge2);
+                // A quad without area has no normal to emit along, keep its intensity as a point.
+                if edge1.cross(edge2).try_normalize().is_none() {
+                    return Self::PointLight(point);
+                }
+                Self::QuadLight(QuadLight {
+                    corner: point.center - 0.5 * (edge1 + edge2),
+                    edge1,
+                    edge2,
+                    intensity: point.intensity,
+                    one_sided: value.one_sided,
+                })
+            }
+            mtl::LightKind::Spot => Self::SpotLight(SpotLight {
+                point,
+                // Shine downwards when no direction is given.
+                direction: Vec3::from(value.direction)
+                    .try_normalize()
+                    .unwrap_or(Vec3::NEG_Y),
+                cos_inner_angle: value.inner_angle.to_radians().cos(),
+                cos_outer_angle: value.outer_angle.to_radians().cos(),
+            }),
+        }
     }
 }
 
@@ -100,8 +547,234 @@ impl From<SphericalLight> for Light {
     }
 }
 
+impl From<QuadLight> for Light {
+    fn from(value: QuadLight) -> Self {
+        Self::QuadLight(value)
+    }
+}
+
+impl From<SpotLight> for Light {
+    fn from(value: SpotLight) -> Self {
+        Self::SpotLight(value)
+    }
+}
+
 impl From<DirectionalLight> for Light {
     fn from(value: DirectionalLight) -> Self {
         Self::DirectionalLight(value)
     }
 }
+
+impl From<TriangleLight> for Light {
+    fn from(value: TriangleLight) -> Self {
+        Self::TriangleLight(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+
+    use super::*;
+    use rand::rngs::SmallRng;
+
+    #[test]
+    fn spherical_light_sample_pdf_matches_intersection_pdf() {
+        let light = SphericalLight {
+            point: PointLight {
+                center: Vec3::new(0.0, 2.0, 0.0),
+                intensity: Vec3::ONE,
+            },
+            radius: 0.5,
+        };
+        let point = Vec3::ZERO;
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let sample = light.sample(point, &mut rng);
+            assert!(sample.pdf > 0.0);
+            let intersection = light.intersect(&sample.shadow_ray).unwrap();
+            assert_relative_eq!(intersection.t, 1.0, max_relative = 1e-4);
+            assert_relative_eq!(intersection.pdf, sample.pdf, max_relative = 1e-3);
+        }
+    }
+
+    #[test]
+    fn spherical_light_samples_only_the_visible_cap() {
+        let light = SphericalLight {
+            point: PointLight {
+                center: Vec3::new(0.0, 2.0, 0.0),
+                intensity: Vec3::ONE,
+            },
+            radius: 1.5,
+        };
+        let point = Vec3::new(0.3, 0.0, 0.1);
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let sample = light.sample(point, &mut rng);
+            let target = sample.shadow_ray.param(1.0);
+            let normal = (target - light.point.center) / light.radius;
+            assert_relative_eq!(normal.length(), 1.0, max_relative = 1e-4);
+            assert!(normal.dot(sample.shadow_ray.direction) <= 1e-4);
+        }
+
+        let inside = light.sample(light.point.center, &mut rng);
+        assert_eq!(inside.pdf, 0.0);
+        let from_inside = light
+            .intersect(&Ray::new(light.point.center, Vec3::X))
+            .unwrap();
+        assert_relative_eq!(from_inside.t, light.radius);
+        assert_eq!(from_inside.radiance, Vec3::ZERO);
+    }
+
+    #[test]
+    fn spherical_light_far_away_irradiance_matches_point_light() {
+        let light = SphericalLight {
+            point: PointLight {
+                center: Vec3::new(0.0, 100.0, 0.0),
+                intensity: Vec3::ONE,
+            },
+            radius: 0.1,
+        };
+        let point = Vec3::ZERO;
+        let mut rng = SmallRng::seed_from_u64(1);
+        let samples = 10000;
+        let irradiance = (0..samples)
+            .map(|_| {
+                let sample = light.sample(point, &mut rng);
+                let cos_theta = sample.shadow_ray.direction.normalize().y;
+                sample.radiance * cos_theta / sample.pdf
+            })
+            .sum::<Vec3>()
+            / samples as f32;
+
+        assert_relative_eq!(irradiance, light.point.emitted(point), max_relative = 1e-2);
+    }
+
+    fn quad_light(one_sided: bool) -> QuadLight {
+        QuadLight {
+            corner: Vec3::new(-0.5, 2.0, -0.25),
+            edge1: Vec3::new(1.0, 0.0, 0.0),
+            edge2: Vec3::new(0.0, 0.0, 0.5),
+            intensity: Vec3::ONE,
+            one_sided,
+        }
+    }
+
+    #[test]
+    fn quad_light_sample_pdf_matches_intersection_pdf() {
+        let light = quad_light(false);
+        let mut rng = SmallRng::seed_from_u64(1);
+        for point in [Vec3::new(0.3, 0.0, 0.1), Vec3::new(0.3, 4.0, 0.1)] {
+            for _ in 0..100 {
+                let sample = light.sample(point, &mut rng);
+                assert!(sample.pdf > 0.0);
+                let intersection = light.intersect(&sample.shadow_ray).unwrap();
+                assert_relative_eq!(intersection.t, 1.0, max_relative = 1e-4);
+                assert_relative_eq!(intersection.pdf, sample.pdf, max_relative = 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn one_sided_quad_light_only_emits_along_its_normal() {
+        // The normal edge1 x edge2 points down.
+        let light = quad_light(true);
+        let mut rng = SmallRng::seed_from_u64(1);
+        let below = light.sample(Vec3::ZERO, &mut rng);
+        assert!(below.pdf > 0.0);
+        assert!(light.intersect(&below.shadow_ray).is_some());
+
+        let above = light.sample(Vec3::new(0.0, 4.0, 0.0), &mut rng);
+        assert_eq!(above.pdf, 0.0);
+        assert_eq!(above.radiance, Vec3::ZERO);
+        let back = light.intersect(&above.shadow_ray).unwrap();
+        assert_eq!((back.radiance, back.pdf), (Vec3::ZERO, 0.0));
+    }
+
+    #[test]
+    fn quad_light_far_away_irradiance_matches_point_light() {
+        let light = QuadLight {
+            corner: Vec3::new(-0.05, 100.0, -0.05),
+            ..quad_light(true)
+        };
+        let light = QuadLight {
+            edge1: light.edge1 * 0.1,
+            edge2: light.edge2 * 0.2,
+            ..light
+        };
+        let point = Vec3::ZERO;
+        let mut rng = SmallRng::seed_from_u64(1);
+        let samples = 1000;
+        let irradiance = (0..samples)
+            .map(|_| {
+                let sample = light.sample(point, &mut rng);
+                let cos_theta = sample.shadow_ray.direction.normalize().y;
+                sample.radiance * cos_theta / sample.pdf
+            })
+            .sum::<Vec3>()
+            / samples as f32;
+
+        assert_relative_eq!(irradiance, Vec3::splat(1.0e-4), max_relative = 1e-2);
+    }
+
+    #[test]
+    fn spot_light_falls_off_between_inner_and_outer_angle() {
+        let light = SpotLight {
+            point: PointLight {
+                center: Vec3::new(0.0, 1.0, 0.0),
+                intensity: Vec3::ONE,
+            },
+            direction: -Vec3::Y,
+            cos_inner_angle: 20.0f32.to_radians().cos(),
+            cos_outer_angle: 30.0f32.to_radians().cos(),
+        };
+        let at_angle = |degrees: f32| {
+            let point = Vec3::new(degrees.to_radians().tan(), 0.0, 0.0);
+            let sample = light.sample(point);
+            assert!(sample.is_delta);
+            sample.radiance.x * (point - light.point.center).length_squared()
+        };
+        assert_relative_eq!(at_angle(0.0), 1.0);
+        assert_relative_eq!(at_angle(19.0), 1.0);
+        assert!(at_angle(22.0) < 1.0 && at_angle(22.0) > at_angle(28.0));
+        assert!(at_angle(28.0) > 0.0);
+        assert_eq!(at_angle(31.0), 0.0);
+    }
+
+    #[test]
+    fn quad_light_without_edges_is_a_point_light() {
+        let light = Light::from(&mtl::Light {
+            kind: mtl::LightKind::Quad,
+            position: [0.0, 2.0, 0.0],
+            color: [1.0; 3],
+            intensity: 4.0,
+            ..Default::default()
+        });
+        let Light::PointLight(point) = &light else {
+            panic!("expected a point light, got {light:?}");
+        };
+        assert_eq!(point.center, Vec3::new(0.0, 2.0, 0.0));
+        let sample = light.sample(Vec3::ZERO, &mut SmallRng::seed_from_u64(1));
+        assert_relative_eq!(sample.radiance, Vec3::ONE);
+    }
+
+    #[test]
+    fn spot_light_without_direction_shines_down() {
+        let light = Light::from(&mtl::Light {
+            kind: mtl::LightKind::Spot,
+            position: [0.0, 1.0, 0.0],
+            color: [1.0; 3],
+            intensity: 1.0,
+            inner_angle: 20.0,
+            outer_angle: 30.0,
+            ..Default::default()
+        });
+        let mut rng = SmallRng::seed_from_u64(1);
+        assert_relative_eq!(light.sample(Vec3::ZERO, &mut rng).radiance, Vec3::ONE);
+        assert_eq!(
+            light.sample(Vec3::new(0.0, 2.0, 0.0), &mut rng).radiance,
+            Vec3::ZERO
+        );
+    }
+}
@@ -0,0 +1,689 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use geometry::{aabb::Aabb, bound::combine_bounding_boxes, ray::Ray};
+use glam::{Quat, Vec3};
+
+use crate::{light::Light, sampler::Sampler, sampling::Distribution1D};
+
+/// How the single light sampled at each vertex is selected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LightSelection {
+    Uniform,
+    /// Proportionally to the emitted power of the lights.
+    #[default]
+    Power,
+    /// By the estimated contribution at the shaded point from a hierarchy of light bounds.
+    Tree,
+}
+
+/// Point and surface normal that a light is selected for.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadingPoint {
+    pub point: Vec3,
+    pub normal: Vec3,
+}
+
+/// Spatial and directional bounds of the emission of one or more lights.
+#[derive(Clone, Debug)]
+pub struct LightBounds {
+    pub aabb: Aabb,
+    /// Emitted power as luminance.
+    pub phi: f32,
+    /// Principal direction of emission.
+    pub w: Vec3,
+    /// Cosine of the spread of the surface normals around `w`.
+    pub cos_theta_o: f32,
+    /// Cosine of the spread of emission around the surface normals.
+    pub cos_theta_e: f32,
+    pub two_sided: bool,
+}
+
+/// `cos(max(0, a - b))` from the sines and cosines of the angles.
+#[inline]
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+/// `sin(max(0, a - b))` from the sines and cosines of the angles.
+#[inline]
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+#[inline]
+fn sin_from_cos(cos: f32) -> f32 {
+    (1.0 - cos * cos).max(0.0).sqrt()
+}
+
+impl LightBounds {
+    /// Conservative estimate of the contribution to `shading_point`, following PBRT-v4.
+    pub fn importance(&self, shading_point: ShadingPoint) -> f32 {
+        if self.phi <= 0.0 {
+            return 0.0;
+        }
+        let ShadingPoint { point, normal } = shading_point;
+        let center = self.aabb.center();
+        let radius = self.aabb.half_size().length();
+        let Some(to_point) = (point - center).try_normalize() else {
+            // Every direction is possible from the center of the bounds.
+            return self.phi;
+        };
+        let center_distance_squared = point.distance_squared(center);
+        // Clamp the distance to avoid the importance blowing up close to the light.
+        let distance_squared = center_distance_squared.max(radius);
+
+        let cos_theta_w = self.w.dot(to_point);
+        let cos_theta_w = if self.two_sided {
+            cos_theta_w.abs()
+        } else {
+            cos_theta_w
+        };
+        let sin_theta_w = sin_from_cos(cos_theta_w);
+
+        // Angle subtended by the bounds as seen from the point.
+        let cos_theta_b = if self.aabb_contains(point) || center_distance_squared <= radius * radius
+        {
+            -1.0
+        } else {
+            (1.0 - radius * radius / center_distance_squared).sqrt()
+        };
+        let sin_theta_b = sin_from_cos(cos_theta_b);
+
+        // Minimum angle between the emission and the direction to the point.
+        let sin_theta_o = sin_from_cos(self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.phi * cos_theta_p / distance_squared;
+        if normal != Vec3::ZERO {
+            let cos_theta_i = normal.dot(-to_point).abs();
+            let sin_theta_i = sin_from_cos(cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+        importance.max(0.0)
+    }
+
+    fn aabb_contains(&self, point: Vec3) -> bool {
+        point.cmpge(*self.aabb.min()).all() && point.cmple(*self.aabb.max()).all()
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        let (w, cos_theta_o) =
+            union_cones((self.w, self.cos_theta_o), (other.w, other.cos_theta_o));
+        Self {
+            aabb: combine_bounding_boxes(&self.aabb, &other.aabb),
+            phi: self.phi + other.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+}
+
+/// Smallest cone of directions containing the cones `a` and `b`, given by axis and cosine of the
+/// half angle.
+fn union_cones(a: (Vec3, f32), b: (Vec3, f32)) -> (Vec3, f32) {
+    let theta_a = a.1.clamp(-1.0, 1.0).acos();
+    let theta_b = b.1.clamp(-1.0, 1.0).acos();
+    let theta_d = a.0.angle_between(b.0);
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    let entire_sphere = (a.0, -1.0);
+    if theta_o >= PI {
+        return entire_sphere;
+    }
+    let Some(axis) = a.0.cross(b.0).try_normalize() else {
+        return entire_sphere;
+    };
+    let w = Quat::from_axis_angle(axis, theta_o - theta_a) * a.0;
+    (w, theta_o.cos())
+}
+
+#[derive(Clone, Debug)]
+enum LightNode {
+    Leaf {
+        bounds: LightBounds,
+        light: usize,
+    },
+    /// The first child directly follows the node.
+    Interior {
+        bounds: LightBounds,
+        second_child: usize,
+    },
+}
+
+impl LightNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            Self::Leaf { bounds, .. } | Self::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Binary tree of light bounds, built by median splits of the light centers along the widest
+/// axis.
+#[derive(Clone, Debug)]
+struct LightTree {
+    nodes: Vec<LightNode>,
+    /// Path from the root to the leaf of each light, bit `i` is set when the second child is
+    /// taken at depth `i`.
+    trails: HashMap<usize, u64>,
+}
+
+impl LightTree {
+    fn new(mut lights: Vec<(usize, LightBounds)>) -> Self {
+        let mut tree = Self {
+            nodes: Vec::new(),
+            trails: HashMap::new(),
+        };
+        if !lights.is_empty() {
+            tree.build(&mut lights, 0, 0);
+        }
+        tree
+    }
+
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        trail: u64,
+        depth: u32,
+    ) -> LightBounds {
+        if let [(light, bounds)] = lights {
+            self.trails.insert(*light, trail);
+            self.nodes.push(LightNode::Leaf {
+                bounds: bounds.clone(),
+                light: *light,
+            });
+            return bounds.clone();
+        }
+
+        let (min, max) = lights.iter().fold(
+            (Vec3::INFINITY, Vec3::NEG_INFINITY),
+            |(min, max), (_, bounds)| {
+                let center = bounds.aabb.center();
+                (min.min(center), max.max(center))
+            },
+        );
+        let axis = (max - min).max_position();
+        let middle = lights.len() / 2;
+        lights.select_nth_unstable_by(middle, |(_, a), (_, b)| {
+            a.aabb.center()[axis].total_cmp(&b.aabb.center()[axis])
+        });
+
+        let node = self.nodes.len();
+        self.nodes.push(LightNode::Interior {
+            bounds: lights[0].1.clone(),
+            second_child: 0,
+        });
+        let (first, second) = lights.split_at_mut(middle);
+        let first_bounds = self.build(first, trail, depth + 1);
+        let second_child = self.nodes.len();
+        let second_bounds = self.build(second, trail | (1 << depth), depth + 1);
+        let bounds = first_bounds.union(&second_bounds);
+        self.nodes[node] = LightNode::Interior {
+            bounds: bounds.clone(),
+            second_child,
+        };
+        bounds
+    }
+
+    /// Probability of taking the first child of the interior node at `node`.
+    fn first_child_probability(
+        &self,
+        node: usize,
+        second_child: usize,
+        shading_point: ShadingPoint,
+    ) -> Option<f32> {
+        let first = self.nodes[node + 1].bounds().importance(shading_point);
+        let second = self.nodes[second_child].bounds().importance(shading_point);
+        (first + second > 0.0).then(|| first / (first + second))
+    }
+
+    fn sample(&self, shading_point: ShadingPoint, mut u: f32) -> Option<(usize, f32)> {
+        let mut node = 0;
+        let mut probability = 1.0;
+        loop {
+            match self.nodes.get(node)? {
+                LightNode::Leaf { bounds, light } => {
+                    return (bounds.importance(shading_point) > 0.0)
+                        .then_some((*light, probability));
+                }
+                LightNode::Interior { second_child, .. } => {
+                    let p = self.first_child_probability(node, *second_child, shading_point)?;
+                    if u < p {
+                        node += 1;
+                        u = (u / p).min(1.0 - f32::EPSILON);
+                        probability *= p;
+                    } else {
+                        node = *second_child;
+                        u = ((u - p) / (1.0 - p)).min(1.0 - f32::EPSILON);
+                        probability *= 1.0 - p;
+                    }
+                }
+            }
+        }
+    }
+
+    fn pmf(&self, shading_point: ShadingPoint, light: usize) -> f32 {
+        let Some(mut trail) = self.trails.get(&light).copied() else {
+            return 0.0;
+        };
+        let mut node = 0;
+        let mut probability = 1.0;
+        loop {
+            match &self.nodes[node] {
+                LightNode::Leaf { bounds, .. } => {
+                    return if bounds.importance(shading_point) > 0.0 {
+                        probability
+                    } else {
+                        0.0
+                    };
+                }
+                LightNode::Interior { second_child, .. } => {
+                    let Some(p) = self.first_child_probability(node, *second_child, shading_point)
+                    else {
+                        return 0.0;
+                    };
+                    if trail & 1 == 0 {
+                        node += 1;
+                        probability *= p;
+                    } else {
+                        node = *second_child;
+                        probability *= 1.0 - p;
+                    }
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Selection {
+    Uniform,
+    Power(Distribution1D),
+    Tree(LightTree),
+}
+
+/// The lights of a scene and the strategy for selecting one of them at each vertex.
+///
+/// Lights infinitely far away, such as directional lights, are not selected among but sampled at
+/// every vertex.
+#[derive(Clone, Debug)]
+pub struct LightSampler {
+    lights: Vec<Light>,
+    infinite: Vec<usize>,
+    /// Lights that one is selected from.
+    finite: Vec<usize>,
+    /// Position of each light in `finite`.
+    finite_index: HashMap<usize, usize>,
+    selection: Selection,
+    /// Scene geometry index of each emissive triangle to its light.
+    triangles: HashMap<u32, usize>,
+}
+
+impl LightSampler {
+    pub fn new(lights: Vec<Light>, selection: LightSelection) -> Self {
+        let (finite, infinite): (Vec<_>, Vec<_>) =
+            (0..lights.len()).partition(|i| lights[*i].bounds().is_some());
+        let bounds = || finite.iter().map(|i| (*i, lights[*i].bounds().unwrap()));
+        let selection = match selection {
+            _ if finite.is_empty() => Selection::Uniform,
+            LightSelection::Uniform => Selection::Uniform,
+            LightSelection::Power => {
+                Selection::Power(Distribution1D::new(bounds().map(|(_, b)| b.phi).collect()))
+            }
+            LightSelection::Tree => {
+                // Lights without power are never selected.
+                Selection::Tree(LightTree::new(
+                    bounds().filter(|(_, b)| b.phi > 0.0).collect(),
+                ))
+            }
+        };
+        let finite_index = finite
+            .iter()
+            .enumerate()
+            .map(|(i, light)| (*light, i))
+            .collect();
+        let triangles = lights
+            .iter()
+            .enumerate()
+            .filter_map(|(i, light)| match light {
+                Light::TriangleLight(triangle) => Some((triangle.index, i)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            lights,
+            infinite,
+            finite,
+            finite_index,
+            selection,
+            triangles,
+        }
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Lights that are sampled at every vertex.
+    pub fn infinite(&self) -> impl Iterator<Item = &Light> {
+        self.infinite.iter().map(|i| &self.lights[*i])
+    }
+
+    /// Select one of the finite lights for `shading_point`, returns it with its probability.
+    pub fn sample(
+        &self,
+        shading_point: ShadingPoint,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(&Light, f32)> {
+        if self.finite.is_empty() {
+            return None;
+        }
+        let u = sampler.get_1d();
+        let (light, probability) = match &self.selection {
+            Selection::Uniform => {
+                let i = ((u * self.finite.len() as f32) as usize).min(self.finite.len() - 1);
+                (self.finite[i], 1.0 / self.finite.len() as f32)
+            }
+            Selection::Power(distribution) => {
+                let (i, probability) = distribution.sample_discrete(u);
+                (self.finite[i], probability)
+            }
+            Selection::Tree(tree) => tree.sample(shading_point, u)?,
+        };
+        (probability > 0.0).then(|| (&self.lights[light], probability))
+    }
+
+    /// Probability of [`LightSampler::sample`] selecting light `light` for `shading_point`, one
+    /// for infinite lights.
+    pub fn pmf(&self, shading_point: ShadingPoint, light: usize) -> f32 {
+        let Some(i) = self.finite_index.get(&light) else {
+            return 1.0;
+        };
+        match &self.selection {
+            Selection::Uniform => 1.0 / self.finite.len() as f32,
+            Selection::Power(distribution) => distribution.probability(*i),
+            Selection::Tree(tree) => tree.pmf(shading_point, light),
+        }
+    }
+
+    /// Solid angle PDF of light sampling at `shading_point` producing the scene geometry with
+    /// `index` hit by `ray` at `t`.
+    pub fn geometry_pdf(&self, shading_point: ShadingPoint, index: u32, ray: &Ray, t: f32) -> f32 {
+        let Some(light) = self.triangles.get(&index) else {
+            return 0.0;
+        };
+        let Light::TriangleLight(triangle) = &self.lights[*light] else {
+            unreachable!()
+        };
+        self.pmf(shading_point, *light) * triangle.pdf(ray, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use geometry::{
+        any_triangle::AnyTriangle,
+        geometry::Geometry,
+        triangle::{Triangle, TriangleNormals, TriangleTexcoords},
+    };
+    use glam::Vec2;
+    use rand::{SeedableRng, rngs::SmallRng};
+
+    use super::*;
+    use crate::{
+        light::{DirectionalLight, PointLight, SphericalLight, TriangleLight},
+        material::{Material, albedo::AlbedoSource},
+        properties::TriangleProperties,
+    };
+
+    fn point_light(center: Vec3, intensity: f32) -> Light {
+        PointLight {
+            center,
+            intensity: Vec3::splat(intensity),
+        }
+        .into()
+    }
+
+    fn scene_lights() -> Vec<Light> {
+        vec![
+            point_light(Vec3::new(-4.0, 1.0, 0.0), 1.0),
+            DirectionalLight {
+                direction: -Vec3::Y,
+                intensity: Vec3::ONE,
+            }
+            .into(),
+            point_light(Vec3::new(4.0, 1.0, 0.0), 3.0),
+            SphericalLight {
+                point: PointLight {
+                    center: Vec3::new(0.0, 3.0, 2.0),
+                    intensity: Vec3::splat(2.0),
+                },
+                radius: 0.5,
+            }
+            .into(),
+            point_light(Vec3::new(0.0, 1.0, -4.0), 0.5),
+        ]
+    }
+
+    const SELECTIONS: [LightSelection; 3] = [
+        LightSelection::Uniform,
+        LightSelection::Power,
+        LightSelection::Tree,
+    ];
+
+    const SHADING_POINT: ShadingPoint = ShadingPoint {
+        point: Vec3::new(1.0, 0.0, 0.5),
+        normal: Vec3::Y,
+    };
+
+    #[test]
+    fn finite_light_pmf_sums_to_one() {
+        for selection in SELECTIONS {
+            let sampler = LightSampler::new(scene_lights(), selection);
+            let total: f32 = [0, 2, 3, 4]
+                .into_iter()
+                .map(|i| sampler.pmf(SHADING_POINT, i))
+                .sum();
+            assert_relative_eq!(total, 1.0, max_relative = 1e-5);
+            assert_eq!(sampler.pmf(SHADING_POINT, 1), 1.0);
+            assert_eq!(sampler.infinite().count(), 1);
+        }
+    }
+
+    #[test]
+    fn sample_probability_matches_pmf() {
+        for selection in SELECTIONS {
+            let sampler = LightSampler::new(scene_lights(), selection);
+            let mut rng = SmallRng::seed_from_u64(1);
+            let mut counts = [0; 5];
+            const N: usize = 100_000;
+            for _ in 0..N {
+                let (light, probability) = sampler.sample(SHADING_POINT, &mut rng).unwrap();
+                let i = sampler
+                    .lights()
+                    .iter()
+                    .position(|l| std::ptr::eq(l, light))
+                    .unwrap();
+                assert_relative_eq!(
+                    probability,
+                    sampler.pmf(SHADING_POINT, i),
+                    max_relative = 1e-5
+                );
+                counts[i] += 1;
+            }
+            assert_eq!(counts[1], 0);
+            for (i, count) in counts.into_iter().enumerate().filter(|(i, _)| *i != 1) {
+                assert_relative_eq!(
+                    count as f32 / N as f32,
+                    sampler.pmf(SHADING_POINT, i),
+                    epsilon = 0.01
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn power_selection_is_proportional_to_power() {
+        let sampler = LightSampler::new(scene_lights(), LightSelection::Power);
+        assert_relative_eq!(
+            sampler.pmf(SHADING_POINT, 2) / sampler.pmf(SHADING_POINT, 0),
+            3.0,
+            max_relative = 1e-4
+        );
+        assert_relative_eq!(
+            sampler.pmf(SHADING_POINT, 4) / sampler.pmf(SHADING_POINT, 0),
+            0.5,
+            max_relative = 1e-4
+        );
+    }
+
+    #[test]
+    fn tree_selection_prefers_nearby_lights() {
+        let lights = vec![
+            point_light(Vec3::new(-10.0, 1.0, 0.0), 1.0),
+            point_light(Vec3::new(10.0, 1.0, 0.0), 1.0),
+        ];
+        let sampler = LightSampler::new(lights, LightSelection::Tree);
+        let near_first = ShadingPoint {
+            point: Vec3::new(-9.0, 0.0, 0.0),
+            normal: Vec3::Y,
+        };
+        assert!(sampler.pmf(near_first, 0) > 0.9);
+        let near_second = ShadingPoint {
+            point: Vec3::new(9.0, 0.0, 0.0),
+            normal: Vec3::Y,
+        };
+        assert!(sampler.pmf(near_second, 1) > 0.9);
+    }
+
+    #[test]
+    fn tree_selection_skips_lights_behind_one_sided_emitters() {
+        let triangles = [Triangle {
+            v0: Vec3::new(0.0, 2.0, 0.0),
+            v1: Vec3::new(1.0, 2.0, 0.0),
+            v2: Vec3::new(0.0, 2.0, 1.0),
+        }]
+        .map(AnyTriangle::from);
+        let properties = [TriangleProperties {
+            material: 0,
+            object: 0,
+            normals: TriangleNormals {
+                n0: -Vec3::Y,
+                n1: -Vec3::Y,
+                n2: -Vec3::Y,
+            },
+            texcoords: TriangleTexcoords {
+                uv0: Vec2::ZERO,
+                uv1: Vec2::ZERO,
+                uv2: Vec2::ZERO,
+            },
+        }];
+        let materials = [emissive(Vec3::ONE)];
+        let mut lights: Vec<Light> = TriangleLight::from_mesh(&triangles, &properties, &materials)
+            .into_iter()
+            .map(Light::from)
+            .collect();
+        lights.push(point_light(Vec3::new(0.0, 10.0, 0.0), 1.0));
+        let sampler = LightSampler::new(lights, LightSelection::Tree);
+        let above = ShadingPoint {
+            point: Vec3::new(0.2, 5.0, 0.2),
+            normal: Vec3::Y,
+        };
+        assert_eq!(sampler.pmf(above, 0), 0.0);
+        assert_eq!(sampler.pmf(above, 1), 1.0);
+    }
+
+    fn emissive(emittance: Vec3) -> Material {
+        Material {
+            albedo: AlbedoSource::ZERO,
+            schlick_f0: Vec3::ZERO,
+            transmission: 0.0,
+            ior: 1.0,
+            emittance,
+            roughness: 0.0,
+        }
+    }
+
+    #[test]
+    fn triangle_light_sample_pdf_matches_geometry_pdf() {
+        let triangles = [
+            Triangle {
+                v0: Vec3::new(-2.0, 1.0, 0.0),
+                v1: Vec3::new(-2.0, 1.0, 1.0),
+                v2: Vec3::new(-1.0, 1.0, 0.0),
+            },
+            Triangle {
+                v0: Vec3::new(0.0, 2.0, 0.0),
+                v1: Vec3::new(0.0, 2.0, 2.0),
+                v2: Vec3::new(2.0, 2.0, 0.0),
+            },
+        ]
+        .map(AnyTriangle::from);
+        let properties = [0, 1].map(|material| TriangleProperties {
+            material,
+            object: 0,
+            normals: TriangleNormals {
+                n0: -Vec3::Y,
+                n1: -Vec3::Y,
+                n2: -Vec3::Y,
+            },
+            texcoords: TriangleTexcoords {
+                uv0: Vec2::ZERO,
+                uv1: Vec2::ZERO,
+                uv2: Vec2::ZERO,
+            },
+        });
+        let materials = [emissive(Vec3::ONE), emissive(Vec3::splat(2.0))];
+        let lights: Vec<Light> = TriangleLight::from_mesh(&triangles, &properties, &materials)
+            .into_iter()
+            .map(Light::from)
+            .collect();
+        let shading_point = ShadingPoint {
+            point: Vec3::new(0.1, 0.0, 0.1),
+            normal: Vec3::ZERO,
+        };
+        for selection in SELECTIONS {
+            let sampler = LightSampler::new(lights.clone(), selection);
+            let mut rng = SmallRng::seed_from_u64(1);
+            for _ in 0..100 {
+                let (light, probability) = sampler.sample(shading_point, &mut rng).unwrap();
+                let sample = light.sample(shading_point.point, &mut rng);
+                let (index, t) = triangles
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, triangle)| {
+                        let intersection = triangle.intersect_ray(&sample.shadow_ray)?;
+                        Some((i as u32, intersection.t))
+                    })
+                    .unwrap();
+                assert_relative_eq!(
+                    sampler.geometry_pdf(shading_point, index, &sample.shadow_ray, t),
+                    sample.pdf * probability,
+                    max_relative = 1e-3
+                );
+            }
+        }
+    }
+}
@@ -2,14 +2,26 @@ use std::{f32::consts::FRAC_1_PI, path::Path};
 
 use glam::{Vec2, Vec3};
 use image::{ConvertColorOptions, metadata::Cicp};
-use rand::{RngExt, rngs::SmallRng};
 use wavefront::mtl;
 
-use crate::{material::albedo::AlbedoSource, sampling::cosine_sample_hemisphere};
+use crate::{
+    material::{
+        albedo::AlbedoSource,
+        microfacet::{Ggx, ShadingFrame},
+    },
+    sampler::Sampler,
+    sampling::{cosine_sample_hemisphere, uniform_sample_unit_square},
+};
 
 pub mod albedo;
+mod microfacet;
+#[cfg(test)]
+mod statistical_tests;
+
+/// Materials with a smaller GGX alpha than this are rendered with the delta lobes.
+const MIN_ALPHA: f32 = 1.0e-3;
 
-fn luminance(c: Vec3) -> f32 {
+pub(crate) fn luminance(c: Vec3) -> f32 {
     // Rec.709 / sRGB linear luminance
     0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
 }
@@ -32,11 +44,22 @@ fn perpendicular(v: Vec3) -> Vec3 {
 }
 
 fn schlicks_approximation(r0: Vec3, wi: Vec3, n: Vec3) -> Vec3 {
-    let cos_theta = wi.dot(n).max(0.0);
-    let t = (1.0 - cos_theta).powi(5);
+    schlick(r0, wi.dot(n).abs())
+}
+
+fn schlick(r0: Vec3, cos_theta: f32) -> Vec3 {
+    let t = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
     r0 + (1.0 - r0) * t
 }
 
+/// Perceptual roughness matching a Blinn-Phong specular exponent, using the common
+/// `alpha = sqrt(2 / (Ns + 2))` mapping.
+fn roughness_from_specular_exponent(exponent: f32) -> f32 {
+    (2.0 / (exponent.max(0.0) + 2.0)).sqrt().sqrt()
+}
+
+/// Local surface properties at an intersection. `wi` is the normalized direction towards where
+/// the ray came from.
 #[derive(Debug)]
 pub struct Surface {
     pub wi: Vec3,
@@ -63,12 +86,130 @@ impl BsdfSample {
     }
 }
 
+struct Lobes {
+    f: Vec3,
+    diffuse: Vec3,
+    refraction: Vec3,
+    p_specular: f32,
+    p_diffuse: f32,
+    p_refraction: f32,
+}
+
 #[derive(Clone, Debug)]
 pub struct Material {
     pub albedo: AlbedoSource,
     pub schlick_f0: Vec3,
     pub transmission: f32,
     pub ior: f32,
+    pub emittance: Vec3,
+    /// Perceptual roughness of the specular and refraction lobes, the GGX alpha is its square.
+    pub roughness: f32,
+}
+
+/// The GGX lobes seen from `wi`, in a shading frame flipped to the side of `wi`.
+struct Glossy {
+    ggx: Ggx,
+    frame: ShadingFrame,
+    wi: Vec3,
+    eta_i: f32,
+    eta_o: f32,
+}
+
+impl Glossy {
+    fn new(ggx: Ggx, surface: &Surface, ior: f32) -> Self {
+        let is_entering = surface.wi.dot(surface.n) >= 0.0;
+        let normal = if is_entering { surface.n } else { -surface.n };
+        let frame = ShadingFrame::new(normal);
+        let wi = frame.to_local(surface.wi);
+        let (eta_i, eta_o) = if is_entering { (1.0, ior) } else { (ior, 1.0) };
+        Self {
+            ggx,
+            frame,
+            wi,
+            eta_i,
+            eta_o,
+        }
+    }
+
+    fn is_total_internal_reflection(&self, cos_theta_i: f32) -> bool {
+        let eta = self.eta_i / self.eta_o;
+        eta * eta * (1.0 - cos_theta_i * cos_theta_i) >= 1.0
+    }
+
+    /// Reflected directions below the macro surface are rejected, they are not part of the PDF.
+    fn sample_reflection(&self, u: Vec2) -> Option<Vec3> {
+        let h = self.ggx.sample_visible_normal(self.wi, u);
+        let wo = reflect(-self.wi, h);
+        (wo.z > 0.0).then_some(wo)
+    }
+
+    /// Refracted directions above and internally reflected directions below the macro surface
+    /// are rejected, they are not part of the PDF.
+    fn sample_refraction(&self, u: Vec2) -> Option<Vec3> {
+        let h = self.ggx.sample_visible_normal(self.wi, u);
+        let cos_theta_i = self.wi.dot(h);
+        if self.is_total_internal_reflection(cos_theta_i) {
+            let wo = reflect(-self.wi, h);
+            return (wo.z > 0.0).then_some(wo);
+        }
+        let eta = self.eta_i / self.eta_o;
+        let cos_theta_t = (1.0 - eta * eta * (1.0 - cos_theta_i * cos_theta_i)).sqrt();
+        let wo = -eta * self.wi + (eta * cos_theta_i - cos_theta_t) * h;
+        (wo.z < 0.0).then_some(wo)
+    }
+
+    /// Returns the BSDF and the mixture PDF of the GGX lobes for the local direction `wo`.
+    fn eval(&self, material: &Material, lobes: &Lobes, albedo: Vec3, wo: Vec3) -> (Vec3, f32) {
+        let wi = self.wi;
+        if wi.z <= 0.0 || wo.z == 0.0 {
+            return (Vec3::ZERO, 0.0);
+        }
+        if wo.z > 0.0 {
+            let h = (wi + wo).normalize();
+            let cos_theta_h = wi.dot(h);
+            if cos_theta_h <= 0.0 {
+                return (Vec3::ZERO, 0.0);
+            }
+            let f = schlick(material.schlick_f0, cos_theta_h);
+            let is_tir =
+                material.transmission > 0.0 && self.is_total_internal_reflection(cos_theta_h);
+            // Light that can not leave through the micro facet is reflected instead.
+            let (color, probability) = if is_tir {
+                (
+                    f + material.transmission * (1.0 - f) * albedo,
+                    lobes.p_specular + lobes.p_refraction,
+                )
+            } else {
+                (f, lobes.p_specular)
+            };
+            let d = self.ggx.d(h);
+            let bsdf = color * (d * self.ggx.g2(wi, wo) / (4.0 * wi.z * wo.z));
+            let pdf = probability * self.ggx.visible_normal_pdf(wi, h) / (4.0 * cos_theta_h);
+            (bsdf, pdf)
+        } else {
+            if material.transmission <= 0.0 {
+                return (Vec3::ZERO, 0.0);
+            }
+            let mut h = (self.eta_i * wi + self.eta_o * wo).normalize();
+            if h.z < 0.0 {
+                h = -h;
+            }
+            let cos_theta_i = wi.dot(h);
+            let cos_theta_o = wo.dot(h);
+            if cos_theta_i <= 0.0 || cos_theta_o >= 0.0 {
+                return (Vec3::ZERO, 0.0);
+            }
+            let f = schlick(material.schlick_f0, cos_theta_i);
+            let eta_scale = (self.eta_i / self.eta_o).powi(2);
+            let denominator = self.eta_i * cos_theta_i + self.eta_o * cos_theta_o;
+            let jacobian = self.eta_o * self.eta_o * -cos_theta_o / (denominator * denominator);
+            let color = material.transmission * (1.0 - f) * albedo * eta_scale;
+            let d = self.ggx.d(h);
+            let bsdf = color * (d * self.ggx.g2(wi, wo) * cos_theta_i * jacobian / (wi.z * -wo.z));
+            let pdf = lobes.p_refraction * self.ggx.visible_normal_pdf(wi, h) * jacobian;
+            (bsdf, pdf)
+        }
+    }
 }
 
 fn sample_specular(surface: &Surface, color: Vec3, probability: f32) -> BsdfSample {
@@ -88,21 +229,21 @@ fn sample_refraction(
     transmitted_diffuse: Vec3,
     probability: f32,
 ) -> BsdfSample {
-    let is_entering = surface.wi.dot(surface.n) < 0.0;
+    let is_entering = surface.wi.dot(surface.n) > 0.0;
     let n1 = if is_entering { 1.0 } else { ior };
     let n2 = if is_entering { ior } else { 1.0 };
     let eta = n1 / n2;
     let normal = if is_entering { surface.n } else { -surface.n };
-    let incoming = -surface.wi;
-    let cos_theta_i = incoming.dot(normal);
+    let cos_theta_i = surface.wi.dot(normal);
     let sin2_theta_t = eta * eta * (1.0 - cos_theta_i * cos_theta_i);
     if sin2_theta_t >= 1.0 {
         // Fallback to reflection if refraction is impossible
         return sample_specular(surface, diffuse, probability);
     }
     let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
-    let wo = (eta * incoming + (eta * cos_theta_i - cos_theta_t) * normal).normalize();
-    let eta_scale = (n2 * n2) / (n1 * n1);
+    let wo = (-eta * surface.wi + (eta * cos_theta_i - cos_theta_t) * normal).normalize();
+    // Radiance is compressed when entering the denser medium as seen from the camera side.
+    let eta_scale = (n1 * n1) / (n2 * n2);
     BsdfSample {
         is_delta: true,
         pdf: probability,
@@ -113,14 +254,14 @@ fn sample_refraction(
 
 fn sample_diffuse(
     surface: &Surface,
-    rng: &mut SmallRng,
+    sampler: &mut dyn Sampler,
     transmitted_diffuse: Vec3,
     probability: f32,
 ) -> BsdfSample {
     let tangent = perpendicular(surface.n).normalize();
     let bitangent = surface.n.cross(tangent);
 
-    let hemisphere_sample = cosine_sample_hemisphere(rng);
+    let hemisphere_sample = cosine_sample_hemisphere(sampler);
 
     let wo = (hemisphere_sample.x * tangent
         + hemisphere_sample.y * bitangent
@@ -156,50 +297,154 @@ impl Material {
             .lerp(material.specular_reflection.into(), material.metalness);
         let transmission = material.transparency;
         let ior = material.index_of_refraction;
+        let emittance = material.emittance.into();
+        let roughness = material.specular_roughness.unwrap_or_else(|| {
+            material
+                .specular_exponent
+                .map_or(0.0, roughness_from_specular_exponent)
+        });
         Self {
             albedo,
             schlick_f0,
             transmission,
             ior,
+            emittance,
+            roughness,
         }
     }
 
-    pub fn sample(&self, surface: &Surface, rng: &mut SmallRng) -> BsdfSample {
+    fn ggx(&self) -> Option<Ggx> {
+        let alpha = self.roughness * self.roughness;
+        (alpha >= MIN_ALPHA).then_some(Ggx { alpha })
+    }
+
+    fn lobes(&self, surface: &Surface) -> Option<Lobes> {
         let f = schlicks_approximation(self.schlick_f0, surface.wi, surface.n);
-        let diffuse = self.albedo.get(surface.uv);
-        let transmitted_diffuse = (1.0 - f) * diffuse;
+        let transmitted_diffuse = (1.0 - f) * self.albedo.get(surface.uv);
+        let diffuse = transmitted_diffuse * (1.0 - self.transmission);
+        let refraction = transmitted_diffuse * self.transmission;
         let specular_strength = luminance(f);
-        let diffuse_strength = luminance(transmitted_diffuse) * (1.0 - self.transmission);
-        let refraction_strength = luminance(transmitted_diffuse) * self.transmission;
+        let diffuse_strength = luminance(diffuse);
+        let refraction_strength = luminance(refraction);
         let total_strength = specular_strength + diffuse_strength + refraction_strength;
         if total_strength <= 0.0 {
+            return None;
+        }
+        Some(Lobes {
+            f,
+            diffuse,
+            refraction,
+            p_specular: specular_strength / total_strength,
+            p_diffuse: diffuse_strength / total_strength,
+            p_refraction: refraction_strength / total_strength,
+        })
+    }
+
+    /// Sample an outgoing direction from the lobe mixture.
+    ///
+    /// Delta samples have the lobe selection probability as `pdf` and the lobe weight as `bsdf`.
+    /// Other samples have the full non-delta BSDF and mixture PDF, as given by [`Material::eval`]
+    /// and [`Material::pdf`].
+    pub fn sample(&self, surface: &Surface, sampler: &mut dyn Sampler) -> BsdfSample {
+        let Some(lobes) = self.lobes(surface) else {
+            return BsdfSample::zero(surface.n);
+        };
+        let r = sampler.get_1d();
+        let Some(ggx) = self.ggx() else {
+            if lobes.p_specular > 0.0 && r < lobes.p_specular {
+                return sample_specular(surface, lobes.f, lobes.p_specular);
+            } else if r < lobes.p_specular + lobes.p_refraction {
+                return sample_refraction(
+                    surface,
+                    self.ior,
+                    lobes.refraction,
+                    lobes.refraction,
+                    lobes.p_refraction,
+                );
+            } else if lobes.p_diffuse > 0.0 {
+                return sample_diffuse(surface, sampler, lobes.diffuse, lobes.p_diffuse);
+            }
+            return BsdfSample::zero(surface.n);
+        };
+
+        let glossy = Glossy::new(ggx, surface, self.ior);
+        let wo = if r < lobes.p_specular {
+            glossy
+                .sample_reflection(uniform_sample_unit_square(sampler))
+                .map(|wo| glossy.frame.to_world(wo))
+        } else if r < lobes.p_specular + lobes.p_refraction {
+            glossy
+                .sample_refraction(uniform_sample_unit_square(sampler))
+                .map(|wo| glossy.frame.to_world(wo))
+        } else if lobes.p_diffuse > 0.0 {
+            Some(sample_diffuse(surface, sampler, lobes.diffuse, lobes.p_diffuse).wo)
+        } else {
+            None
+        };
+        let Some(wo) = wo else {
+            return BsdfSample::zero(surface.n);
+        };
+        let wo = wo.normalize();
+        let (bsdf, pdf) = self.eval_with_lobes(surface, &lobes, wo);
+        if pdf == 0.0 {
             return BsdfSample::zero(surface.n);
         }
-        let p_specular = specular_strength / total_strength;
-        let p_diffuse = diffuse_strength / total_strength;
-        let p_refraction = refraction_strength / total_strength;
-        let r = rng.random::<f32>();
-        if p_specular > 0.0 && r < p_specular {
-            return sample_specular(surface, f, p_specular);
-        } else if r < p_specular + p_refraction {
-            return sample_refraction(surface, self.ior, f, transmitted_diffuse, p_refraction);
-        } else if p_diffuse > 0.0 {
-            return sample_diffuse(surface, rng, transmitted_diffuse, p_diffuse);
+        BsdfSample {
+            is_delta: false,
+            pdf,
+            bsdf,
+            wo,
+        }
+    }
+
+    fn eval_with_lobes(&self, surface: &Surface, lobes: &Lobes, wo: Vec3) -> (Vec3, f32) {
+        let cos_theta = wo.dot(surface.n);
+        let (mut bsdf, mut pdf) = if lobes.p_diffuse > 0.0 && cos_theta > 0.0 {
+            (
+                lobes.diffuse * FRAC_1_PI,
+                lobes.p_diffuse * cos_theta * FRAC_1_PI,
+            )
+        } else {
+            (Vec3::ZERO, 0.0)
+        };
+        if let Some(ggx) = self.ggx() {
+            let glossy = Glossy::new(ggx, surface, self.ior);
+            let albedo = self.albedo.get(surface.uv);
+            let (glossy_bsdf, glossy_pdf) =
+                glossy.eval(self, lobes, albedo, glossy.frame.to_local(wo));
+            bsdf += glossy_bsdf;
+            pdf += glossy_pdf;
         }
+        (bsdf, pdf)
+    }
 
-        BsdfSample::zero(surface.n)
+    /// Evaluate the non-delta part of the BSDF for the outgoing direction `wo`.
+    ///
+    /// Delta lobes are excluded since the probability of any given direction hitting them is
+    /// zero.
+    pub fn eval(&self, surface: &Surface, wo: Vec3) -> Vec3 {
+        self.lobes(surface).map_or(Vec3::ZERO, |lobes| {
+            self.eval_with_lobes(surface, &lobes, wo).0
+        })
+    }
+
+    /// Full-mixture PDF of [`Material::sample`] producing the non-delta direction `wo`.
+    pub fn pdf(&self, surface: &Surface, wo: Vec3) -> f32 {
+        self.lobes(surface)
+            .map_or(0.0, |lobes| self.eval_with_lobes(surface, &lobes, wo).1)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use approx::assert_ulps_eq;
+    use approx::{assert_relative_eq, assert_ulps_eq};
     use rand::SeedableRng;
 
     use super::*;
+    use rand::{RngExt, rngs::SmallRng};
 
     #[test]
-    fn sample_refraction_entering() {
+    fn sample_refraction_exiting() {
         let surface = Surface {
             wi: -Vec3::X,
             n: Vec3::X,
@@ -217,7 +462,7 @@ mod tests {
     }
 
     #[test]
-    fn sample_refraction_exiting() {
+    fn sample_refraction_entering() {
         let surface = Surface {
             wi: Vec3::X,
             n: Vec3::X,
@@ -246,6 +491,8 @@ mod tests {
             schlick_f0: Vec3::ZERO,
             transmission: 0.0,
             ior: 1.0,
+            emittance: Vec3::ZERO,
+            roughness: 0.0,
         };
         let mut rng = SmallRng::seed_from_u64(1234);
 
@@ -274,6 +521,8 @@ mod tests {
             schlick_f0: Vec3::new(0.2, 0.4, 0.6),
             transmission: 0.0,
             ior: 1.0,
+            emittance: Vec3::ZERO,
+            roughness: 0.0,
         };
         let mut rng = SmallRng::seed_from_u64(1234);
 
@@ -298,6 +547,8 @@ mod tests {
             schlick_f0: Vec3::ZERO,
             transmission: 0.0,
             ior: 1.0,
+            emittance: Vec3::ZERO,
+            roughness: 0.0,
         };
         let rng = || SmallRng::seed_from_u64(1);
 
@@ -324,6 +575,8 @@ mod tests {
             schlick_f0: Vec3::splat(0.25),
             transmission: 0.0,
             ior: 1.0,
+            emittance: Vec3::ZERO,
+            roughness: 0.0,
         };
         let f = material.schlick_f0;
         let p_specular = 0.5;
@@ -341,6 +594,53 @@ mod tests {
         assert!(cos_theta > 0.0);
     }
 
+    #[test]
+    fn pdf_and_eval_match_diffuse_sample() {
+        let surface = Surface {
+            wi: Vec3::new(0.0, 1.0, 0.0),
+            n: Vec3::new(0.0, 1.0, 0.0),
+            uv: Vec2::ZERO,
+        };
+        let material = Material {
+            albedo: AlbedoSource::Color(Vec3::new(0.2, 0.34604772, 0.6)),
+            schlick_f0: Vec3::splat(0.25),
+            transmission: 0.0,
+            ior: 1.0,
+            emittance: Vec3::ZERO,
+            roughness: 0.0,
+        };
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let sample = material.sample(&surface, &mut rng);
+            if sample.is_delta {
+                continue;
+            }
+            assert_ulps_eq!(material.pdf(&surface, sample.wo), sample.pdf);
+            assert_ulps_eq!(material.eval(&surface, sample.wo), sample.bsdf);
+        }
+    }
+
+    #[test]
+    fn pdf_and_eval_zero_for_specular_only() {
+        let surface = Surface {
+            wi: Vec3::new(0.8, 0.6, 0.0),
+            n: Vec3::new(0.0, 1.0, 0.0),
+            uv: Vec2::ZERO,
+        };
+        let material = Material {
+            albedo: AlbedoSource::ZERO,
+            schlick_f0: Vec3::new(0.2, 0.4, 0.6),
+            transmission: 0.0,
+            ior: 1.0,
+            emittance: Vec3::ZERO,
+            roughness: 0.0,
+        };
+        let wo = Vec3::new(-0.8, 0.6, 0.0);
+
+        assert_eq!(material.pdf(&surface, wo), 0.0);
+        assert_eq!(material.eval(&surface, wo), Vec3::ZERO);
+    }
+
     #[test]
     fn sample_mixed_lobes_specular_branch() {
         let surface = Surface {
@@ -354,6 +654,8 @@ mod tests {
             schlick_f0: Vec3::splat(0.25),
             transmission: 0.0,
             ior: 1.0,
+            emittance: Vec3::ZERO,
+            roughness: 0.0,
         };
         let p_specular = 0.5;
         let seed = (0u64..1024)
@@ -368,4 +670,104 @@ mod tests {
         assert_ulps_eq!(actual.bsdf, material.schlick_f0);
         assert_ulps_eq!(actual.wo, Vec3::new(0.0, 1.0, 0.0));
     }
+
+    #[test]
+    fn sample_refraction_follows_snells_law() {
+        let wi = Vec3::new(-1.0, 1.0, 0.0).normalize();
+        let surface = Surface {
+            wi,
+            n: Vec3::Y,
+            uv: Vec2::ZERO,
+        };
+
+        let sample = sample_refraction(&surface, 1.5, Vec3::ONE, Vec3::ONE, 1.0);
+
+        let sin_theta_i = wi.cross(Vec3::Y).length();
+        let sin_theta_t = sample.wo.cross(Vec3::Y).length();
+        assert!(sample.wo.y < 0.0);
+        assert!(sample.wo.x > 0.0);
+        assert_relative_eq!(sin_theta_i, 1.5 * sin_theta_t, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn fresnel_uses_the_angle_to_either_side_of_the_surface() {
+        let r0 = Vec3::splat(0.04);
+        let wi = Vec3::new(0.6, 0.8, 0.0);
+        let below = Vec3::new(0.6, -0.8, 0.0);
+        assert_eq!(
+            schlicks_approximation(r0, below, Vec3::Y),
+            schlicks_approximation(r0, wi, Vec3::Y)
+        );
+        assert!(schlicks_approximation(r0, below, Vec3::Y).x < 0.1);
+    }
+
+    fn rough_material(transmission: f32) -> Material {
+        Material {
+            albedo: AlbedoSource::Color(Vec3::new(0.8, 0.7, 0.6)),
+            schlick_f0: Vec3::splat(0.04),
+            transmission,
+            ior: 1.5,
+            emittance: Vec3::ZERO,
+            roughness: 0.5,
+        }
+    }
+
+    #[test]
+    fn pdf_and_eval_match_rough_sample() {
+        for (transmission, wi) in [
+            (0.0, Vec3::new(0.6, 0.8, 0.0)),
+            (1.0, Vec3::new(0.6, 0.8, 0.0)),
+            (1.0, Vec3::new(0.6, -0.8, 0.0)),
+        ] {
+            let material = rough_material(transmission);
+            let surface = Surface {
+                wi,
+                n: Vec3::Y,
+                uv: Vec2::ZERO,
+            };
+            let mut rng = SmallRng::seed_from_u64(1);
+            for _ in 0..1000 {
+                let sample = material.sample(&surface, &mut rng);
+                if sample.pdf == 0.0 {
+                    continue;
+                }
+                assert!(!sample.is_delta);
+                assert_relative_eq!(
+                    material.pdf(&surface, sample.wo),
+                    sample.pdf,
+                    max_relative = 1e-4
+                );
+                assert_relative_eq!(
+                    material.eval(&surface, sample.wo),
+                    sample.bsdf,
+                    max_relative = 1e-4
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rough_pdf_integrates_to_at_most_one() {
+        for transmission in [0.0, 1.0] {
+            let material = rough_material(transmission);
+            let surface = Surface {
+                wi: Vec3::new(0.6, 0.8, 0.0),
+                n: Vec3::Y,
+                uv: Vec2::ZERO,
+            };
+            let steps = 256;
+            let d_theta = std::f32::consts::PI / steps as f32;
+            let d_phi = std::f32::consts::TAU / steps as f32;
+            let integral: f32 = (0..steps)
+                .flat_map(|i| (0..steps).map(move |j| (i, j)))
+                .map(|(i, j)| {
+                    let (sin_theta, cos_theta) = ((i as f32 + 0.5) * d_theta).sin_cos();
+                    let (sin_phi, cos_phi) = ((j as f32 + 0.5) * d_phi).sin_cos();
+                    let wo = Vec3::new(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi);
+                    material.pdf(&surface, wo) * sin_theta * d_theta * d_phi
+                })
+                .sum();
+            assert!(integral > 0.8 && integral < 1.01, "{integral}");
+        }
+    }
 }
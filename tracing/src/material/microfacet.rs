@@ -0,0 +1,158 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::{Vec2, Vec3};
+
+/// Orthonormal basis with the shading normal as the z-axis.
+pub struct ShadingFrame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl ShadingFrame {
+    pub fn new(normal: Vec3) -> Self {
+        let tangent = super::perpendicular(normal).normalize();
+        let bitangent = normal.cross(tangent);
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    #[inline]
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            v.dot(self.tangent),
+            v.dot(self.bitangent),
+            v.dot(self.normal),
+        )
+    }
+
+    #[inline]
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x * self.tangent + v.y * self.bitangent + v.z * self.normal
+    }
+}
+
+/// Isotropic GGX (Trowbridge-Reitz) microfacet distribution with the Smith masking function.
+///
+/// All directions are in the local shading frame where the macro surface normal is +z.
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub alpha: f32,
+}
+
+impl Ggx {
+    /// Distribution of micro normals.
+    pub fn d(&self, h: Vec3) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let alpha2 = self.alpha * self.alpha;
+        let denominator = h.z * h.z * (alpha2 - 1.0) + 1.0;
+        alpha2 / (PI * denominator * denominator)
+    }
+
+    fn lambda(&self, w: Vec3) -> f32 {
+        let cos2_theta = w.z * w.z;
+        if cos2_theta == 0.0 {
+            return f32::INFINITY;
+        }
+        let tan2_theta = (1.0 - cos2_theta).max(0.0) / cos2_theta;
+        ((1.0 + self.alpha * self.alpha * tan2_theta).sqrt() - 1.0) * 0.5
+    }
+
+    /// Smith masking for a single direction.
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated Smith masking-shadowing for a pair of directions.
+    pub fn g2(&self, wi: Vec3, wo: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wi) + self.lambda(wo))
+    }
+
+    /// Sample a micro normal from the distribution of normals visible from `wi`, which must be in
+    /// the upper hemisphere (Heitz 2018, "Sampling the GGX Distribution of Visible Normals").
+    pub fn sample_visible_normal(&self, wi: Vec3, u: Vec2) -> Vec3 {
+        let vh = Vec3::new(self.alpha * wi.x, self.alpha * wi.y, wi.z).normalize();
+        let length_squared = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / length_squared.sqrt()
+        } else {
+            Vec3::X
+        };
+        let t2 = vh.cross(t1);
+
+        let r = u.x.sqrt();
+        let (sin_phi, cos_phi) = (TAU * u.y).sin_cos();
+        let p1 = r * cos_phi;
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * sin_phi;
+        let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let nh = p1 * t1 + p2 * t2 + p3 * vh;
+
+        Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(0.0)).normalize()
+    }
+
+    /// PDF of [`Ggx::sample_visible_normal`] producing the micro normal `h`.
+    pub fn visible_normal_pdf(&self, wi: Vec3, h: Vec3) -> f32 {
+        if wi.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wi) * wi.dot(h).max(0.0) * self.d(h) / wi.z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngExt, SeedableRng, rngs::SmallRng};
+
+    fn spherical_direction(theta: f32, phi: f32) -> Vec3 {
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+    }
+
+    #[test]
+    fn projected_micro_normal_area_is_one() {
+        let ggx = Ggx { alpha: 0.3 };
+        let steps = 512;
+        let d_theta = 0.5 * PI / steps as f32;
+        let d_phi = TAU / steps as f32;
+        let integral: f32 = (0..steps)
+            .flat_map(|i| (0..steps).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                let theta = (i as f32 + 0.5) * d_theta;
+                let phi = (j as f32 + 0.5) * d_phi;
+                let h = spherical_direction(theta, phi);
+                ggx.d(h) * h.z * theta.sin() * d_theta * d_phi
+            })
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-2, "{integral}");
+    }
+
+    #[test]
+    fn visible_normals_are_visible() {
+        let ggx = Ggx { alpha: 0.5 };
+        let wi = spherical_direction(1.2, 0.3);
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let u = Vec2::new(rng.random(), rng.random());
+            let h = ggx.sample_visible_normal(wi, u);
+            assert!((h.length() - 1.0).abs() < 1e-5);
+            assert!(h.z >= 0.0);
+            assert!(wi.dot(h) >= -1e-5, "{}", wi.dot(h));
+        }
+    }
+
+    #[test]
+    fn shading_frame_round_trip() {
+        let frame = ShadingFrame::new(Vec3::new(0.3, -0.8, 0.2).normalize());
+        let v = Vec3::new(1.0, 2.0, 3.0);
+        let actual = frame.to_world(frame.to_local(v));
+        assert!((actual - v).length() < 1e-5, "{actual}");
+    }
+}
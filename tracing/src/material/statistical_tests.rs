@@ -0,0 +1,408 @@
+//! Statistical tests of [`Material`]: energy conservation, white furnace renders and chi-square
+//! tests comparing the sampled directions with [`Material::pdf`].
+
+use std::f32::consts::TAU;
+
+use geometry::sphere::Sphere;
+use glam::{UVec2, Vec2, Vec3};
+use rand::{RngExt, SeedableRng, rngs::SmallRng};
+
+use super::*;
+use crate::{
+    camera::{Camera, Pinhole},
+    collections::SphereCollection,
+    environment::{Environment, EnvironmentMap},
+    filter::PixelFilter,
+    image_buffer::ImageBuffer,
+    light_sampler::{LightSampler, LightSelection},
+    pathtracer::Pathtracer,
+    properties::SphereProperties,
+    raylogger::{RayLoggerWithIteration, RayLoggerWriter},
+    sampler::SamplerKind,
+    sampling::{RussianRoulette, uniform_sample_unit_sphere},
+};
+
+fn material(albedo: Vec3, f0: f32, transmission: f32, ior: f32, roughness: f32) -> Material {
+    Material {
+        albedo: AlbedoSource::Color(albedo),
+        schlick_f0: Vec3::splat(f0),
+        transmission,
+        ior,
+        emittance: Vec3::ZERO,
+        roughness,
+    }
+}
+
+fn random_material(rng: &mut SmallRng) -> Material {
+    let albedo = Vec3::new(rng.random(), rng.random(), rng.random());
+    let ior = rng.random_range(1.0..2.5);
+    let transmission = [0.0, 1.0, rng.random()][rng.random_range(0..3)];
+    let roughness = [0.0, rng.random()][rng.random_range(0..2)];
+    Material {
+        albedo: AlbedoSource::Color(albedo),
+        schlick_f0: Vec3::new(rng.random(), rng.random(), rng.random()),
+        ..material(Vec3::ZERO, 0.0, transmission, ior, roughness)
+    }
+}
+
+/// Energy carried by a sample, without the radiance scaling at refractive interfaces.
+fn sample_weight(material: &Material, surface: &Surface, sample: &BsdfSample) -> Vec3 {
+    if sample.pdf == 0.0 {
+        return Vec3::ZERO;
+    }
+    let cos_i = surface.wi.dot(surface.n);
+    let cos_o = sample.wo.dot(surface.n);
+    let weight = if sample.is_delta {
+        sample.bsdf / sample.pdf
+    } else {
+        sample.bsdf * cos_o.abs() / sample.pdf
+    };
+    if cos_i * cos_o < 0.0 {
+        let (eta_i, eta_o) = if cos_i > 0.0 {
+            (1.0, material.ior)
+        } else {
+            (material.ior, 1.0)
+        };
+        weight * (eta_o / eta_i).powi(2)
+    } else {
+        weight
+    }
+}
+
+#[test]
+fn reflectance_is_at_most_one() {
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut sampler = SmallRng::seed_from_u64(2);
+    for _ in 0..100 {
+        let material = random_material(&mut rng);
+        for _ in 0..4 {
+            let mut wi = uniform_sample_unit_sphere(&mut rng);
+            // From inside a partially transmissive material the diffuse lobe transmits without
+            // the radiance scaling of refraction, its energy can not be told apart.
+            if material.transmission > 0.0 && material.transmission < 1.0 {
+                wi.z = wi.z.abs();
+            }
+            let surface = Surface {
+                wi,
+                n: Vec3::Z,
+                uv: Vec2::ZERO,
+            };
+            let count = 4000;
+            let reflectance = (0..count)
+                .map(|_| {
+                    let sample = material.sample(&surface, &mut sampler);
+                    sample_weight(&material, &surface, &sample)
+                })
+                .sum::<Vec3>()
+                / count as f32;
+            assert!(
+                reflectance.max_element() <= 1.02,
+                "{reflectance} for {material:?} from {}",
+                surface.wi
+            );
+        }
+    }
+}
+
+/// Mean radiance of a sphere with `material` inside `environment`.
+fn furnace_in(material: Material, environment: Environment) -> Vec3 {
+    let camera = Camera::new(Vec3::new(-4.0, 0.0, 0.0), Vec3::ZERO, Vec3::Z, 30.0);
+    let pinhole = Pinhole::new(camera, UVec2::new(16, 16));
+    let pathtracer = Pathtracer {
+        max_bounces: 64,
+        russian_roulette: RussianRoulette::default(),
+        filter: PixelFilter::default(),
+        sampler: SamplerKind::Independent,
+        seed: 0,
+        geometry_collection: SphereCollection {
+            spheres: vec![Sphere::new([0.0, 0.0, 0.0], 1.0)],
+            properties: vec![SphereProperties {
+                material: 0,
+                radius: 1.0,
+            }],
+            materials: vec![material],
+        },
+        lights: LightSampler::new(Vec::new(), LightSelection::default()),
+        environment,
+    };
+    let mut buffer = ImageBuffer::new(pinhole.size);
+    let mut ray_logger = RayLoggerWithIteration {
+        writer: &mut RayLoggerWriter::None,
+        iteration: 0,
+    };
+    let mut sampler = pathtracer.sampler.create(pathtracer.seed);
+    for iteration in 0..64 {
+        pathtracer.render_mut(
+            &pinhole,
+            &mut ray_logger,
+            sampler.as_mut(),
+            iteration,
+            &mut buffer,
+        );
+    }
+    buffer.pixels().iter().sum::<Vec3>() / buffer.weights().iter().sum::<f32>()
+}
+
+/// Mean radiance of a sphere with `material` inside a uniform environment of radiance one.
+fn furnace(material: Material) -> Vec3 {
+    furnace_in(material, Vec3::ONE.into())
+}
+
+#[test]
+fn furnace_lossless_materials_are_invisible() {
+    for material in [
+        material(Vec3::ONE, 0.0, 1.0, 1.0, 0.0),
+        material(Vec3::ONE, 0.0, 0.0, 1.0, 0.0),
+        material(Vec3::ONE, 0.04, 0.0, 1.5, 0.0),
+        material(Vec3::ONE, 1.0, 0.0, 1.0, 0.0),
+        material(Vec3::ONE, 0.04, 1.0, 1.5, 0.0),
+        material(Vec3::ONE, 0.1, 1.0, 2.4, 0.0),
+    ] {
+        let radiance = furnace(material.clone());
+        assert!(
+            (radiance - Vec3::ONE).abs().max_element() < 0.02,
+            "{radiance} for {material:?}"
+        );
+    }
+}
+
+#[test]
+fn furnace_uniform_environment_map_matches_constant() {
+    // Light sampling the map and its MIS weights must not change the expected radiance.
+    let size = UVec2::new(32, 16);
+    let map = EnvironmentMap::new(size, vec![Vec3::ONE; (size.x * size.y) as usize], 0.0, 1.0);
+    for material in [
+        material(Vec3::ONE, 0.0, 0.0, 1.0, 0.0),
+        material(Vec3::new(0.8, 0.5, 0.2), 0.04, 0.0, 1.5, 0.4),
+        material(Vec3::ONE, 0.04, 1.0, 1.5, 0.0),
+    ] {
+        let constant = furnace(material.clone());
+        let radiance = furnace_in(material.clone(), map.clone().into());
+        assert!(
+            (radiance - constant).abs().max_element() < 0.02,
+            "{radiance} != {constant} for {material:?}"
+        );
+    }
+}
+
+#[test]
+fn furnace_does_not_create_energy() {
+    let mut rng = SmallRng::seed_from_u64(3);
+    for _ in 0..16 {
+        let material = random_material(&mut rng);
+        let radiance = furnace(material.clone());
+        assert!(
+            radiance.max_element() <= 1.02,
+            "{radiance} for {material:?}"
+        );
+    }
+}
+
+/// Lower incomplete gamma function P(a, x) by series expansion, valid for x < a + 1.
+fn gamma_p_series(a: f64, x: f64) -> f64 {
+    let mut term = 1.0 / a;
+    let mut sum = term;
+    for n in 1..1000 {
+        term *= x / (a + n as f64);
+        sum += term;
+        if term.abs() < sum.abs() * 1.0e-14 {
+            break;
+        }
+    }
+    sum * (-x + a * x.ln() - ln_gamma(a)).exp()
+}
+
+/// Upper incomplete gamma function Q(a, x) by continued fraction, valid for x >= a + 1.
+fn gamma_q_continued_fraction(a: f64, x: f64) -> f64 {
+    let tiny = 1.0e-300;
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / tiny;
+    let mut d = 1.0 / b;
+    let mut h = d;
+    for i in 1..1000 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        d = if d.abs() < tiny { tiny } else { d };
+        c = b + an / c;
+        c = if c.abs() < tiny { tiny } else { c };
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1.0e-14 {
+            break;
+        }
+    }
+    (-x + a * x.ln() - ln_gamma(a)).exp() * h
+}
+
+/// Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series: f64 = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .map(|(i, c)| c / (x + 1.0 + i as f64))
+        .sum();
+    -tmp + (2.506_628_274_631_000_5 * (1.000_000_000_190_015 + series) / x).ln()
+}
+
+/// Probability of a chi-square statistic at least as large as `chi2` under the null hypothesis.
+fn chi_square_p_value(chi2: f64, degrees_of_freedom: usize) -> f64 {
+    let a = degrees_of_freedom as f64 / 2.0;
+    let x = chi2 / 2.0;
+    if x < a + 1.0 {
+        1.0 - gamma_p_series(a, x)
+    } else {
+        gamma_q_continued_fraction(a, x)
+    }
+}
+
+const COS_THETA_BINS: usize = 16;
+const PHI_BINS: usize = 32;
+
+/// Bins of equal solid angle, uniform in cos(theta) around +z and in phi.
+fn bin(wo: Vec3) -> usize {
+    let z = ((wo.z + 1.0) * 0.5 * COS_THETA_BINS as f32) as usize;
+    let phi = wo.y.atan2(wo.x).rem_euclid(TAU);
+    let phi = (phi / TAU * PHI_BINS as f32) as usize;
+    z.min(COS_THETA_BINS - 1) * PHI_BINS + phi.min(PHI_BINS - 1)
+}
+
+/// Expected fraction of samples in each bin, by integrating the PDF over the bin.
+fn integrate_pdf(material: &Material, surface: &Surface) -> Vec<f64> {
+    const STEPS: usize = 16;
+    let d_z = 2.0 / COS_THETA_BINS as f32;
+    let d_phi = TAU / PHI_BINS as f32;
+    (0..COS_THETA_BINS * PHI_BINS)
+        .map(|index| {
+            let (z_bin, phi_bin) = (index / PHI_BINS, index % PHI_BINS);
+            let sum: f64 = (0..STEPS * STEPS)
+                .map(|step| {
+                    let u = ((step / STEPS) as f32 + 0.5) / STEPS as f32;
+                    let v = ((step % STEPS) as f32 + 0.5) / STEPS as f32;
+                    let z = -1.0 + (z_bin as f32 + u) * d_z;
+                    let phi = (phi_bin as f32 + v) * d_phi;
+                    let r = (1.0 - z * z).max(0.0).sqrt();
+                    let wo = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                    material.pdf(surface, wo) as f64
+                })
+                .sum();
+            sum / (STEPS * STEPS) as f64 * (d_z * d_phi) as f64
+        })
+        .collect()
+}
+
+/// Chi-square test of the sampled directions against the integrated PDF. Directions that
+/// [`Material::sample`] fails to produce go in an extra bin for the missing probability mass.
+fn chi_square_test(material: &Material, wi: Vec3) -> f64 {
+    let surface = Surface {
+        wi,
+        n: Vec3::Z,
+        uv: Vec2::ZERO,
+    };
+    let count = 200_000;
+    let mut observed = vec![0.0; COS_THETA_BINS * PHI_BINS + 1];
+    let mut sampler = SmallRng::seed_from_u64(4);
+    for _ in 0..count {
+        let sample = material.sample(&surface, &mut sampler);
+        if sample.pdf == 0.0 {
+            observed[COS_THETA_BINS * PHI_BINS] += 1.0;
+        } else {
+            assert!(!sample.is_delta);
+            observed[bin(sample.wo)] += 1.0;
+        }
+    }
+    let mut expected: Vec<f64> = integrate_pdf(material, &surface)
+        .iter()
+        .map(|p| p * count as f64)
+        .collect();
+    let missing = count as f64 - expected.iter().sum::<f64>();
+    expected.push(missing.max(0.0));
+
+    // Pool the bins with few expected samples, the chi-square distribution does not hold for
+    // them.
+    let mut chi2 = 0.0;
+    let mut degrees_of_freedom = 0;
+    let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+    for (observed, expected) in observed.iter().zip(&expected) {
+        if *expected < 5.0 {
+            pooled_observed += observed;
+            pooled_expected += expected;
+        } else {
+            chi2 += (observed - expected).powi(2) / expected;
+            degrees_of_freedom += 1;
+        }
+    }
+    if pooled_expected >= 5.0 {
+        chi2 += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+        degrees_of_freedom += 1;
+    } else {
+        assert!(
+            pooled_observed < 5.0 + pooled_expected * 2.0,
+            "{pooled_observed} samples in bins expecting {pooled_expected}"
+        );
+    }
+    chi_square_p_value(chi2, degrees_of_freedom - 1)
+}
+
+#[test]
+fn chi_square_p_value_matches_table() {
+    // Critical values at the 5% significance level.
+    assert!((chi_square_p_value(3.841, 1) - 0.05).abs() < 1.0e-3);
+    assert!((chi_square_p_value(18.307, 10) - 0.05).abs() < 1.0e-3);
+    assert!((chi_square_p_value(124.342, 100) - 0.05).abs() < 1.0e-3);
+}
+
+#[test]
+fn sampled_directions_follow_pdf() {
+    let oblique = Vec3::new(0.6, 0.0, 0.8);
+    let grazing = Vec3::new(0.95, 0.0, 0.1).normalize();
+    let cases = [
+        (
+            "diffuse",
+            material(Vec3::splat(0.8), 0.0, 0.0, 1.0, 0.3),
+            oblique,
+        ),
+        (
+            "plastic",
+            material(Vec3::splat(0.5), 0.04, 0.0, 1.5, 0.5),
+            oblique,
+        ),
+        ("metal", material(Vec3::ZERO, 0.9, 0.0, 1.0, 0.4), grazing),
+        (
+            "glass entering",
+            material(Vec3::ONE, 0.04, 1.0, 1.5, 0.5),
+            oblique,
+        ),
+        (
+            "glass exiting",
+            material(Vec3::ONE, 0.04, 1.0, 1.5, 0.5),
+            -oblique,
+        ),
+        (
+            "glass grazing",
+            material(Vec3::ONE, 0.04, 1.0, 1.5, 0.6),
+            -grazing,
+        ),
+        (
+            "translucent",
+            material(Vec3::splat(0.7), 0.04, 0.5, 1.3, 0.6),
+            oblique,
+        ),
+    ];
+    // Šidák correction for testing several cases at the 1% significance level.
+    let significance = 1.0 - 0.99f64.powf(1.0 / cases.len() as f64);
+    for (name, material, wi) in cases {
+        let p_value = chi_square_test(&material, wi);
+        assert!(p_value > significance, "{name}: p-value {p_value}");
+    }
+}
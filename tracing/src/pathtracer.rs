@@ -1,35 +1,184 @@
 use crate::{
+    adaptive::Tile,
     camera::Pinhole,
     collections::GeometryCollection,
+    environment::Environment,
+    filter::PixelFilter,
     image_buffer::ImageBuffer,
-    light::Light,
-    material::Surface,
+    light::LightSample,
+    light_sampler::{LightSampler, ShadingPoint},
+    material::{Material, Surface},
     raylogger::{RayLoggerWithIteration, RayLoggerWithIterationAndPixel},
-    sampling::uniform_sample_unit_square,
+    sampler::{Sampler, SamplerKind},
+    sampling::{
+        RussianRoulette, concentric_sample_unit_disk, power_heuristic, uniform_sample_unit_square,
+    },
 };
 use geometry::{geometry::Intersection, ray::Ray};
-use glam::{UVec2, Vec3};
-use rand::rngs::SmallRng;
+use glam::{UVec2, Vec2, Vec3};
 
 pub struct Pathtracer<GC> {
     pub max_bounces: u8,
+    pub russian_roulette: RussianRoulette,
+    pub filter: PixelFilter,
+    pub sampler: SamplerKind,
+    /// Every sample is derived from the seed, the pixel and the sample index.
+    pub seed: u64,
     pub geometry_collection: GC,
-    pub lights: Vec<Light>,
-    pub environment: Vec3,
+    pub lights: LightSampler,
+    pub environment: Environment,
 }
 
 impl<GC> Pathtracer<GC>
 where
     GC: GeometryCollection,
 {
+    /// Radiance emitted towards the ray origin by the closest light hit before `t_max`, `None`
+    /// when no light is hit.
+    ///
+    /// The `bsdf_pdf` is the PDF of the BSDF sample at `vertex` that generated the ray, or `None`
+    /// when the ray can not be generated by light sampling (camera rays and delta bounces).
+    fn emitted_along_ray(
+        &self,
+        ray: &Ray,
+        t_max: f32,
+        bsdf_pdf: Option<f32>,
+        vertex: ShadingPoint,
+    ) -> Option<Vec3> {
+        self.lights
+            .lights()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, light)| Some((i, light.intersect(ray)?)))
+            .filter(|(_, hit)| hit.t < t_max)
+            .min_by(|(_, a), (_, b)| a.t.total_cmp(&b.t))
+            .map(|(i, hit)| match bsdf_pdf {
+                Some(bsdf_pdf) => {
+                    let light_pdf = hit.pdf * self.lights.pmf(vertex, i);
+                    hit.radiance * power_heuristic(bsdf_pdf, light_pdf)
+                }
+                None => hit.radiance,
+            })
+    }
+
+    /// Whether a light surface blocks the shadow ray before the end of its range. The sampled
+    /// light itself is hit at the end of the range, which is not counted.
+    fn occluded_by_lights(&self, sample: &LightSample) -> bool {
+        let t_max = sample.t_range.end() * (1.0 - 1.0e-4);
+        self.lights
+            .lights()
+            .iter()
+            .filter_map(|light| light.intersect(&sample.shadow_ray))
+            .any(|hit| hit.t < t_max)
+    }
+
+    /// Radiance reflected towards the ray origin from one sample of a selected light, each light
+    /// infinitely far away and the environment.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_lights(
+        &self,
+        ray_logger: &mut RayLoggerWithIterationAndPixel,
+        sampler: &mut dyn Sampler,
+        bounce: u8,
+        material: &Material,
+        surface: &Surface,
+        vertex: ShadingPoint,
+        point_above: Vec3,
+        point_below: Vec3,
+    ) -> Vec3 {
+        let selected =
+            self.lights
+                .sample(vertex, sampler)
+                .map_or(Vec3::ZERO, |(light, probability)| {
+                    let mut sample = light.sample(point_above, sampler);
+                    sample.pdf *= probability;
+                    self.shade_light_sample(
+                        ray_logger,
+                        bounce,
+                        material,
+                        surface,
+                        point_below,
+                        sample,
+                    )
+                });
+        let infinite: Vec3 = self
+            .lights
+            .infinite()
+            .map(|light| {
+                let sample = light.sample(point_above, sampler);
+                self.shade_light_sample(ray_logger, bounce, material, surface, point_below, sample)
+            })
+            .sum();
+        let environment =
+            self.environment
+                .sample(point_above, sampler)
+                .map_or(Vec3::ZERO, |sample| {
+                    self.shade_light_sample(
+                        ray_logger,
+                        bounce,
+                        material,
+                        surface,
+                        point_below,
+                        sample,
+                    )
+                });
+        selected + infinite + environment
+    }
+
+    fn shade_light_sample(
+        &self,
+        ray_logger: &mut RayLoggerWithIterationAndPixel,
+        bounce: u8,
+        material: &Material,
+        surface: &Surface,
+        point_below: Vec3,
+        mut sample: LightSample,
+    ) -> Vec3 {
+        if sample.pdf == 0.0 {
+            return Vec3::ZERO;
+        }
+        let wo = sample.shadow_ray.direction.normalize();
+        let bsdf = material.eval(surface, wo);
+        if bsdf == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+        if wo.dot(surface.n) < 0.0 {
+            // Transmitted light, start the shadow ray on the other side of the surface.
+            sample.shadow_ray = Ray::new(point_below, sample.shadow_ray.direction);
+        }
+        let occluded = self
+            .geometry_collection
+            .intersect(&sample.shadow_ray, sample.t_range.clone())
+            .is_some()
+            || self.occluded_by_lights(&sample);
+        ray_logger
+            .log_shadow(&sample.shadow_ray, bounce, occluded)
+            .unwrap();
+        if occluded {
+            return Vec3::ZERO;
+        }
+        let weight = if sample.is_delta {
+            1.0
+        } else {
+            power_heuristic(sample.pdf, material.pdf(surface, wo))
+        };
+        bsdf * sample.radiance * (wo.dot(surface.n).abs() * weight / sample.pdf)
+    }
+
     fn trace_ray(
         &self,
         mut ray_logger: RayLoggerWithIterationAndPixel,
-        rng: &mut SmallRng,
+        sampler: &mut dyn Sampler,
         mut ray: Ray,
     ) -> Vec3 {
         let mut accumulated_radiance = Vec3::ZERO;
         let mut accumulated_transport = Vec3::ONE;
+        let mut bsdf_pdf = None;
+        // The previous vertex, where the current ray was sampled from the BSDF.
+        let mut vertex = ShadingPoint {
+            point: ray.origin,
+            normal: Vec3::ZERO,
+        };
         for bounce in 1..=self.max_bounces {
             let intersection = self.geometry_collection.intersect(&ray, 0.0..=f32::MAX);
             ray_logger
@@ -41,11 +190,23 @@ where
                     intersection.is_some(),
                 )
                 .unwrap();
+            let t_max = intersection
+                .as_ref()
+                .map_or(f32::MAX, |isect| isect.inner.t());
+            if let Some(emitted) = self.emitted_along_ray(&ray, t_max, bsdf_pdf, vertex) {
+                // Light surfaces are opaque and do not reflect, the path ends at the light.
+                return accumulated_radiance + accumulated_transport * emitted;
+            }
             let Some(intersection) = intersection else {
-                return accumulated_radiance + accumulated_transport * self.environment;
+                let direction = ray.direction.normalize();
+                let weight = bsdf_pdf.map_or(1.0, |bsdf_pdf| {
+                    power_heuristic(bsdf_pdf, self.environment.pdf(direction))
+                });
+                return accumulated_radiance
+                    + accumulated_transport * self.environment.radiance(direction) * weight;
             };
 
-            let wi = -ray.direction;
+            let wi = -ray.direction.normalize();
             let n = self.geometry_collection.compute_normal(&intersection);
             let uv = self.geometry_collection.compute_texcoord(&intersection);
             let material = self.geometry_collection.material(&intersection);
@@ -60,43 +221,49 @@ where
             let point_below = point - offset;
             let surface = Surface { wi, n, uv };
 
-            let sample = material.sample(&surface, rng);
-
-            let incoming_radiance: Vec3 = if sample.is_delta {
-                Vec3::ZERO
-            } else {
-                self.lights
-                    .iter()
-                    .map(|light| {
-                        let (shadow_ray, t_range) = light.sample_shadow_ray(point_above, rng);
-                        let intersection = self.geometry_collection.intersect(&shadow_ray, t_range);
-                        ray_logger
-                            .log_shadow(&shadow_ray, bounce, intersection.is_some())
-                            .unwrap();
-                        if intersection.is_some() {
-                            return Vec3::ZERO;
-                        }
-                        let radiance = light.emitted(&point);
-                        let wo = shadow_ray.direction.normalize();
-                        sample.bsdf * radiance * wo.dot(surface.n).abs()
-                    })
-                    .sum()
-            };
+            if material.emittance != Vec3::ZERO && wi.dot(n) > 0.0 {
+                let weight = bsdf_pdf.map_or(1.0, |bsdf_pdf| {
+                    let t = intersection.inner.t();
+                    let light_pdf = self
+                        .lights
+                        .geometry_pdf(vertex, intersection.index, &ray, t);
+                    power_heuristic(bsdf_pdf, light_pdf)
+                });
+                accumulated_radiance += accumulated_transport * material.emittance * weight;
+            }
 
+            vertex = ShadingPoint { point, normal: n };
+            let incoming_radiance = self.sample_lights(
+                &mut ray_logger,
+                sampler,
+                bounce,
+                material,
+                &surface,
+                vertex,
+                point_above,
+                point_below,
+            );
             accumulated_radiance += accumulated_transport * incoming_radiance;
 
+            let sample = material.sample(&surface, sampler);
             if sample.pdf == 0.0 {
                 return accumulated_radiance;
             }
 
             let cosine_term = sample.wo.dot(surface.n);
             if sample.is_delta {
-                accumulated_transport *= sample.bsdf;
+                accumulated_transport *= sample.bsdf / sample.pdf;
+                bsdf_pdf = None;
             } else {
                 accumulated_transport *= sample.bsdf * (cosine_term.abs() / sample.pdf);
+                bsdf_pdf = Some(sample.pdf);
             }
 
-            if accumulated_transport.length_squared() <= 1.0e-4 {
+            if !self.russian_roulette.survive(
+                u32::from(bounce),
+                &mut accumulated_transport,
+                sampler,
+            ) {
                 return accumulated_radiance;
             }
 
@@ -110,50 +277,146 @@ where
         accumulated_radiance
     }
 
-    fn sample_ray_for_pixel(pinhole: &Pinhole, rng: &mut SmallRng, pixel: UVec2) -> Ray {
+    /// Returns the continuous image position of the sample together with the camera ray.
+    fn sample_ray_for_pixel(
+        pinhole: &Pinhole,
+        sampler: &mut dyn Sampler,
+        pixel: UVec2,
+    ) -> (Vec2, Ray) {
         debug_assert!(pixel.x < pinhole.size.x && pixel.y < pinhole.size.y);
-        let pixel_center = pixel.as_vec2() + uniform_sample_unit_square(rng);
-        pinhole.ray(pixel_center / pinhole.size.as_vec2())
+        let position = pixel.as_vec2() + uniform_sample_unit_square(sampler);
+        let lens = concentric_sample_unit_disk(sampler);
+        (
+            position,
+            pinhole.ray(position / pinhole.size.as_vec2(), lens),
+        )
     }
 
-    fn render_pixel(
+    /// Trace sample `sample_index` of `pixel`, returns the image position of the sample and its
+    /// radiance.
+    pub fn sample_pixel(
         &self,
         pinhole: &Pinhole,
         pixel: UVec2,
         ray_logger: &mut RayLoggerWithIteration,
-        rng: &mut SmallRng,
-    ) -> Vec3 {
+        sampler: &mut dyn Sampler,
+        sample_index: u32,
+    ) -> (Vec2, Vec3) {
+        sampler.start_pixel_sample(pixel, sample_index);
         let ray_logger = ray_logger.with_pixel(pixel.x as u16, pixel.y as u16);
-        let ray = Self::sample_ray_for_pixel(pinhole, rng, pixel);
-        self.trace_ray(ray_logger, rng, ray)
+        let (position, ray) = Self::sample_ray_for_pixel(pinhole, sampler, pixel);
+        (position, self.trace_ray(ray_logger, sampler, ray))
     }
 
+    /// Render sample `sample_index` of every pixel.
     pub fn render_mut(
         &self,
         pinhole: &Pinhole,
         ray_logger: &mut RayLoggerWithIteration,
-        rng: &mut SmallRng,
+        sampler: &mut dyn Sampler,
+        sample_index: u32,
         buffer: &mut ImageBuffer,
     ) {
-        for (pixel, value) in buffer.iter_mut() {
-            *value += self.render_pixel(pinhole, pixel, ray_logger, rng);
+        for pixel in buffer.coordinates() {
+            let (position, value) =
+                self.sample_pixel(pinhole, pixel, ray_logger, sampler, sample_index);
+            buffer.splat(&self.filter, position, value);
         }
     }
 
+    /// Render sample `sample_index` of the pixels in `tile`.
     pub fn render_subdivided_mut(
         &self,
         pinhole: &Pinhole,
         ray_logger: &mut RayLoggerWithIteration,
-        rng: &mut SmallRng,
+        sampler: &mut dyn Sampler,
+        sample_index: u32,
         buffer: &mut ImageBuffer,
-        sub_start: UVec2,
-        sub_size: UVec2,
+        tile: Tile,
     ) {
-        for sub_y in 0..sub_size.y {
-            for sub_x in 0..sub_size.x {
-                let pixel = sub_start + UVec2::new(sub_x, sub_y);
-                buffer[pixel] += self.render_pixel(pinhole, pixel, ray_logger, rng);
+        for sub_y in 0..tile.size.y {
+            for sub_x in 0..tile.size.x {
+                let pixel = tile.start + UVec2::new(sub_x, sub_y);
+                let (position, value) =
+                    self.sample_pixel(pinhole, pixel, ray_logger, sampler, sample_index);
+                buffer.splat(&self.filter, position, value);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use rand::{SeedableRng, rngs::SmallRng};
+
+    use super::*;
+    use crate::{
+        collections::SphereCollection,
+        light::{Light, PointLight, SphericalLight},
+        light_sampler::LightSelection,
+    };
+
+    fn sphere_light(y: f32, intensity: f32) -> Light {
+        Light::from(SphericalLight {
+            point: PointLight {
+                center: Vec3::new(0.0, y, 0.0),
+                intensity: Vec3::splat(intensity),
+            },
+            radius: 0.5,
+        })
+    }
+
+    fn pathtracer(lights: Vec<Light>) -> Pathtracer<SphereCollection> {
+        Pathtracer {
+            max_bounces: 1,
+            russian_roulette: RussianRoulette::default(),
+            filter: PixelFilter::default(),
+            sampler: SamplerKind::Independent,
+            seed: 0,
+            geometry_collection: SphereCollection {
+                spheres: Vec::new(),
+                properties: Vec::new(),
+                materials: Vec::new(),
+            },
+            lights: LightSampler::new(lights, LightSelection::default()),
+            environment: Vec3::ZERO.into(),
+        }
+    }
+
+    #[test]
+    fn rays_stop_at_the_closest_light() {
+        let pathtracer = pathtracer(vec![sphere_light(4.0, 100.0), sphere_light(2.0, 1.0)]);
+        let ray = Ray::new(Vec3::ZERO, Vec3::Y);
+        let vertex = ShadingPoint {
+            point: Vec3::ZERO,
+            normal: Vec3::ZERO,
+        };
+        let emitted = pathtracer.emitted_along_ray(&ray, f32::MAX, None, vertex);
+        assert_eq!(emitted, Some(Vec3::splat(1.0 / (PI * 0.25))));
+        assert_eq!(pathtracer.emitted_along_ray(&ray, 1.0, None, vertex), None);
+    }
+
+    #[test]
+    fn lights_occlude_shadow_rays_to_other_lights() {
+        let sphere = sphere_light(2.0, 1.0);
+        let pathtracer = pathtracer(vec![sphere.clone()]);
+        let point_light = |center: Vec3| {
+            Light::from(PointLight {
+                center,
+                intensity: Vec3::ONE,
+            })
+        };
+        let mut rng = SmallRng::seed_from_u64(1);
+        let behind = point_light(Vec3::new(0.0, 4.0, 0.0)).sample(Vec3::ZERO, &mut rng);
+        assert!(pathtracer.occluded_by_lights(&behind));
+        let beside = point_light(Vec3::new(4.0, 0.0, 0.0)).sample(Vec3::ZERO, &mut rng);
+        assert!(!pathtracer.occluded_by_lights(&beside));
+        // The sampled light itself is hit at the end of the shadow ray.
+        for _ in 0..100 {
+            let sample = sphere.sample(Vec3::ZERO, &mut rng);
+            assert!(!pathtracer.occluded_by_lights(&sample));
+        }
+    }
+}
@@ -4,11 +4,14 @@ use geometry::{
     triangle::{Triangle, TriangleIntersection, TriangleNormals, TriangleTexcoords},
 };
 use glam::{Vec2, Vec3};
+use std::collections::HashMap;
 use wavefront::{mtl, obj};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct TriangleProperties {
     pub material: usize,
+    /// Index of the object in [`obj::Obj::objects`] that the triangle belongs to.
+    pub object: usize,
     pub normals: TriangleNormals,
     pub texcoords: TriangleTexcoords,
 }
@@ -46,47 +49,146 @@ impl SphereProperties {
     }
 }
 
+/// Crease angle in degrees used for generating smooth vertex normals.
+pub const DEFAULT_CREASE_ANGLE: f32 = 60.0;
+
+/// Angle between the edges of `triangle` at corner `i`.
+fn corner_angle(triangle: &[Vec3; 3], i: usize) -> f32 {
+    let corner = triangle[i];
+    let a = triangle[(i + 1) % 3] - corner;
+    let b = triangle[(i + 2) % 3] - corner;
+    a.angle_between(b)
+}
+
+/// Normals for the corners of `triangles`. Triangles in smoothing group zero get their face
+/// normal. Other corners get the angle weighted average of the face normals of the triangles in the
+/// same smoothing group sharing the vertex position, except triangles whose face normal differs
+/// by more than `crease_angle` degrees. Triangles without area take the average normal of their
+/// neighbours in the smoothing group, or an arbitrary unit normal when there are none.
+fn generate_normals(triangles: &[[Vec3; 3]], groups: &[u32], crease_angle: f32) -> Vec<[Vec3; 3]> {
+    let face_normals: Vec<Vec3> = triangles
+        .iter()
+        .map(|[v0, v1, v2]| (*v1 - *v0).cross(*v2 - *v0).normalize_or_zero())
+        .collect();
+    // Corners by smoothing group and vertex position, adding zero turns -0 into 0.
+    let key = |t: usize, i: usize| {
+        let position = triangles[t][i].to_array().map(|x| (x + 0.0).to_bits());
+        (groups[t], position)
+    };
+    let mut corners: HashMap<_, Vec<(usize, usize)>> = HashMap::new();
+    for (t, group) in groups.iter().enumerate() {
+        if *group != 0 {
+            for i in 0..3 {
+                corners.entry(key(t, i)).or_default().push((t, i));
+            }
+        }
+    }
+    let cos_crease_angle = crease_angle.to_radians().cos();
+    (0..triangles.len())
+        .map(|t| {
+            let face_normal = face_normals[t];
+            let is_degenerate = face_normal == Vec3::ZERO;
+            let flat_normal = if is_degenerate { Vec3::Z } else { face_normal };
+            if groups[t] == 0 {
+                return [flat_normal; 3];
+            }
+            std::array::from_fn(|i| {
+                corners[&key(t, i)]
+                    .iter()
+                    .filter(|(other, _)| {
+                        let other_normal = face_normals[*other];
+                        other_normal != Vec3::ZERO
+                            && (is_degenerate || other_normal.dot(face_normal) >= cos_crease_angle)
+                    })
+                    .map(|(other, j)| face_normals[*other] * corner_angle(&triangles[*other], *j))
+                    .sum::<Vec3>()
+                    .try_normalize()
+                    .unwrap_or(flat_normal)
+            })
+        })
+        .collect()
+}
+
+/// Triangle of a face in an OBJ file.
+struct FaceTriangle<'a> {
+    material: usize,
+    object: usize,
+    smoothing_group: u32,
+    points: [&'a obj::Point; 3],
+}
+
+/// Triangulate the faces of `obj`. Corners without a normal in the file get one generated from the
+/// smoothing groups and `crease_angle` in degrees. Panics when a face uses a material that is not
+/// in `mtl`, which [`wavefront::read_obj_and_mtl_with_print_logging`] rules out.
 pub fn from_wavefront(
     obj: &obj::Obj,
     mtl: &mtl::Mtl,
+    crease_angle: f32,
 ) -> (Vec<AnyTriangle>, Vec<TriangleProperties>) {
     let materials: Vec<&str> = mtl.materials.iter().map(|m| m.name.as_str()).collect();
-    let (shapes, properties) = obj
+    // The objects cover all faces in file order, which is also the order of the chunks.
+    let objects = obj
+        .objects
+        .iter()
+        .enumerate()
+        .flat_map(|(i, object)| object.faces.clone().map(move |_| i));
+    let faces: Vec<FaceTriangle> = obj
         .chunks
         .iter()
         .flat_map(|chunk| {
-            chunk.faces.iter().map(|face| {
-                assert!(
-                    face.points.len() == 3,
-                    "Only tringular faces supported but found {} vertices.",
-                    face.points.len()
-                );
-                let triangle = AnyTriangle::from(Triangle {
-                    v0: obj.index_vertex(&face.points[0]).into(),
-                    v1: obj.index_vertex(&face.points[1]).into(),
-                    v2: obj.index_vertex(&face.points[2]).into(),
-                });
-                let normals = TriangleNormals {
-                    n0: obj.index_normal(&face.points[0]).into(),
-                    n1: obj.index_normal(&face.points[1]).into(),
-                    n2: obj.index_normal(&face.points[2]).into(),
-                };
-                let texcoords = TriangleTexcoords {
-                    uv0: obj.index_texcoord(&face.points[0]).into(),
-                    uv1: obj.index_texcoord(&face.points[1]).into(),
-                    uv2: obj.index_texcoord(&face.points[2]).into(),
-                };
-                let material_index = materials.iter().position(|m| *m == chunk.material).unwrap();
-                let properties = TriangleProperties {
-                    normals,
-                    texcoords,
-                    material: material_index,
-                };
-                (triangle, properties)
+            let material = materials
+                .iter()
+                .position(|m| *m == chunk.material)
+                .expect("faces use materials from the material library");
+            chunk.faces.iter().map(move |face| (material, face))
+        })
+        .zip(objects)
+        .flat_map(|((material, face), object)| {
+            obj.triangulate(face).map(move |points| FaceTriangle {
+                material,
+                object,
+                smoothing_group: face.smoothing_group,
+                points,
             })
         })
-        .unzip();
-    (shapes, properties)
+        .collect();
+    let triangles: Vec<[Vec3; 3]> = faces
+        .iter()
+        .map(|face| face.points.map(|p| obj.index_vertex(p).into()))
+        .collect();
+    let groups: Vec<u32> = faces.iter().map(|face| face.smoothing_group).collect();
+    let generated_normals = generate_normals(&triangles, &groups, crease_angle);
+    faces
+        .iter()
+        .zip(triangles)
+        .zip(generated_normals)
+        .map(|((face, [v0, v1, v2]), generated)| {
+            let points = face.points;
+            let normal = |i: usize| {
+                if points[i].n == 0 {
+                    generated[i]
+                } else {
+                    obj.index_normal(points[i]).into()
+                }
+            };
+            let [p0, p1, p2] = points;
+            let properties = TriangleProperties {
+                normals: TriangleNormals {
+                    n0: normal(0),
+                    n1: normal(1),
+                    n2: normal(2),
+                },
+                texcoords: TriangleTexcoords {
+                    uv0: obj.index_texcoord(p0).into(),
+                    uv1: obj.index_texcoord(p1).into(),
+                    uv2: obj.index_texcoord(p2).into(),
+                },
+                material: face.material,
+                object: face.object,
+            };
+            (AnyTriangle::from(Triangle { v0, v1, v2 }), properties)
+        })
+        .unzip()
 }
 
 #[cfg(test)]
@@ -94,6 +196,7 @@ mod tests {
     use super::*;
     use geometry::ray::Ray;
     use geometry::sphere::Sphere;
+    use std::f32::consts::FRAC_PI_2;
 
     #[test]
     fn compute_normal_origo_sphere_intersected_along_x_axis() {
@@ -148,4 +251,92 @@ mod tests {
 
         assert_eq!(actual, Vec3::new(0.0, 0.0, 1.0));
     }
+
+    /// Two triangles sharing the edge along the z-axis, folded by `fold` radians.
+    fn folded(fold: f32) -> [[Vec3; 3]; 2] {
+        let (sin, cos) = fold.sin_cos();
+        [
+            [Vec3::ZERO, Vec3::Z, Vec3::X],
+            [Vec3::ZERO, Vec3::new(-cos, sin, 0.0), Vec3::Z],
+        ]
+    }
+
+    #[test]
+    fn generate_normals_without_smoothing_group_are_flat() {
+        let normals = generate_normals(&folded(0.5), &[0, 0], 180.0);
+        assert_eq!(normals[0], [Vec3::Y; 3]);
+        assert!(normals[1].iter().all(|n| n.angle_between(Vec3::Y) > 0.49));
+    }
+
+    #[test]
+    fn generate_normals_in_smoothing_group_are_averaged_at_shared_vertices() {
+        let triangles = folded(FRAC_PI_2);
+        let normals = generate_normals(&triangles, &[1, 1], 180.0);
+        let average = Vec3::new(1.0, 1.0, 0.0).normalize();
+        assert!(normals[0][0].abs_diff_eq(average, 1e-6));
+        assert!(normals[0][1].abs_diff_eq(average, 1e-6));
+        assert!(normals[1][0].abs_diff_eq(average, 1e-6));
+        assert!(normals[0][2].abs_diff_eq(Vec3::Y, 1e-6));
+        assert!(normals[1][1].abs_diff_eq(Vec3::X, 1e-6));
+    }
+
+    #[test]
+    fn generate_normals_keeps_creases_and_group_boundaries() {
+        let triangles = folded(FRAC_PI_2);
+        let creased = generate_normals(&triangles, &[1, 1], 60.0);
+        assert!(creased[0].iter().all(|n| n.abs_diff_eq(Vec3::Y, 1e-6)));
+        let separate = generate_normals(&triangles, &[1, 2], 180.0);
+        assert!(separate[0].iter().all(|n| n.abs_diff_eq(Vec3::Y, 1e-6)));
+    }
+
+    #[test]
+    fn generate_normals_for_collinear_triangles_are_unit_length() {
+        let collinear = [Vec3::ZERO, Vec3::X, 2.0 * Vec3::X];
+        let alone = generate_normals(&[collinear], &[0], 180.0);
+        assert!(alone[0].iter().all(|n| n.is_normalized()));
+        let smooth = generate_normals(&[collinear], &[1], 180.0);
+        assert!(smooth[0].iter().all(|n| n.is_normalized()));
+
+        // Next to a triangle in the xz-plane the collinear one takes its normal at the shared
+        // corners without affecting it.
+        let triangles = [[Vec3::ZERO, Vec3::Z, Vec3::X], collinear];
+        let normals = generate_normals(&triangles, &[1, 1], 60.0);
+        assert_eq!(normals[0], [Vec3::Y; 3]);
+        assert_eq!(normals[1][0], Vec3::Y);
+        assert_eq!(normals[1][1], Vec3::Y);
+        assert!(normals[1][2].is_normalized());
+    }
+
+    #[test]
+    fn generate_normals_are_weighted_by_corner_angle() {
+        // A quad split into two triangles along the diagonal, and a third triangle at a right
+        // angle. The quad's two triangles together have the same weight as the third triangle at
+        // the corner they all share.
+        let triangles = [
+            [Vec3::ZERO, Vec3::Z, Vec3::new(1.0, 0.0, 1.0)],
+            [Vec3::ZERO, Vec3::new(1.0, 0.0, 1.0), Vec3::X],
+            [Vec3::ZERO, Vec3::Z, Vec3::NEG_Y],
+        ];
+        let normals = generate_normals(&triangles, &[1, 1, 1], 180.0);
+        let expected = Vec3::new(1.0, 1.0, 0.0).normalize();
+        assert!(normals[0][0].abs_diff_eq(expected, 1e-6));
+    }
+
+    #[test]
+    fn from_wavefront_assigns_objects_to_triangles() {
+        let obj = obj::obj(
+            &mut "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nusemtl m\nf 1 2 3\n\
+                  o quad\nf 1 2 3 4\no last\nf 1 3 4"
+                .as_bytes(),
+            wavefront::ParseMode::Strict,
+        )
+        .unwrap();
+        let mtl = mtl::mtl(&mut "newmtl m".as_bytes(), wavefront::ParseMode::Strict).unwrap();
+        let (triangles, properties) = from_wavefront(&obj, &mtl, DEFAULT_CREASE_ANGLE);
+        assert_eq!(triangles.len(), 4);
+        let objects: Vec<usize> = properties.iter().map(|p| p.object).collect();
+        assert_eq!(objects, [0, 1, 1, 2]);
+        assert_eq!(obj.objects[2].name, "last");
+        assert!(properties.iter().all(|p| p.normals.n0 == Vec3::Z));
+    }
 }
@@ -0,0 +1,409 @@
+use glam::{UVec2, Vec2};
+use rand::{RngExt, SeedableRng, rngs::SmallRng};
+
+/// Largest `f32` below one.
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Source of sample values in [0, 1), requested one or two dimensions at a time.
+///
+/// The values only depend on the seed, the pixel, the sample index and the order of the requests,
+/// so a sample renders the same no matter which thread or iteration produces it.
+pub trait Sampler {
+    /// Start sample `index` of `pixel`, the next request returns the first dimension.
+    fn start_pixel_sample(&mut self, pixel: UVec2, index: u32);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> Vec2;
+}
+
+/// Plain random numbers, ignores the pixel and sample index.
+impl Sampler for SmallRng {
+    fn start_pixel_sample(&mut self, _pixel: UVec2, _index: u32) {}
+
+    fn get_1d(&mut self) -> f32 {
+        self.random()
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        Vec2::new(self.random(), self.random())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    /// Jittered strata, `strata` per axis for two dimensional requests. Each round of
+    /// `strata * strata` sample indices covers all strata.
+    Stratified { strata: u32 },
+    /// Halton sequence with a random shift per pixel and dimension.
+    Halton,
+    /// Owen-scrambled Sobol (0, 2)-sequence, decorrelated between dimensions by shuffling the
+    /// sample index (Burley 2020, "Practical Hash-based Owen Scrambling").
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn create(self, seed: u64) -> Box<dyn Sampler + Send> {
+        let state = PixelSample {
+            seed,
+            pixel: UVec2::ZERO,
+            index: 0,
+            dimension: 0,
+        };
+        match self {
+            Self::Independent => Box::new(IndependentSampler {
+                seed,
+                rng: SmallRng::seed_from_u64(seed),
+            }),
+            Self::Stratified { strata } => Box::new(StratifiedSampler {
+                strata: strata.max(1),
+                state,
+            }),
+            Self::Halton => Box::new(HaltonSampler { state }),
+            Self::Sobol => Box::new(SobolSampler { state }),
+        }
+    }
+}
+
+const fn mix(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
+
+fn hash(parts: &[u64]) -> u64 {
+    parts
+        .iter()
+        .fold(0x9e37_79b9_7f4a_7c15, |hash, part| mix(hash ^ mix(*part)))
+}
+
+/// Uniform value in [0, 1) from the high bits of a hash.
+fn to_unit(bits: u64) -> f32 {
+    (bits >> 40) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+/// Uniform value in [0, 1) from a 32-bit fixed point fraction.
+fn fraction_to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+struct PixelSample {
+    seed: u64,
+    pixel: UVec2,
+    index: u32,
+    dimension: u32,
+}
+
+impl PixelSample {
+    fn start(&mut self, pixel: UVec2, index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_dimension(&mut self, count: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += count;
+        dimension
+    }
+
+    fn hash(&self, dimension: u32, extra: u64) -> u64 {
+        hash(&[
+            self.seed,
+            u64::from(self.pixel.x),
+            u64::from(self.pixel.y),
+            u64::from(dimension),
+            extra,
+        ])
+    }
+}
+
+struct IndependentSampler {
+    seed: u64,
+    rng: SmallRng,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: UVec2, index: u32) {
+        let seed = hash(&[
+            self.seed,
+            u64::from(pixel.x),
+            u64::from(pixel.y),
+            u64::from(index),
+        ]);
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.get_1d()
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        self.rng.get_2d()
+    }
+}
+
+/// Element `i` of a pseudo random permutation of `0..l` (Kensler 2013, "Correlated Multi-Jittered
+/// Sampling").
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    i.wrapping_add(p) % l
+}
+
+struct StratifiedSampler {
+    strata: u32,
+    state: PixelSample,
+}
+
+impl StratifiedSampler {
+    /// Stratum of the current sample index and the jitter hash for a dimension.
+    fn stratum(&self, dimension: u32) -> (u32, u64) {
+        let count = self.strata * self.strata;
+        let round = self.state.index / count;
+        let permutation = self.state.hash(dimension, u64::from(round)) as u32;
+        let stratum = permutation_element(self.state.index % count, count, permutation);
+        let jitter = self
+            .state
+            .hash(dimension, u64::from(self.state.index) | 1 << 32);
+        (stratum, jitter)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: UVec2, index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.state.next_dimension(1);
+        let (stratum, jitter) = self.stratum(dimension);
+        let count = self.strata * self.strata;
+        ((stratum as f32 + to_unit(jitter)) / count as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let dimension = self.state.next_dimension(2);
+        let (stratum, jitter) = self.stratum(dimension);
+        let cell = Vec2::new(
+            (stratum % self.strata) as f32,
+            (stratum / self.strata) as f32,
+        );
+        let jitter = Vec2::new(to_unit(jitter), to_unit(mix(jitter)));
+        ((cell + jitter) / self.strata as f32).min(Vec2::splat(ONE_MINUS_EPSILON))
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inverse_base = 1.0 / f64::from(base);
+    let mut digits = 0.0;
+    let mut scale = 1.0;
+    while index > 0 {
+        scale *= inverse_base;
+        digits += f64::from(index % base) * scale;
+        index /= base;
+    }
+    digits as f32
+}
+
+struct HaltonSampler {
+    state: PixelSample,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: UVec2, index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.state.next_dimension(1);
+        let Some(base) = PRIMES.get(dimension as usize) else {
+            // Out of bases, pad with random values.
+            return to_unit(self.state.hash(dimension, u64::from(self.state.index)));
+        };
+        let shift = to_unit(self.state.hash(dimension, 0));
+        (radical_inverse(*base, self.state.index) + shift)
+            .fract()
+            .min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        Vec2::new(self.get_1d(), self.get_1d())
+    }
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// The first two dimensions of the Sobol sequence as 32-bit fractions.
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut y = 0;
+    let mut v = 1 << 31;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    (index.reverse_bits(), y)
+}
+
+struct SobolSampler {
+    state: PixelSample,
+}
+
+impl SobolSampler {
+    fn sample(&mut self, count: u32) -> Vec2 {
+        let dimension = self.state.next_dimension(count);
+        let shuffle = self.state.hash(dimension, 0);
+        let index = nested_uniform_scramble(self.state.index, shuffle as u32);
+        let (x, y) = sobol_2d(index);
+        let scramble = mix(shuffle);
+        Vec2::new(
+            fraction_to_unit(nested_uniform_scramble(x, scramble as u32)),
+            fraction_to_unit(nested_uniform_scramble(y, (scramble >> 32) as u32)),
+        )
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: UVec2, index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.sample(1).x
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        self.sample(2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified { strata: 4 },
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    fn values(sampler: &mut dyn Sampler, pixel: UVec2, index: u32) -> [f32; 5] {
+        sampler.start_pixel_sample(pixel, index);
+        let a = sampler.get_2d();
+        let b = sampler.get_1d();
+        let c = sampler.get_2d();
+        [a.x, a.y, b, c.x, c.y]
+    }
+
+    #[test]
+    fn samples_are_deterministic_per_pixel_and_index() {
+        for kind in KINDS {
+            let mut first = kind.create(7);
+            let mut second = kind.create(7);
+            let pixel = UVec2::new(3, 5);
+            let expected = values(first.as_mut(), pixel, 11);
+            values(second.as_mut(), UVec2::new(1, 1), 2);
+            assert_eq!(values(second.as_mut(), pixel, 11), expected, "{kind:?}");
+            assert_ne!(values(second.as_mut(), pixel, 12), expected, "{kind:?}");
+            assert_ne!(values(kind.create(8).as_mut(), pixel, 11), expected);
+            for index in 0..1000 {
+                let values = values(first.as_mut(), UVec2::new(index % 7, 2), index);
+                assert!(values.iter().all(|v| (0.0..1.0).contains(v)), "{kind:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_matches_reference_points() {
+        let points: Vec<_> = (0..4).map(sobol_2d).collect();
+        assert_eq!(
+            points,
+            [
+                (0, 0),
+                (0x8000_0000, 0x8000_0000),
+                (0x4000_0000, 0xc000_0000),
+                (0xc000_0000, 0x4000_0000)
+            ]
+        );
+    }
+
+    #[test]
+    fn sixteen_samples_fill_four_by_four_strata() {
+        for kind in [SamplerKind::Stratified { strata: 4 }, SamplerKind::Sobol] {
+            let mut sampler = kind.create(3);
+            for dimension in 0..4 {
+                let mut strata = [0; 16];
+                for index in 0..16 {
+                    sampler.start_pixel_sample(UVec2::new(2, 9), index);
+                    for _ in 0..dimension {
+                        sampler.get_2d();
+                    }
+                    let cell = (sampler.get_2d() * 4.0).as_uvec2();
+                    strata[(cell.y * 4 + cell.x) as usize] += 1;
+                }
+                assert_eq!(strata, [1; 16], "{kind:?} dimension {dimension}");
+            }
+        }
+    }
+
+    #[test]
+    fn permutation_element_is_a_permutation() {
+        for l in [1, 5, 16, 33] {
+            let mut elements: Vec<_> = (0..l).map(|i| permutation_element(i, l, 0x1234)).collect();
+            elements.sort_unstable();
+            assert_eq!(elements, (0..l).collect::<Vec<_>>());
+        }
+    }
+}
@@ -1,14 +1,16 @@
 use glam::{Vec2, Vec3};
-use rand::{RngExt, rngs::SmallRng};
+
+use crate::sampler::Sampler;
 
 #[inline]
-pub fn uniform_sample_unit_square(rng: &mut SmallRng) -> Vec2 {
-    Vec2::new(rng.random(), rng.random())
+pub fn uniform_sample_unit_square(sampler: &mut dyn Sampler) -> Vec2 {
+    sampler.get_2d()
 }
 
-pub fn uniform_sample_unit_sphere(rng: &mut SmallRng) -> Vec3 {
-    let z = rng.random_range(-1.0..1.0);
-    let a = rng.random_range(0.0..std::f32::consts::TAU);
+pub fn uniform_sample_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let u = sampler.get_2d();
+    let z = 2.0 * u.x - 1.0;
+    let a = std::f32::consts::TAU * u.y;
     let r = (1.0f32 - z * z).sqrt();
     let (a_sin, a_cos) = a.sin_cos();
     let x = r * a_cos;
@@ -16,17 +18,17 @@ pub fn uniform_sample_unit_sphere(rng: &mut SmallRng) -> Vec3 {
     Vec3::new(x, y, z)
 }
 
-// fn uniform_sample_hemisphere(rng: &mut SmallRng) -> Vec3 {
-//     let r = uniform_sample_unit_square(rng);
+// fn uniform_sample_hemisphere(sampler: &mut dyn Sampler) -> Vec3 {
+//     let r = uniform_sample_unit_square(sampler);
 
 //     let a = 2.0 * (r.y * (1.0 - r.y)).sqrt();
 //     let b = std::f32::consts::TAU * r.x;
 //     Vec3::new(a * b.cos(), a * b.sin(), (1.0 - 2.0 * r.y).abs())
 // }
 
-fn concentric_sample_unit_disk(rng: &mut SmallRng) -> Vec2 {
-    let x = rng.random_range(-1.0f32..=1.0f32);
-    let y = rng.random_range(-1.0f32..=1.0f32);
+pub fn concentric_sample_unit_disk(sampler: &mut dyn Sampler) -> Vec2 {
+    let u = 2.0 * sampler.get_2d() - 1.0;
+    let (x, y) = (u.x, u.y);
     if x == 0.0 && y == 0.0 {
         return Vec2::ZERO;
     }
@@ -41,16 +43,185 @@ fn concentric_sample_unit_disk(rng: &mut SmallRng) -> Vec2 {
     r * Vec2::from((theta * std::f32::consts::FRAC_PI_4).sin_cos())
 }
 
-pub fn cosine_sample_hemisphere(rng: &mut SmallRng) -> Vec3 {
-    let ret = concentric_sample_unit_disk(rng);
+pub fn cosine_sample_hemisphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let ret = concentric_sample_unit_disk(sampler);
     let z = (0.0f32.max(1.0 - ret.x * ret.x - ret.y * ret.y)).sqrt();
     Vec3::new(ret.x, ret.y, z)
 }
 
+/// `1 - cos(theta)` for an angle below 90 degrees without cancellation for small angles.
+#[inline]
+fn one_minus_cos(sin2_theta: f32) -> f32 {
+    sin2_theta / (1.0 + (1.0 - sin2_theta).max(0.0).sqrt())
+}
+
+/// Uniformly sample a direction inside the cone around +z with half angle `asin(sqrt(sin2_theta_max))`,
+/// which must be at most 90 degrees.
+///
+/// The cone is given by the squared sine to stay accurate for the tiny cones of distant lights.
+pub fn uniform_sample_cone(sampler: &mut dyn Sampler, sin2_theta_max: f32) -> Vec3 {
+    let u = sampler.get_2d();
+    let one_minus_cos_theta = u.x * one_minus_cos(sin2_theta_max);
+    let cos_theta = 1.0 - one_minus_cos_theta;
+    let sin_theta = (one_minus_cos_theta * (2.0 - one_minus_cos_theta))
+        .max(0.0)
+        .sqrt();
+    let (sin_phi, cos_phi) = (std::f32::consts::TAU * u.y).sin_cos();
+    Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+}
+
+/// Solid angle PDF of [`uniform_sample_cone`].
+#[inline]
+pub fn uniform_cone_pdf(sin2_theta_max: f32) -> f32 {
+    1.0 / (std::f32::consts::TAU * one_minus_cos(sin2_theta_max))
+}
+
+/// Uniformly sample barycentric coordinates (u, v) on a triangle.
+pub fn uniform_sample_triangle(sampler: &mut dyn Sampler) -> Vec2 {
+    let u = sampler.get_2d();
+    let su = u.x.sqrt();
+    let v = u.y * su;
+    Vec2::new(1.0 - su, v)
+}
+
+/// Multiple importance sampling weight for a sample drawn from `f` when it could also have been
+/// drawn from `g`, using the power heuristic with beta = 2.
+#[inline]
+pub fn power_heuristic(f_pdf: f32, g_pdf: f32) -> f32 {
+    let f2 = f_pdf * f_pdf;
+    let g2 = g_pdf * g_pdf;
+    if f2 + g2 == 0.0 { 0.0 } else { f2 / (f2 + g2) }
+}
+
+/// Piecewise constant distribution over [0, 1) proportional to `function`, sampled by inverting
+/// its CDF. Falls back to a uniform distribution when all values are zero.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    function: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(function: Vec<f32>) -> Self {
+        assert!(!function.is_empty());
+        let n = function.len() as f32;
+        let mut cdf: Vec<f32> = std::iter::once(0.0)
+            .chain(function.iter().scan(0.0, |total, f| {
+                *total += f.max(0.0) / n;
+                Some(*total)
+            }))
+            .collect();
+        let integral = cdf[cdf.len() - 1];
+        if integral > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= integral);
+        } else {
+            cdf.iter_mut()
+                .enumerate()
+                .for_each(|(i, c)| *c = i as f32 / n);
+        }
+        Self {
+            function,
+            cdf,
+            integral,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.function.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.function.is_empty()
+    }
+
+    /// Integral of the function over [0, 1).
+    #[inline]
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    fn find(&self, u: f32) -> usize {
+        (self.cdf.partition_point(|c| *c <= u).max(1) - 1).min(self.len() - 1)
+    }
+
+    /// Returns the bucket containing `u` and its probability.
+    pub fn sample_discrete(&self, u: f32) -> (usize, f32) {
+        let i = self.find(u);
+        (i, self.probability(i))
+    }
+
+    /// Returns a position in [0, 1), its PDF and the bucket it is in.
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        let i = self.find(u);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            ((u - self.cdf[i]) / width).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let x = ((i as f32 + offset) / self.len() as f32).min(1.0 - f32::EPSILON);
+        (x, self.probability(i) * self.len() as f32, i)
+    }
+
+    /// Probability of sampling bucket `i`.
+    #[inline]
+    pub fn probability(&self, i: usize) -> f32 {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+
+    /// PDF of [`Distribution1D::sample_continuous`] producing `x`.
+    pub fn pdf(&self, x: f32) -> f32 {
+        let i = ((x * self.len() as f32) as usize).min(self.len() - 1);
+        self.probability(i) * self.len() as f32
+    }
+}
+
+/// Unbiased path termination with a survival probability based on the path throughput, applied
+/// from `min_bounces` and onwards. Surviving paths get their throughput scaled up to compensate.
+#[derive(Clone, Copy, Debug)]
+pub struct RussianRoulette {
+    pub min_bounces: u32,
+}
+
+impl Default for RussianRoulette {
+    fn default() -> Self {
+        Self { min_bounces: 3 }
+    }
+}
+
+impl RussianRoulette {
+    pub fn survival_probability(&self, bounce: u32, transport: Vec3) -> f32 {
+        if transport == Vec3::ZERO {
+            0.0
+        } else if bounce < self.min_bounces {
+            1.0
+        } else {
+            transport.max_element().clamp(0.0, 1.0)
+        }
+    }
+
+    /// Returns `false` if the path should be terminated, otherwise `transport` is compensated for
+    /// the survival probability.
+    pub fn survive(&self, bounce: u32, transport: &mut Vec3, sampler: &mut dyn Sampler) -> bool {
+        let probability = self.survival_probability(bounce, *transport);
+        if probability >= 1.0 {
+            return true;
+        }
+        if probability <= 0.0 || sampler.get_1d() >= probability {
+            return false;
+        }
+        *transport /= probability;
+        true
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand::SeedableRng;
+    use rand::{SeedableRng, rngs::SmallRng};
 
     #[test]
     fn test_uniform_sample_unit_square() {
@@ -92,6 +263,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_uniform_sample_cone() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let cos_theta_max = 0.9f32;
+        let sin2_theta_max = 1.0 - cos_theta_max * cos_theta_max;
+        for _ in 0..1000 {
+            let direction = uniform_sample_cone(&mut rng, sin2_theta_max);
+            assert!((direction.length() - 1.0).abs() <= 1e-6);
+            assert!(direction.z >= cos_theta_max - 1e-6);
+        }
+        let solid_angle = std::f32::consts::TAU * (1.0 - cos_theta_max);
+        assert!((uniform_cone_pdf(sin2_theta_max) * solid_angle - 1.0).abs() <= 1e-5);
+    }
+
+    #[test]
+    fn uniform_cone_pdf_is_accurate_for_tiny_cones() {
+        // The solid angle of a cone with sin(theta) = 1e-4 is pi * 1e-8 to first order.
+        let pdf = uniform_cone_pdf(1.0e-8);
+        assert!(
+            (pdf * std::f32::consts::PI * 1.0e-8 - 1.0).abs() <= 1e-6,
+            "{pdf}"
+        );
+    }
+
+    #[test]
+    fn test_uniform_sample_triangle() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let point = uniform_sample_triangle(&mut rng);
+            assert!(point.x >= 0.0 && point.y >= 0.0 && point.x + point.y <= 1.0);
+        }
+    }
+
+    #[test]
+    fn distribution_1d_samples_proportionally() {
+        let distribution = Distribution1D::new(vec![1.0, 0.0, 3.0, 4.0]);
+        assert_eq!(distribution.integral(), 2.0);
+        assert_eq!(distribution.sample_discrete(0.0), (0, 0.125));
+        assert_eq!(distribution.sample_discrete(0.2), (2, 0.375));
+        assert_eq!(distribution.sample_discrete(0.999), (3, 0.5));
+
+        let (x, pdf, i) = distribution.sample_continuous(0.125 + 0.375 / 2.0);
+        assert_eq!((x, pdf, i), (0.625, 1.5, 2));
+        assert_eq!(distribution.pdf(x), pdf);
+        assert_eq!(distribution.pdf(0.3), 0.0);
+    }
+
+    #[test]
+    fn distribution_1d_of_zeros_is_uniform() {
+        let distribution = Distribution1D::new(vec![0.0; 4]);
+        assert_eq!(distribution.sample_continuous(0.3), (0.3, 1.0, 1));
+        assert_eq!(distribution.probability(3), 0.25);
+    }
+
+    #[test]
+    fn power_heuristic_weights_sum_to_one() {
+        let a = 0.3;
+        let b = 1.7;
+        let sum = power_heuristic(a, b) + power_heuristic(b, a);
+        assert!((sum - 1.0).abs() <= 1e-6, "{}", sum);
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+        assert_eq!(power_heuristic(1.0, 0.0), 1.0);
+    }
+
     #[test]
     fn cosine_cosine_sample_hemisphere() {
         let mut rng = SmallRng::seed_from_u64(1);
@@ -102,4 +337,32 @@ mod tests {
             assert!(error <= 1e-6, "{}", error);
         }
     }
+
+    #[test]
+    fn russian_roulette_keeps_paths_before_min_bounces() {
+        let roulette = RussianRoulette { min_bounces: 3 };
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut transport = Vec3::splat(0.01);
+        for bounce in 0..3 {
+            assert!(roulette.survive(bounce, &mut transport, &mut rng));
+        }
+        assert_eq!(transport, Vec3::splat(0.01));
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        let roulette = RussianRoulette { min_bounces: 0 };
+        let mut rng = SmallRng::seed_from_u64(1);
+        let expected = Vec3::new(0.1, 0.3, 0.2);
+        let count = 100_000;
+        let mut sum = Vec3::ZERO;
+        for _ in 0..count {
+            let mut transport = expected;
+            if roulette.survive(1, &mut transport, &mut rng) {
+                sum += transport;
+            }
+        }
+        let actual = sum / count as f32;
+        assert!((actual - expected).abs().max_element() < 0.005, "{actual}");
+    }
 }
@@ -0,0 +1,360 @@
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use geometry::ray::Ray;
+use glam::{UVec2, Vec3};
+use wavefront::mtl;
+
+use crate::{
+    environment::EnvironmentMap,
+    light::LightSample,
+    material::luminance,
+    sampler::Sampler,
+    sampling::{uniform_cone_pdf, uniform_sample_cone},
+};
+
+/// Illuminance from the sun outside the atmosphere in kilolux.
+const SOLAR_ILLUMINANCE: f32 = 128.0;
+
+/// Wavelengths in micrometres used for the red, green and blue transmittance of the atmosphere.
+const WAVELENGTHS: [f32; 3] = [0.68, 0.55, 0.44];
+
+/// Resolution of the tabulated sky that is used to importance sample it.
+const SAMPLING_MAP_SIZE: UVec2 = UVec2::new(128, 64);
+
+/// Coefficients A to E of the Perez sky distribution.
+type PerezCoefficients = [f32; 5];
+
+/// Coefficients for luminance and the x and y chromaticities from Preetham et al.
+fn perez_coefficients(turbidity: f32) -> [PerezCoefficients; 3] {
+    let t = turbidity;
+    [
+        [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ],
+        [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ],
+        [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ],
+    ]
+}
+
+/// Relative sky value at zenith angle `theta` and angle `gamma` to the sun.
+fn perez(c: &PerezCoefficients, cos_theta: f32, gamma: f32) -> f32 {
+    let cos_gamma = gamma.cos();
+    (1.0 + c[0] * (c[1] / cos_theta).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+}
+
+/// Luminance in kcd/m² and chromaticity at the zenith for the sun at zenith angle `theta_sun`.
+fn zenith(turbidity: f32, theta_sun: f32) -> [f32; 3] {
+    let t = turbidity;
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+    let luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+    let theta = Vec3::new(theta_sun.powi(3), theta_sun.powi(2), theta_sun);
+    let chromaticity = |c2: [f32; 4], c1: [f32; 4], c0: [f32; 4]| {
+        let polynomial = |c: [f32; 4]| theta.dot(Vec3::new(c[0], c[1], c[2])) + c[3];
+        t * t * polynomial(c2) + t * polynomial(c1) + polynomial(c0)
+    };
+    let x = chromaticity(
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+    );
+    let y = chromaticity(
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+    );
+    [luminance, x, y]
+}
+
+fn xyy_to_linear_srgb(x: f32, y: f32, luminance: f32) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let big_x = x * luminance / y;
+    let big_z = (1.0 - x - y) * luminance / y;
+    Vec3::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+    .max(Vec3::ZERO)
+}
+
+/// Transmittance towards the sun due to Rayleigh and aerosol scattering, following the appendix
+/// of Preetham et al. without the absorption by ozone and water vapour.
+fn sun_transmittance(turbidity: f32, theta_sun: f32) -> Vec3 {
+    let relative_air_mass =
+        1.0 / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    Vec3::from(WAVELENGTHS.map(|lambda| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-(rayleigh + aerosol) * relative_air_mass).exp()
+    }))
+}
+
+/// The Preetham sky without the sun disc.
+#[derive(Clone, Debug)]
+struct SkyModel {
+    sun_direction: Vec3,
+    coefficients: [PerezCoefficients; 3],
+    /// Zenith values divided by the Perez function at the zenith.
+    scale: [f32; 3],
+    intensity: f32,
+}
+
+impl SkyModel {
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let cos_theta = direction.y;
+        if cos_theta <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] =
+            std::array::from_fn(|i| self.scale[i] * perez(&self.coefficients[i], cos_theta, gamma));
+        xyy_to_linear_srgb(x, y, luminance) * self.intensity
+    }
+}
+
+/// Analytic daylight sky by Preetham et al. with a sun disc of finite size, +y is up.
+///
+/// Radiance is in kcd/m² times the intensity. The sun keeps the same illuminance for any size,
+/// larger suns give softer shadows. The ground below the horizon is black.
+#[derive(Clone, Debug)]
+pub struct Sky {
+    model: SkyModel,
+    sun_cos_theta_max: f32,
+    sun_sin2_theta_max: f32,
+    sun_radiance: Vec3,
+    /// Probability of sampling the sun rather than the sky, by their share of the power.
+    sun_probability: f32,
+    sky_map: EnvironmentMap,
+}
+
+impl Sky {
+    pub fn new(
+        sun_elevation_degrees: f32,
+        sun_azimuth_degrees: f32,
+        sun_size_degrees: f32,
+        turbidity: f32,
+        intensity: f32,
+    ) -> Self {
+        let elevation = sun_elevation_degrees.to_radians();
+        let azimuth = sun_azimuth_degrees.to_radians();
+        let sun_direction = Vec3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        );
+        // The model is only defined for the sun above the horizon.
+        let theta_sun = (FRAC_PI_2 - elevation).clamp(0.0, FRAC_PI_2);
+        let coefficients = perez_coefficients(turbidity);
+        let zenith = zenith(turbidity, theta_sun);
+        let scale = std::array::from_fn(|i| zenith[i] / perez(&coefficients[i], 1.0, theta_sun));
+        let model = SkyModel {
+            sun_direction,
+            coefficients,
+            scale,
+            intensity,
+        };
+
+        let (sun_sin_theta_max, sun_cos_theta_max) =
+            (0.5 * sun_size_degrees).to_radians().sin_cos();
+        let sun_sin2_theta_max = sun_sin_theta_max * sun_sin_theta_max;
+        let sun_solid_angle = 1.0 / uniform_cone_pdf(sun_sin2_theta_max);
+        let sun_radiance = if elevation > 0.0 {
+            sun_transmittance(turbidity, theta_sun)
+                * (SOLAR_ILLUMINANCE * intensity / sun_solid_angle)
+        } else {
+            Vec3::ZERO
+        };
+
+        let pixel_solid_angle = TAU * PI / (SAMPLING_MAP_SIZE.x * SAMPLING_MAP_SIZE.y) as f32;
+        let mut sky_power = 0.0;
+        let pixels = (0..SAMPLING_MAP_SIZE.y)
+            .flat_map(|y| (0..SAMPLING_MAP_SIZE.x).map(move |x| UVec2::new(x, y)))
+            .map(|pixel| {
+                let uv = (pixel.as_vec2() + 0.5) / SAMPLING_MAP_SIZE.as_vec2();
+                let (sin_theta, cos_theta) = (PI * uv.y).sin_cos();
+                let (sin_phi, cos_phi) = (TAU * uv.x).sin_cos();
+                let direction = Vec3::new(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi);
+                let radiance = model.radiance(direction);
+                sky_power += luminance(radiance) * sin_theta * pixel_solid_angle;
+                radiance
+            })
+            .collect();
+        let sky_map = EnvironmentMap::new(SAMPLING_MAP_SIZE, pixels, 0.0, 1.0);
+        let sun_power = luminance(sun_radiance) * sun_solid_angle;
+        let sun_probability = if sun_power > 0.0 {
+            sun_power / (sun_power + sky_power)
+        } else {
+            0.0
+        };
+
+        Self {
+            model,
+            sun_cos_theta_max,
+            sun_sin2_theta_max,
+            sun_radiance,
+            sun_probability,
+            sky_map,
+        }
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.model.sun_direction
+    }
+
+    #[inline]
+    fn in_sun(&self, direction: Vec3) -> bool {
+        direction.dot(self.model.sun_direction) >= self.sun_cos_theta_max
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let sun = if self.in_sun(direction) {
+            self.sun_radiance
+        } else {
+            Vec3::ZERO
+        };
+        self.model.radiance(direction) + sun
+    }
+
+    pub fn sample(&self, point: Vec3, sampler: &mut dyn Sampler) -> LightSample {
+        let direction = if sampler.get_1d() < self.sun_probability {
+            let local = uniform_sample_cone(sampler, self.sun_sin2_theta_max);
+            let (tangent, bitangent) = self.model.sun_direction.any_orthonormal_pair();
+            (local.x * tangent + local.y * bitangent + local.z * self.model.sun_direction)
+                .normalize()
+        } else {
+            self.sky_map.sample_direction(sampler)
+        };
+        LightSample {
+            is_delta: false,
+            pdf: self.pdf(direction),
+            radiance: self.radiance(direction),
+            shadow_ray: Ray::new(point, direction),
+            t_range: 0.0..=f32::MAX,
+        }
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let sun = if self.in_sun(direction) {
+            uniform_cone_pdf(self.sun_sin2_theta_max)
+        } else {
+            0.0
+        };
+        self.sun_probability * sun + (1.0 - self.sun_probability) * self.sky_map.pdf(direction)
+    }
+}
+
+impl From<&mtl::Sky> for Sky {
+    fn from(value: &mtl::Sky) -> Self {
+        Self::new(
+            value.sun_elevation,
+            value.sun_azimuth,
+            value.sun_size,
+            value.turbidity,
+            value.intensity,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use rand::{SeedableRng, rngs::SmallRng};
+
+    use super::*;
+
+    #[test]
+    fn zenith_luminance_matches_model() {
+        // Preetham et al. give 5.14 kcd/m² for turbidity 3 and the sun 60 degrees from zenith.
+        let sky = Sky::new(30.0, 0.0, 0.53, 3.0, 1.0);
+        assert_relative_eq!(luminance(sky.radiance(Vec3::Y)), 5.14, max_relative = 1e-2);
+    }
+
+    #[test]
+    fn clear_sky_is_blue_and_brightest_around_the_sun() {
+        let sky = Sky::new(30.0, 90.0, 0.53, 2.0, 1.0);
+        let zenith = sky.radiance(Vec3::Y);
+        assert!(zenith.z > zenith.x, "{zenith}");
+
+        let sun = sky.sun_direction();
+        let near_sun = (sun + Vec3::new(0.0, 0.1, 0.0)).normalize();
+        let away_from_sun = Vec3::new(sun.x, sun.y, -sun.z);
+        assert!(luminance(sky.radiance(near_sun)) > luminance(sky.radiance(away_from_sun)));
+        assert!(luminance(sky.radiance(sun)) > 1000.0 * luminance(sky.radiance(near_sun)));
+        assert_eq!(sky.radiance(-Vec3::Y), Vec3::ZERO);
+    }
+
+    #[test]
+    fn sun_below_horizon_is_not_sampled() {
+        let sky = Sky::new(-5.0, 0.0, 0.53, 3.0, 1.0);
+        assert_eq!(sky.sun_probability, 0.0);
+        assert_eq!(sky.sun_radiance, Vec3::ZERO);
+        assert_ne!(sky.radiance(Vec3::Y), Vec3::ZERO);
+    }
+
+    #[test]
+    fn sample_pdf_matches_pdf_and_radiance() {
+        let sky = Sky::new(20.0, 45.0, 5.0, 3.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut sun = 0;
+        for _ in 0..1000 {
+            let sample = sky.sample(Vec3::ZERO, &mut rng);
+            let direction = sample.shadow_ray.direction;
+            assert_relative_eq!(direction.length(), 1.0, epsilon = 1e-5);
+            assert_eq!(sample.pdf, sky.pdf(direction));
+            assert_eq!(sample.radiance, sky.radiance(direction));
+            assert!(direction.y > 0.0);
+            if sky.in_sun(direction) {
+                sun += 1;
+            }
+        }
+        assert_relative_eq!(sun as f32 / 1000.0, sky.sun_probability, epsilon = 0.05);
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let sky = Sky::new(20.0, 45.0, 10.0, 3.0, 1.0);
+        let size = UVec2::new(1024, 512);
+        let integral: f32 = (0..size.x * size.y)
+            .map(|i| {
+                let uv = (UVec2::new(i % size.x, i / size.x).as_vec2() + 0.5) / size.as_vec2();
+                let (sin_theta, cos_theta) = (PI * uv.y).sin_cos();
+                let (sin_phi, cos_phi) = (TAU * uv.x).sin_cos();
+                let direction = Vec3::new(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi);
+                sky.pdf(direction) * sin_theta * TAU * PI / (size.x * size.y) as f32
+            })
+            .sum();
+        assert_relative_eq!(integral, 1.0, epsilon = 1e-2);
+    }
+
+    #[test]
+    fn sun_illuminance_does_not_depend_on_size() {
+        let small = Sky::new(60.0, 0.0, 0.53, 3.0, 1.0);
+        let large = Sky::new(60.0, 0.0, 5.0, 3.0, 1.0);
+        let illuminance = |sky: &Sky| sky.sun_radiance / uniform_cone_pdf(sky.sun_sin2_theta_max);
+        assert_relative_eq!(
+            illuminance(&small),
+            illuminance(&large),
+            max_relative = 1e-3
+        );
+    }
+}
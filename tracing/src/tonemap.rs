@@ -0,0 +1,166 @@
+use glam::{Mat3, Vec3};
+
+use crate::material::luminance;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapping {
+    Clamp,
+    Reinhard,
+    /// Reinhard with the luminance that maps to white.
+    ExtendedReinhard {
+        white: f32,
+    },
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// Polynomial approximation of the AgX base look.
+    AgX,
+}
+
+impl ToneMapping {
+    /// Map linear scene radiance to linear display values in the range [0, 1].
+    pub fn apply(self, x: Vec3) -> Vec3 {
+        match self {
+            Self::Clamp => x,
+            Self::Reinhard => x / (1.0 + luminance(x)),
+            Self::ExtendedReinhard { white } => {
+                let l = luminance(x);
+                if l <= 0.0 {
+                    return Vec3::ZERO;
+                }
+                let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+                x * (mapped / l)
+            }
+            Self::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            Self::AgX => agx(x),
+        }
+        .clamp(Vec3::ZERO, Vec3::ONE)
+    }
+}
+
+fn agx(x: Vec3) -> Vec3 {
+    const INSET: Mat3 = Mat3::from_cols_array(&[
+        0.842_479_06,
+        0.042_328_242,
+        0.042_375_655,
+        0.078_433_6,
+        0.878_468_6,
+        0.078_433_6,
+        0.079_223_745,
+        0.079_166_13,
+        0.879_143,
+    ]);
+    const OUTSET: Mat3 = Mat3::from_cols_array(&[
+        1.196_879,
+        -0.052_896_85,
+        -0.052_971_635,
+        -0.098_020_88,
+        1.151_903_1,
+        -0.098_043_45,
+        -0.099_029_74,
+        -0.098_961_18,
+        1.151_073_6,
+    ]);
+    const MIN_EV: f32 = -12.473_93;
+    const MAX_EV: f32 = 4.026_069;
+
+    let encoded = (INSET * x.max(Vec3::splat(1e-10)))
+        .to_array()
+        .map(|c| (c.log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV))
+        .map(|x| {
+            let x2 = x * x;
+            let x4 = x2 * x2;
+            15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+                - 0.00232
+        });
+    // The curve output is display encoded, go back to linear for the sRGB transfer.
+    (OUTSET * Vec3::from_array(encoded))
+        .max(Vec3::ZERO)
+        .powf(2.2)
+}
+
+/// The sRGB opto-electronic transfer function (IEC 61966-2-1).
+pub fn srgb_oetf(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Conversion from accumulated linear radiance to display values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayTransform {
+    /// Exposure adjustment in stops (EV).
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapping: ToneMapping::Clamp,
+        }
+    }
+}
+
+impl DisplayTransform {
+    /// Returns sRGB encoded values in the range [0, 1].
+    pub fn apply(&self, x: Vec3) -> Vec3 {
+        let exposed = x * self.exposure.exp2();
+        let mapped = self.tone_mapping.apply(exposed);
+        Vec3::from_array(mapped.to_array().map(srgb_oetf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    const OPERATORS: [ToneMapping; 5] = [
+        ToneMapping::Clamp,
+        ToneMapping::Reinhard,
+        ToneMapping::ExtendedReinhard { white: 4.0 },
+        ToneMapping::Aces,
+        ToneMapping::AgX,
+    ];
+
+    #[test]
+    fn srgb_oetf_known_values() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert_abs_diff_eq!(srgb_oetf(0.001), 0.01292, epsilon = 1e-6);
+        assert_abs_diff_eq!(srgb_oetf(0.18), 0.461_356, epsilon = 1e-5);
+        assert_abs_diff_eq!(srgb_oetf(1.0), 1.0, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn operators_are_bounded_and_monotonic() {
+        for operator in OPERATORS {
+            let mut previous = -1.0;
+            for i in 0..=1000 {
+                let x = Vec3::splat(i as f32 * 0.05);
+                let y = operator.apply(x);
+                assert!(y.cmpge(Vec3::ZERO).all() && y.cmple(Vec3::ONE).all(), "{y}");
+                assert!(y.x >= previous, "{operator:?} at {x}");
+                previous = y.x;
+            }
+        }
+    }
+
+    #[test]
+    fn extended_reinhard_maps_white_to_one() {
+        let actual = ToneMapping::ExtendedReinhard { white: 4.0 }.apply(Vec3::splat(4.0));
+        assert_abs_diff_eq!(actual, Vec3::ONE, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let transform = DisplayTransform {
+            exposure: 1.0,
+            tone_mapping: ToneMapping::Clamp,
+        };
+        let actual = transform.apply(Vec3::splat(0.09));
+        assert_abs_diff_eq!(actual, Vec3::splat(srgb_oetf(0.18)), epsilon = 1e-6);
+    }
+}
@@ -1,12 +1,20 @@
-use image::RgbImage;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use std::{ops::Add, sync::mpsc::Sender, thread};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::Instant,
+};
 use time::Duration;
 
-use glam::UVec2;
-use rand::rngs::SmallRng;
+use glam::{UVec2, Vec2, Vec3};
 
 use crate::{
+    adaptive::{AdaptiveSampling, Tile},
     camera::Pinhole,
     collections::GeometryCollection,
     image_buffer::ImageBuffer,
@@ -24,96 +32,537 @@ fn create_ray_logger(thread: u32) -> RayLoggerWriter {
     }
 }
 
+/// Accumulated samples of a progressive render.
+#[derive(Clone)]
+pub struct Accumulation {
+    pub buffer: ImageBuffer,
+    pub iterations: u32,
+}
+
+impl Accumulation {
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            buffer: ImageBuffer::new(size),
+            iterations: 0,
+        }
+    }
+}
+
+/// Adaptive iterations are rendered in rounds of this many iterations. The tiles of a round are
+/// selected once every earlier iteration has been accumulated, so that they do not depend on the
+/// number of threads or their scheduling.
+const ADAPTIVE_ROUND: u32 = 4;
+
+/// First iteration of the adaptive round that `iteration` belongs to.
+const fn round_start(iteration: u32) -> u32 {
+    iteration - iteration % ADAPTIVE_ROUND
+}
+
+/// Adds iterations in index order no matter in which order they complete, floating point
+/// addition is not associative and the result should not depend on thread scheduling.
+struct OrderedAccumulation {
+    accumulation: Accumulation,
+    completed: BTreeMap<u32, ImageBuffer>,
+    adaptive: Option<AdaptiveSampling>,
+    /// First iteration of the current adaptive round and the tiles it renders.
+    round: Option<(u32, Vec<Tile>)>,
+}
+
+impl OrderedAccumulation {
+    /// A resumed render starting within a round selects the tiles of that round from `start`.
+    fn new(start: Accumulation, adaptive: Option<AdaptiveSampling>) -> Self {
+        let round = adaptive.map(|adaptive| {
+            let tiles = adaptive.select_tiles(&start.buffer, start.iterations);
+            (round_start(start.iterations), tiles)
+        });
+        Self {
+            accumulation: start,
+            completed: BTreeMap::new(),
+            adaptive,
+            round,
+        }
+    }
+
+    fn add(&mut self, iteration: u32, buffer: ImageBuffer) {
+        self.completed.insert(iteration, buffer);
+        while let Some(buffer) = self.completed.remove(&self.accumulation.iterations) {
+            self.accumulation.buffer += buffer;
+            self.accumulation.iterations += 1;
+        }
+        // No iteration of the next round can complete before its tiles are selected, so the
+        // accumulation stops exactly at the start of the round.
+        let iterations = self.accumulation.iterations;
+        if let Some(adaptive) = &self.adaptive
+            && round_start(iterations) == iterations
+            && self
+                .round
+                .as_ref()
+                .is_none_or(|(start, _)| *start != iterations)
+        {
+            let tiles = adaptive.select_tiles(&self.accumulation.buffer, iterations);
+            self.round = Some((iterations, tiles));
+        }
+    }
+
+    /// Whether the tiles for `iteration` are known, always true without adaptive sampling.
+    fn is_selected(&self, iteration: u32) -> bool {
+        self.adaptive.is_none()
+            || self
+                .round
+                .as_ref()
+                .is_some_and(|(start, _)| *start == round_start(iteration))
+    }
+
+    /// Tiles to render in `iteration`, `None` to render the full frame.
+    fn tiles(&self, iteration: u32) -> Option<Vec<Tile>> {
+        debug_assert!(self.is_selected(iteration));
+        self.round.as_ref().map(|(_, tiles)| tiles.clone())
+    }
+}
+
+/// Callback invoked with a snapshot of the accumulation at a fixed interval while rendering.
+pub struct CheckpointSchedule<'a> {
+    pub interval: std::time::Duration,
+    pub write: &'a mut dyn FnMut(&Accumulation),
+}
+
+/// When to stop a progressive render, the first target reached ends it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StopTarget {
+    /// Total number of iterations, including resumed ones.
+    pub iterations: Option<u32>,
+    /// Wall-clock budget, no new iterations are started after it has passed.
+    pub time: Option<std::time::Duration>,
+    /// Mean relative standard error of the pixel luminance, see [`ImageBuffer::relative_error`].
+    pub relative_error: Option<f32>,
+}
+
+/// Sent after every completed iteration of a progressive render.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// Time spent rendering the iteration.
+    pub duration: Duration,
+    /// Total number of accumulated iterations.
+    pub iterations: u32,
+    /// Wall-clock time since the render started.
+    pub elapsed: Duration,
+    /// Only estimated when a relative error target is set.
+    pub relative_error: Option<f32>,
+}
+
+impl StopTarget {
+    pub fn is_reached(&self, progress: &Progress) -> bool {
+        self.iterations.is_some_and(|n| progress.iterations >= n)
+            || self
+                .time
+                .is_some_and(|t| progress.elapsed.as_seconds_f64() >= t.as_secs_f64())
+            || self
+                .relative_error
+                .zip(progress.relative_error)
+                .is_some_and(|(target, error)| error <= target)
+    }
+
+    /// Estimated fraction of the render that is done, for the closest target.
+    pub fn fraction(&self, start_iterations: u32, progress: &Progress) -> f32 {
+        let rendered = progress.iterations.saturating_sub(start_iterations) as f32;
+        let iterations = self
+            .iterations
+            .map(|n| rendered / n.saturating_sub(start_iterations).max(1) as f32);
+        let time = self
+            .time
+            .map(|t| progress.elapsed.as_seconds_f32() / t.as_secs_f32());
+        let noise = self
+            .relative_error
+            .zip(progress.relative_error)
+            .map(|(target, error)| {
+                // The error decreases with the square root of the number of samples.
+                let needed = progress.iterations as f32 * (error / target).powi(2);
+                rendered / (needed - start_iterations as f32).max(1.0)
+            });
+        [iterations, time, noise]
+            .into_iter()
+            .flatten()
+            .fold(0.0, f32::max)
+            .min(1.0)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn render_iterations(
     thread: u32,
     pathtracer: &Pathtracer<impl GeometryCollection>,
     camera: &Pinhole,
-    size: UVec2,
-    iterations: u32,
-    tx: &Sender<Duration>,
-) -> ImageBuffer {
-    let mut rng: SmallRng = rand::make_rng();
-    let mut buffer = ImageBuffer::new(size);
+    target: &StopTarget,
+    started: Instant,
+    next_iteration: &AtomicU32,
+    done: &AtomicBool,
+    accumulation: &Mutex<OrderedAccumulation>,
+    round_selected: &Condvar,
+    tx: &Sender<Progress>,
+) {
+    let mut sampler = pathtracer.sampler.create(pathtracer.seed);
     let mut ray_logger = create_ray_logger(thread);
-    for iteration in 0..iterations {
-        let (duration, ()) = measure::measure(|| {
-            pathtracer.render_mut(
+    loop {
+        if done.load(Ordering::Relaxed) || target.time.is_some_and(|t| started.elapsed() >= t) {
+            return;
+        }
+        let iteration = next_iteration.fetch_add(1, Ordering::Relaxed);
+        if target.iterations.is_some_and(|n| iteration >= n) {
+            return;
+        }
+        let tiles = round_selected
+            .wait_while(accumulation.lock().unwrap(), |ordered| {
+                !ordered.is_selected(iteration)
+            })
+            .unwrap()
+            .tiles(iteration);
+        let mut buffer = ImageBuffer::new(camera.size);
+        let mut iteration_logger = ray_logger.with_iteration(iteration as u16);
+        let (duration, ()) = measure::measure(|| match &tiles {
+            Some(tiles) => {
+                for tile in tiles {
+                    pathtracer.render_subdivided_mut(
+                        camera,
+                        &mut iteration_logger,
+                        sampler.as_mut(),
+                        iteration,
+                        &mut buffer,
+                        *tile,
+                    );
+                }
+            }
+            None => pathtracer.render_mut(
                 camera,
-                &mut ray_logger.with_iteration(iteration as u16),
-                &mut rng,
+                &mut iteration_logger,
+                sampler.as_mut(),
+                iteration,
                 &mut buffer,
-            );
+            ),
         });
-        tx.send(duration).unwrap();
+        let progress = {
+            let mut ordered = accumulation.lock().unwrap();
+            ordered.add(iteration, buffer);
+            round_selected.notify_all();
+            let accumulation = &ordered.accumulation;
+            Progress {
+                duration,
+                iterations: accumulation.iterations,
+                elapsed: Duration::seconds_f64(started.elapsed().as_secs_f64()),
+                relative_error: target
+                    .relative_error
+                    .and_then(|_| accumulation.buffer.relative_error()),
+            }
+        };
+        if target.is_reached(&progress) {
+            done.store(true, Ordering::Relaxed);
+        }
+        tx.send(progress).unwrap();
     }
-    buffer
 }
 
+/// Render sample `sample_index` of every pixel, split into tiles of `sub_size` that are rendered
+/// in parallel. The samples are splatted in pixel order afterwards so that the image is the same
+/// as from [`Pathtracer::render_mut`] for any tile size.
 pub fn render_parallel_subdivided(
     pathtracer: &Pathtracer<impl GeometryCollection + Send + Sync>,
     pinhole: &Pinhole,
     sub_size: UVec2,
+    sample_index: u32,
 ) -> ImageBuffer {
     let count = pinhole.size / sub_size;
-    (0..count.x * count.y)
+    let tiles: Vec<Vec<(UVec2, Vec2, Vec3)>> = (0..count.x * count.y)
         .into_par_iter()
-        .fold(
-            || (rand::make_rng(), ImageBuffer::new(pinhole.size)),
-            |(mut rng, mut buffer), i| {
-                let pixel = UVec2::new(i % count.x * sub_size.x, i / count.x * sub_size.y);
-                let mut ray_logger = RayLoggerWithIteration {
-                    writer: &mut RayLoggerWriter::None,
-                    iteration: 0,
-                };
-                pathtracer.render_subdivided_mut(
-                    pinhole,
-                    &mut ray_logger,
-                    &mut rng,
-                    &mut buffer,
-                    pixel,
-                    sub_size,
-                );
-                (rng, buffer)
-            },
-        )
-        .map(|(_, buffer)| buffer)
-        .reduce_with(Add::add)
-        .unwrap()
+        .map(|i| {
+            let tile = Tile {
+                start: UVec2::new(i % count.x * sub_size.x, i / count.x * sub_size.y),
+                size: sub_size,
+            };
+            let mut sampler = pathtracer.sampler.create(pathtracer.seed);
+            let mut ray_logger = RayLoggerWithIteration {
+                writer: &mut RayLoggerWriter::None,
+                iteration: 0,
+            };
+            (0..tile.size.y)
+                .flat_map(|y| (0..tile.size.x).map(move |x| tile.start + UVec2::new(x, y)))
+                .map(|pixel| {
+                    let (position, value) = pathtracer.sample_pixel(
+                        pinhole,
+                        pixel,
+                        &mut ray_logger,
+                        sampler.as_mut(),
+                        sample_index,
+                    );
+                    (pixel, position, value)
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut buffer = ImageBuffer::new(pinhole.size);
+    let mut samples = vec![None; (pinhole.size.x * pinhole.size.y) as usize];
+    for (pixel, position, value) in tiles.into_iter().flatten() {
+        samples[(pixel.y * pinhole.size.x + pixel.x) as usize] = Some((position, value));
+    }
+    for (position, value) in samples.into_iter().flatten() {
+        buffer.splat(&pathtracer.filter, position, value);
+    }
+    buffer
 }
 
+/// Render on `threads` threads until `start` has accumulated `total_iterations` iterations.
+#[allow(clippy::too_many_arguments)]
 pub fn render_parallel_iterations(
     pathtracer: &Pathtracer<impl GeometryCollection + Send + Sync>,
     camera: &Pinhole,
-    size: UVec2,
     threads: u32,
-    iterations_per_thread: u32,
-    tx: Sender<Duration>,
-) -> (Duration, RgbImage) {
-    let total_iterations = threads * iterations_per_thread;
-    let result = thread::scope(|s| {
-        let (duration, buffer) = measure::measure(|| {
-            let threads = (0..threads)
-                .map(|i| {
-                    let tx = &tx;
-                    let pathtracer = &pathtracer;
-                    let camera = &camera;
-                    s.spawn(move || {
-                        render_iterations(i, pathtracer, camera, size, iterations_per_thread, tx)
-                    })
-                })
-                .collect::<Vec<_>>();
+    target: &StopTarget,
+    adaptive: Option<&AdaptiveSampling>,
+    start: Accumulation,
+    mut checkpoint: Option<CheckpointSchedule>,
+    tx: Sender<Progress>,
+) -> (Duration, Accumulation) {
+    let started = Instant::now();
+    let next_iteration = AtomicU32::new(start.iterations);
+    let done = AtomicBool::new(false);
+    let accumulation = Mutex::new(OrderedAccumulation::new(start, adaptive.copied()));
+    let round_selected = Condvar::new();
+    let (duration, ()) = measure::measure(|| {
+        thread::scope(|s| {
+            let (done_tx, done_rx) = mpsc::channel::<()>();
+            for i in 0..threads {
+                let done_tx = done_tx.clone();
+                let tx = &tx;
+                let next_iteration = &next_iteration;
+                let done = &done;
+                let accumulation = &accumulation;
+                let round_selected = &round_selected;
+                s.spawn(move || {
+                    render_iterations(
+                        i,
+                        pathtracer,
+                        camera,
+                        target,
+                        started,
+                        next_iteration,
+                        done,
+                        accumulation,
+                        round_selected,
+                        tx,
+                    );
+                    drop(done_tx);
+                });
+            }
+            drop(done_tx);
 
-            let buffers = threads.into_iter().map(|t| t.join().unwrap());
-            buffers.reduce(Add::add).unwrap()
+            // The channel disconnects when all render threads are done.
+            while let Some(checkpoint) = &mut checkpoint {
+                match done_rx.recv_timeout(checkpoint.interval) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        let snapshot = accumulation.lock().unwrap().accumulation.clone();
+                        (checkpoint.write)(&snapshot);
+                    }
+                    _ => break,
+                }
+            }
+            let _ = done_rx.recv();
         });
-
-        let image = RgbImage::from_raw(
-            buffer.size.x,
-            buffer.size.y,
-            buffer.to_rgb8(total_iterations as u16),
-        );
-        (duration, image.unwrap())
     });
     drop(tx);
-    result
+    (duration, accumulation.into_inner().unwrap().accumulation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        collections::SphereCollection,
+        filter::PixelFilter,
+        light::{DirectionalLight, Light, PointLight},
+        light_sampler::{LightSampler, LightSelection},
+        material::{Material, albedo::AlbedoSource},
+        properties::SphereProperties,
+        sampler::SamplerKind,
+        sampling::RussianRoulette,
+    };
+    use geometry::sphere::Sphere;
+
+    fn scene(seed: u64) -> (Pinhole, Pathtracer<SphereCollection>) {
+        let camera = Camera::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::ZERO, Vec3::Z, 40.0);
+        let spheres = vec![
+            Sphere::new([0.0, -0.6, 0.0], 0.5),
+            Sphere::new([0.0, 0.6, 0.0], 0.5),
+        ];
+        let properties = (0..spheres.len())
+            .map(|material| SphereProperties {
+                material,
+                radius: 0.5,
+            })
+            .collect();
+        let materials = vec![
+            Material {
+                albedo: AlbedoSource::Color(Vec3::new(0.8, 0.2, 0.2)),
+                schlick_f0: Vec3::splat(0.04),
+                transmission: 0.0,
+                ior: 1.5,
+                emittance: Vec3::ZERO,
+                roughness: 0.3,
+            },
+            Material {
+                albedo: AlbedoSource::Color(Vec3::ONE),
+                schlick_f0: Vec3::splat(0.04),
+                transmission: 1.0,
+                ior: 1.5,
+                emittance: Vec3::ZERO,
+                roughness: 0.0,
+            },
+        ];
+        let pathtracer = Pathtracer {
+            max_bounces: 4,
+            russian_roulette: RussianRoulette::default(),
+            filter: PixelFilter::gaussian(),
+            sampler: SamplerKind::Sobol,
+            seed,
+            geometry_collection: SphereCollection {
+                spheres,
+                properties,
+                materials,
+            },
+            lights: LightSampler::new(
+                vec![
+                    Light::from(DirectionalLight {
+                        direction: Vec3::new(1.0, 1.0, -1.0).normalize(),
+                        intensity: Vec3::ONE,
+                    }),
+                    Light::from(PointLight {
+                        center: Vec3::new(0.0, 0.0, 3.0),
+                        intensity: Vec3::splat(2.0),
+                    }),
+                ],
+                LightSelection::Tree,
+            ),
+            environment: Vec3::splat(0.5).into(),
+        };
+        (Pinhole::new(camera, UVec2::new(16, 16)), pathtracer)
+    }
+
+    fn render_iterations_on(
+        threads: u32,
+        seed: u64,
+        adaptive: Option<&AdaptiveSampling>,
+        iterations: u32,
+    ) -> ImageBuffer {
+        let (pinhole, pathtracer) = scene(seed);
+        let target = StopTarget {
+            iterations: Some(iterations),
+            ..StopTarget::default()
+        };
+        let (tx, _rx) = mpsc::channel();
+        let (_, accumulation) = render_parallel_iterations(
+            &pathtracer,
+            &pinhole,
+            threads,
+            &target,
+            adaptive,
+            Accumulation::new(pinhole.size),
+            None,
+            tx,
+        );
+        assert_eq!(accumulation.iterations, iterations);
+        accumulation.buffer
+    }
+
+    #[test]
+    fn iterations_are_identical_for_any_thread_count() {
+        let single = render_iterations_on(1, 7, None, 6);
+        let multi = render_iterations_on(4, 7, None, 6);
+        assert_eq!(single.pixels(), multi.pixels());
+        assert_eq!(single.weights(), multi.weights());
+        assert_eq!(single.first_moments(), multi.first_moments());
+        assert_eq!(single.second_moments(), multi.second_moments());
+        assert_ne!(
+            single.pixels(),
+            render_iterations_on(1, 8, None, 6).pixels()
+        );
+    }
+
+    #[test]
+    fn adaptive_iterations_are_identical_for_any_thread_count() {
+        let adaptive = AdaptiveSampling {
+            tile_size: 4,
+            uniform_iterations: 4,
+            fraction: 0.25,
+        };
+        let single = render_iterations_on(1, 7, Some(&adaptive), 16);
+        for threads in [2, 3, 8] {
+            let multi = render_iterations_on(threads, 7, Some(&adaptive), 16);
+            assert_eq!(single.pixels(), multi.pixels());
+            assert_eq!(single.weights(), multi.weights());
+            assert_eq!(single.samples(), multi.samples());
+        }
+        // Only some of the tiles were rendered after the uniform iterations.
+        let samples = single.samples();
+        assert!(samples.iter().min() < samples.iter().max());
+    }
+
+    #[test]
+    fn subdivided_matches_full_frame_for_any_tile_size() {
+        let (pinhole, pathtracer) = scene(3);
+        let mut full = ImageBuffer::new(pinhole.size);
+        let mut ray_logger = RayLoggerWithIteration {
+            writer: &mut RayLoggerWriter::None,
+            iteration: 0,
+        };
+        let mut sampler = pathtracer.sampler.create(pathtracer.seed);
+        pathtracer.render_mut(&pinhole, &mut ray_logger, sampler.as_mut(), 5, &mut full);
+        for sub_size in [UVec2::new(4, 4), UVec2::new(8, 2), UVec2::new(16, 16)] {
+            let subdivided = render_parallel_subdivided(&pathtracer, &pinhole, sub_size, 5);
+            assert_eq!(full.pixels(), subdivided.pixels(), "{sub_size}");
+            assert_eq!(full.weights(), subdivided.weights(), "{sub_size}");
+        }
+    }
+
+    fn progress(iterations: u32, seconds: f64, relative_error: Option<f32>) -> Progress {
+        Progress {
+            duration: Duration::SECOND,
+            iterations,
+            elapsed: Duration::seconds_f64(seconds),
+            relative_error,
+        }
+    }
+
+    #[test]
+    fn first_target_reached_stops() {
+        let target = StopTarget {
+            iterations: Some(10),
+            time: Some(std::time::Duration::from_secs(60)),
+            relative_error: Some(0.01),
+        };
+        assert!(!target.is_reached(&progress(5, 30.0, Some(0.02))));
+        assert!(target.is_reached(&progress(10, 30.0, Some(0.02))));
+        assert!(target.is_reached(&progress(5, 60.0, Some(0.02))));
+        assert!(target.is_reached(&progress(5, 30.0, Some(0.01))));
+        assert!(!StopTarget::default().is_reached(&progress(100, 100.0, None)));
+    }
+
+    #[test]
+    fn fraction_follows_closest_target() {
+        let target = StopTarget {
+            iterations: Some(12),
+            time: Some(std::time::Duration::from_secs(100)),
+            relative_error: None,
+        };
+        assert_eq!(target.fraction(2, &progress(7, 10.0, None)), 0.5);
+        assert_eq!(target.fraction(2, &progress(3, 80.0, None)), 0.8);
+    }
+
+    #[test]
+    fn fraction_predicts_samples_for_relative_error() {
+        let target = StopTarget {
+            relative_error: Some(0.05),
+            ..StopTarget::default()
+        };
+        // Halving the error takes four times the samples.
+        assert_eq!(target.fraction(0, &progress(4, 1.0, Some(0.1))), 0.25);
+    }
 }
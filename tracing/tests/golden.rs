@@ -0,0 +1,181 @@
+//! Renders the scenes in `resources` at a low resolution with a fixed seed and compares them to
+//! the reference images in `tests/golden`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to write new references after an intended change to the output. On
+//! failure the render and a heatmap of the perceptual difference are written to the cargo target
+//! temporary directory.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use glam::{UVec2, Vec3};
+use image::RgbImage;
+use kdtree::{build::build_kdtree, sah::SahCost};
+use tracing::{
+    camera::Pinhole,
+    collections::TriangleCollection,
+    filter::PixelFilter,
+    image_compare::{ImageDifference, compare},
+    light::{Light, TriangleLight},
+    light_sampler::{LightSampler, LightSelection},
+    material::Material,
+    pathtracer::Pathtracer,
+    properties::{DEFAULT_CREASE_ANGLE, from_wavefront},
+    sampler::SamplerKind,
+    sampling::RussianRoulette,
+    tonemap::DisplayTransform,
+    worker::{Accumulation, StopTarget, render_parallel_iterations},
+};
+use wavefront::{ParseMode, read_obj_and_mtl_with_print_logging};
+
+const SIZE: UVec2 = UVec2::new(64, 64);
+const ITERATIONS: u32 = 16;
+const SEED: u64 = 0;
+
+/// Loose enough for differences in floating point rounding between platforms that make a few
+/// paths diverge, strict enough to catch changes to materials, lights or intersections.
+const TOLERANCE: ImageDifference = ImageDifference {
+    rmse: 0.01,
+    flip: 0.02,
+    max_error: 0.25,
+};
+
+fn render(scene: &Path) -> RgbImage {
+    let (obj, mtl, mtl_path) =
+        read_obj_and_mtl_with_print_logging(scene, ParseMode::Strict).unwrap();
+    let (triangles, properties) = from_wavefront(&obj, &mtl, DEFAULT_CREASE_ANGLE);
+    let kdtree = build_kdtree(&triangles, &SahCost::default());
+    let camera = Pinhole::new(mtl.cameras[0].clone().into(), SIZE);
+    let image_directory = mtl_path.parent().unwrap();
+    let materials: Vec<Material> = mtl
+        .materials
+        .iter()
+        .map(|m| Material::load_from_mtl(image_directory, m))
+        .collect();
+    let mut lights: Vec<Light> = mtl.lights.iter().map(Light::from).collect();
+    lights.extend(
+        TriangleLight::from_mesh(&triangles, &properties, &materials)
+            .into_iter()
+            .map(Light::from),
+    );
+    let pathtracer = Pathtracer {
+        max_bounces: 10,
+        russian_roulette: RussianRoulette::default(),
+        filter: PixelFilter::default(),
+        sampler: SamplerKind::default(),
+        seed: SEED,
+        geometry_collection: TriangleCollection {
+            triangles,
+            properties,
+            materials,
+            kdtree,
+        },
+        lights: LightSampler::new(lights, LightSelection::default()),
+        environment: Vec3::new(0.8, 0.8, 0.8).into(),
+    };
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get() as u32);
+    let target = StopTarget {
+        iterations: Some(ITERATIONS),
+        ..StopTarget::default()
+    };
+    let (tx, _rx) = mpsc::channel();
+    let (_, accumulation) = render_parallel_iterations(
+        &pathtracer,
+        &camera,
+        threads,
+        &target,
+        None,
+        Accumulation::new(SIZE),
+        None,
+        tx,
+    );
+    accumulation
+        .buffer
+        .to_rgb_image(&DisplayTransform::default())
+}
+
+fn check(name: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let scene = root.join("../resources").join(format!("{name}.obj"));
+    let reference_path = root.join("tests/golden").join(format!("{name}.png"));
+    let image = render(&scene);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        image.save(&reference_path).unwrap();
+        return;
+    }
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "Failed to read {}, run with UPDATE_GOLDEN=1 to create it: {e}",
+                reference_path.display()
+            )
+        })
+        .into_rgb8();
+
+    let comparison = compare(&reference, &image);
+    if !comparison.difference.is_within(&TOLERANCE) {
+        let output = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        std::fs::create_dir_all(&output).unwrap();
+        let render_path = output.join(format!("{name}.png"));
+        let diff_path = output.join(format!("{name}-diff.png"));
+        image.save(&render_path).unwrap();
+        comparison.heatmap().save(&diff_path).unwrap();
+        panic!(
+            "{name} differs from the reference: {:?}, tolerance {:?}\n  render: {}\n  diff: {}",
+            comparison.difference,
+            TOLERANCE,
+            render_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+fn cornell() {
+    check("cornell");
+}
+
+#[test]
+fn cornell_textured() {
+    check("cornell_textured");
+}
+
+#[test]
+fn cube() {
+    check("cube");
+}
+
+#[test]
+#[ignore = "slow in debug builds, run with --release -- --ignored"]
+fn cornell_teapot() {
+    check("cornell_teapot");
+}
+
+#[test]
+#[ignore = "slow in debug builds, run with --release -- --ignored"]
+fn cornellbottle2() {
+    check("cornellbottle2");
+}
+
+#[test]
+#[ignore = "slow in debug builds, run with --release -- --ignored"]
+fn cubesplosion() {
+    check("cubesplosion");
+}
+
+#[test]
+#[ignore = "slow in debug builds, run with --release -- --ignored"]
+fn sr2() {
+    check("sr2");
+}
+
+#[test]
+#[ignore = "slow in debug builds, run with --release -- --ignored"]
+fn teapot() {
+    check("teapot");
+}
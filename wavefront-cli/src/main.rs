@@ -1,5 +1,5 @@
 use std::{env, fs::File, io::BufReader, path::Path};
-use wavefront::{mtl, obj};
+use wavefront::{ParseMode, mtl, obj};
 
 fn main() {
     for arg in env::args().skip(1) {
@@ -7,8 +7,8 @@ fn main() {
         let file = File::open(path).unwrap();
         let mut buf = BufReader::new(file);
         match path.extension().and_then(|s| s.to_str()) {
-            Some("obj") => println!("{:#?}", obj::obj(&mut buf)),
-            Some("mtl") => println!("{:#?}", mtl::mtl(&mut buf)),
+            Some("obj") => println!("{:#?}", obj::obj(&mut buf, ParseMode::Lenient)),
+            Some("mtl") => println!("{:#?}", mtl::mtl(&mut buf, ParseMode::Lenient)),
             _ => panic!("Unexpected file extension for {path:?}"),
         }
     }
@@ -0,0 +1,239 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// How statements that cannot be used are handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail on the first problem.
+    Strict,
+    /// Skip the statement and keep the problem as a warning.
+    #[default]
+    Lenient,
+}
+
+impl ParseMode {
+    pub(crate) fn report(self, error: Error, warnings: &mut Vec<Error>) -> Result<(), Error> {
+        match self {
+            Self::Strict => Err(error),
+            Self::Lenient => {
+                warnings.push(error);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(io::Error),
+    UnknownKeyword,
+    /// A known keyword with a value that could not be parsed.
+    InvalidValue,
+    /// A statement that belongs to a block, such as `Kd` to `newmtl`, before the first such block.
+    OutsideBlock(&'static str),
+    /// A `usemtl` statement naming a material that the material library does not define.
+    UnknownMaterial,
+}
+
+impl PartialEq for ErrorKind {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Io(a), Self::Io(b)) => a.kind() == b.kind(),
+            (Self::OutsideBlock(a), Self::OutsideBlock(b)) => a == b,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+/// Problem in a Wavefront file. Lines and columns start at 1, zero when the problem is not tied
+/// to a line such as failing to open the file. Columns count characters, not bytes.
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    /// The offending part of the line.
+    pub text: String,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    /// Problem with `text`, which must be a slice of `line`.
+    pub(crate) fn at(line_number: usize, line: &str, text: &str, kind: ErrorKind) -> Self {
+        let offset = offset_in(line, text);
+        Self {
+            file: None,
+            line: line_number,
+            column: line[..offset].chars().count() + 1,
+            text: text.to_owned(),
+            kind,
+        }
+    }
+
+    /// Problem with `text` in a statement joined from several physical lines. Each physical line
+    /// starts at a byte offset into `statement` given in `lines` together with its line number.
+    pub(crate) fn at_joined(
+        lines: &[(usize, usize)],
+        statement: &str,
+        text: &str,
+        kind: ErrorKind,
+    ) -> Self {
+        let offset = offset_in(statement, text);
+        let (start, line_number) = lines
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= offset)
+            .copied()
+            .unwrap_or((0, 0));
+        Self::at(line_number, &statement[start..], text, kind)
+    }
+
+    pub(crate) fn io(line_number: usize, error: io::Error) -> Self {
+        Self {
+            file: None,
+            line: line_number,
+            column: 0,
+            text: String::new(),
+            kind: ErrorKind::Io(error),
+        }
+    }
+
+    #[must_use]
+    pub fn with_file(self, file: &Path) -> Self {
+        Self {
+            file: Some(file.to_owned()),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if self.line > 0 {
+            write!(f, "{}:", self.line)?;
+        }
+        if self.column > 0 {
+            write!(f, "{}:", self.column)?;
+        }
+        if self.file.is_some() || self.line > 0 {
+            write!(f, " ")?;
+        }
+        match &self.kind {
+            ErrorKind::Io(error) => write!(f, "{error}"),
+            ErrorKind::UnknownKeyword => write!(f, "unknown keyword \"{}\"", self.text),
+            ErrorKind::InvalidValue => write!(f, "invalid value \"{}\"", self.text),
+            ErrorKind::OutsideBlock(block) => {
+                write!(f, "\"{}\" before the first {block}", self.text)
+            }
+            ErrorKind::UnknownMaterial => write!(f, "unknown material \"{}\"", self.text),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match &error.kind {
+            ErrorKind::Io(error) => error.kind(),
+            _ => io::ErrorKind::InvalidData,
+        };
+        Self::new(kind, error)
+    }
+}
+
+/// Byte offset of `text`, which must be a slice of `line`.
+fn offset_in(line: &str, text: &str) -> usize {
+    let offset = text.as_ptr() as usize - line.as_ptr() as usize;
+    debug_assert!(offset + text.len() <= line.len());
+    offset
+}
+
+/// Read the next line into `line`, counting lines in `line_number`. Returns false at the end.
+pub(crate) fn read_line<R: io::BufRead>(
+    input: &mut R,
+    line: &mut String,
+    line_number: &mut usize,
+) -> Result<bool, Error> {
+    *line_number += 1;
+    let read = input
+        .read_line(line)
+        .map_err(|error| Error::io(*line_number, error))?;
+    Ok(read > 0)
+}
+
+/// The problem with a statement that is neither handled nor malformed for a known keyword.
+pub(crate) fn unhandled<'a>(statement: &'a str, keywords: &[&str]) -> (&'a str, ErrorKind) {
+    let keyword = statement.split_whitespace().next().unwrap_or(statement);
+    if keywords.contains(&keyword) {
+        let value = statement[keyword.len()..].trim_start();
+        let text = if value.is_empty() { statement } else { value };
+        (text, ErrorKind::InvalidValue)
+    } else {
+        (keyword, ErrorKind::UnknownKeyword)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_location() {
+        let line = "  Kd 1 x 3";
+        let error = Error::at(12, line, &line[5..], ErrorKind::InvalidValue)
+            .with_file(Path::new("scene.mtl"));
+        assert_eq!(error.column, 6);
+        assert_eq!(error.to_string(), "scene.mtl:12:6: invalid value \"1 x 3\"");
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let line = "newmtl läder\tKd x";
+        let error = Error::at(1, line, &line[line.len() - 1..], ErrorKind::InvalidValue);
+        assert_eq!(error.column, 17);
+    }
+
+    #[test]
+    fn joined_lines_report_the_physical_line() {
+        let statement = "f 1 \n  2 99 \n 3";
+        let lines = [(0, 4), (5, 5), (13, 6)];
+        let error = Error::at_joined(
+            &lines,
+            statement,
+            &statement[9..11],
+            ErrorKind::InvalidValue,
+        );
+        assert_eq!(
+            (error.line, error.column, error.text.as_str()),
+            (5, 5, "99")
+        );
+        let error = Error::at_joined(&lines, statement, &statement[2..3], ErrorKind::InvalidValue);
+        assert_eq!((error.line, error.column), (4, 3));
+    }
+
+    #[test]
+    fn unhandled_statements() {
+        assert_eq!(
+            unhandled("Kd 1 x", &["Kd"]),
+            ("1 x", ErrorKind::InvalidValue)
+        );
+        assert_eq!(unhandled("Kd", &["Kd"]), ("Kd", ErrorKind::InvalidValue));
+        assert_eq!(
+            unhandled("Kx 1", &["Kd"]),
+            ("Kx", ErrorKind::UnknownKeyword)
+        );
+    }
+}
@@ -1,18 +1,66 @@
 use std::{
     fs::File,
-    io::{BufReader, Error},
+    io::BufReader,
     path::{Path, PathBuf},
 };
 
+mod error;
 pub mod mtl;
 pub mod obj;
+pub mod triangulate;
 
+pub use error::{Error, ErrorKind, ParseMode};
+
+fn open(path: &Path) -> Result<BufReader<File>, Error> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|error| Error::io(0, error).with_file(path))
+}
+
+/// Check that the materials used by `obj` are defined in `mtl`, in lenient mode undefined ones are
+/// added with default values.
+fn check_materials(
+    obj: &obj::Obj,
+    mtl: &mut mtl::Mtl,
+    mode: ParseMode,
+    warnings: &mut Vec<Error>,
+) -> Result<(), Error> {
+    for chunk in &obj.chunks {
+        if mtl.materials.iter().any(|m| m.name == chunk.material) {
+            continue;
+        }
+        let error = Error {
+            file: None,
+            line: chunk.line,
+            column: 0,
+            text: chunk.material.clone(),
+            kind: ErrorKind::UnknownMaterial,
+        };
+        mode.report(error, warnings)?;
+        mtl.materials
+            .push(mtl::Material::new(chunk.material.clone()));
+    }
+    Ok(())
+}
+
+fn print_warnings(path: &Path, warnings: &mut [Error]) {
+    for warning in warnings {
+        warning.file = Some(path.to_owned());
+        println!("  Warning: {warning}");
+    }
+}
+
+/// Read an OBJ file and the material library it refers to. Faces may only use materials from the
+/// library.
 pub fn read_obj_and_mtl_with_print_logging(
     path: &Path,
+    mode: ParseMode,
 ) -> Result<(obj::Obj, mtl::Mtl, PathBuf), Error> {
     println!("Loading {}...", path.display());
-    let obj = obj::obj(&mut BufReader::new(File::open(path)?))?;
+    let mut obj = obj::obj(&mut open(path)?, mode).map_err(|error| error.with_file(path))?;
+    print_warnings(path, &mut obj.warnings);
     println!("  Chunks: {}", obj.chunks.len());
+    println!("  Objects: {}", obj.objects.len());
     println!("  Vertices: {}", obj.vertices.len());
     println!("  Normals: {}", obj.normals.len());
     println!("  Texcoords: {}", obj.texcoords.len());
@@ -21,10 +69,44 @@ pub fn read_obj_and_mtl_with_print_logging(
         .parent()
         .map_or(obj.mtl_lib.clone(), |p| p.join(&obj.mtl_lib));
     println!("Loading {}...", mtl_path.display());
-    let mtl = mtl::mtl(&mut BufReader::new(File::open(&mtl_path)?))?;
+    let mut mtl =
+        mtl::mtl(&mut open(&mtl_path)?, mode).map_err(|error| error.with_file(&mtl_path))?;
+    print_warnings(&mtl_path, &mut mtl.warnings);
     println!("  Materials: {}", mtl.materials.len());
     println!("  Lights: {}", mtl.lights.len());
     println!("  Cameras: {}", mtl.cameras.len());
 
+    let mut warnings = Vec::new();
+    check_materials(&obj, &mut mtl, mode, &mut warnings).map_err(|error| error.with_file(path))?;
+    print_warnings(path, &mut warnings);
+
     Ok((obj, mtl, mtl_path))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_materials() {
+        let obj = obj::obj(
+            &mut "usemtl known\nusemtl missing\nusemtl missing\n".as_bytes(),
+            ParseMode::Strict,
+        )
+        .unwrap();
+        let parse_mtl = || mtl::mtl(&mut "newmtl known".as_bytes(), ParseMode::Strict).unwrap();
+
+        let mut warnings = Vec::new();
+        let error =
+            check_materials(&obj, &mut parse_mtl(), ParseMode::Strict, &mut warnings).unwrap_err();
+        assert_eq!((error.line, error.text.as_str()), (2, "missing"));
+        assert_eq!(error.kind, ErrorKind::UnknownMaterial);
+        assert_eq!(error.to_string(), "2: unknown material \"missing\"");
+
+        let mut mtl = parse_mtl();
+        check_materials(&obj, &mut mtl, ParseMode::Lenient, &mut warnings).unwrap();
+        assert_eq!(warnings.len(), 1);
+        let names: Vec<_> = mtl.materials.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["known", "missing"]);
+    }
+}
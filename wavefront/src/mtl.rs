@@ -1,9 +1,15 @@
 use nom::{
-    IResult, bytes::complete::tag, character::complete::space0, combinator::rest,
+    IResult, Parser,
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::space0,
+    combinator::{rest, value},
     number::complete::float,
 };
 use std::io::BufRead;
 
+use crate::error::{Error, ErrorKind, ParseMode, read_line, unhandled};
+
 #[derive(Debug, PartialEq)]
 pub struct Material {
     pub name: String,
@@ -16,6 +22,8 @@ pub struct Material {
     pub reflection_90_degrees: f32,
     pub index_of_refraction: f32,
     pub metalness: f32,
+    pub specular_exponent: Option<f32>,
+    pub specular_roughness: Option<f32>,
 }
 
 impl Material {
@@ -31,16 +39,45 @@ impl Material {
             reflection_90_degrees: 0.0,
             index_of_refraction: 1.0,
             metalness: 0.0,
+            specular_exponent: None,
+            specular_roughness: None,
         }
     }
 }
 
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum LightKind {
+    #[default]
+    Sphere,
+    /// Parallelogram centered at the position and spanned by the two edges.
+    Quad,
+    /// Point light shining in a direction, angles are half-angles in degrees.
+    Spot,
+}
+
 #[derive(Debug, Default, PartialEq)]
 pub struct Light {
+    pub kind: LightKind,
     pub position: [f32; 3],
     pub color: [f32; 3],
     pub radius: f32,
     pub intensity: f32,
+    pub edge1: [f32; 3],
+    pub edge2: [f32; 3],
+    /// Quad lights only emit towards `edge1 x edge2` when set.
+    pub one_sided: bool,
+    pub direction: [f32; 3],
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+fn light_kind(input: &str) -> IResult<&str, LightKind> {
+    alt((
+        value(LightKind::Sphere, tag("sphere")),
+        value(LightKind::Quad, tag("quad")),
+        value(LightKind::Spot, tag("spot")),
+    ))
+    .parse(input)
 }
 
 #[derive(Debug, Default, PartialEq, Clone)]
@@ -49,6 +86,31 @@ pub struct Camera {
     pub target: [f32; 3],
     pub up: [f32; 3],
     pub fov: f32,
+    pub aperture: f32,
+    pub focus: f32,
+}
+
+/// Analytic daylight sky with a sun, angles are in degrees.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Sky {
+    pub sun_elevation: f32,
+    pub sun_azimuth: f32,
+    /// Angular diameter of the sun disc.
+    pub sun_size: f32,
+    pub turbidity: f32,
+    pub intensity: f32,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self {
+            sun_elevation: 45.0,
+            sun_azimuth: 0.0,
+            sun_size: 0.53,
+            turbidity: 3.0,
+            intensity: 1.0,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -56,6 +118,9 @@ pub struct Mtl {
     pub materials: Vec<Material>,
     pub lights: Vec<Light>,
     pub cameras: Vec<Camera>,
+    pub sky: Option<Sky>,
+    /// Problems with statements that were skipped in lenient mode.
+    pub warnings: Vec<Error>,
 }
 
 fn tagged<'a, O>(
@@ -77,83 +142,176 @@ fn vec3(input: &str) -> IResult<&str, [f32; 3]> {
     Ok((input, [x, y, z]))
 }
 
-pub fn mtl<R>(input: &mut R) -> std::io::Result<Mtl>
+const KEYWORDS: &[&str] = &[
+    "newlight",
+    "lightposition",
+    "lightcolor",
+    "lightradius",
+    "lightintensity",
+    "lighttype",
+    "lightedge1",
+    "lightedge2",
+    "lightonesided",
+    "lightdirection",
+    "lightinnerangle",
+    "lightouterangle",
+    "newcamera",
+    "cameraposition",
+    "cameratarget",
+    "cameraup",
+    "camerafov",
+    "cameraaperture",
+    "camerafocus",
+    "newsky",
+    "skysunelevation",
+    "skysunazimuth",
+    "skysunsize",
+    "skyturbidity",
+    "skyintensity",
+    "newmtl",
+    "illum",
+    "Ka",
+    "Kd",
+    "map_Kd",
+    "Ks",
+    "Ns",
+    "Ke",
+    "reflat0deg",
+    "reflat90deg",
+    "Ni",
+    "d",
+    "Tr",
+    "Pm",
+    "specularroughness",
+];
+
+/// The last block started by `block`, which `statement` belongs to.
+fn last<'a, 'b, T>(
+    blocks: &'a mut [T],
+    block: &'static str,
+    statement: &'b str,
+) -> Result<&'a mut T, (&'b str, ErrorKind)> {
+    blocks
+        .last_mut()
+        .ok_or((statement, ErrorKind::OutsideBlock(block)))
+}
+
+fn statement<'a>(mtl: &mut Mtl, statement: &'a str) -> Result<(), (&'a str, ErrorKind)> {
+    if let Ok((_, _)) = tagged("newlight", rest, statement) {
+        mtl.lights.push(Light::default());
+    } else if let Ok((_, x)) = tagged("lightposition", vec3, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.position = x;
+    } else if let Ok((_, x)) = tagged("lightcolor", vec3, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.color = x;
+    } else if let Ok((_, x)) = tagged("lightradius", float, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.radius = x;
+    } else if let Ok((_, x)) = tagged("lightintensity", float, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.intensity = x;
+    } else if let Ok((_, x)) = tagged("lighttype", light_kind, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.kind = x;
+    } else if let Ok((_, x)) = tagged("lightedge1", vec3, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.edge1 = x;
+    } else if let Ok((_, x)) = tagged("lightedge2", vec3, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.edge2 = x;
+    } else if let Ok((_, x)) = tagged("lightonesided", float, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.one_sided = x != 0.0;
+    } else if let Ok((_, x)) = tagged("lightdirection", vec3, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.direction = x;
+    } else if let Ok((_, x)) = tagged("lightinnerangle", float, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.inner_angle = x;
+    } else if let Ok((_, x)) = tagged("lightouterangle", float, statement) {
+        last(&mut mtl.lights, "newlight", statement)?.outer_angle = x;
+    } else if let Ok((_, _)) = tagged("newcamera", rest, statement) {
+        mtl.cameras.push(Camera::default());
+    } else if let Ok((_, x)) = tagged("cameraposition", vec3, statement) {
+        last(&mut mtl.cameras, "newcamera", statement)?.position = x;
+    } else if let Ok((_, x)) = tagged("cameratarget", vec3, statement) {
+        last(&mut mtl.cameras, "newcamera", statement)?.target = x;
+    } else if let Ok((_, x)) = tagged("cameraup", vec3, statement) {
+        last(&mut mtl.cameras, "newcamera", statement)?.up = x;
+    } else if let Ok((_, x)) = tagged("camerafov", float, statement) {
+        last(&mut mtl.cameras, "newcamera", statement)?.fov = x;
+    } else if let Ok((_, x)) = tagged("cameraaperture", float, statement) {
+        last(&mut mtl.cameras, "newcamera", statement)?.aperture = x;
+    } else if let Ok((_, x)) = tagged("camerafocus", float, statement) {
+        last(&mut mtl.cameras, "newcamera", statement)?.focus = x;
+    } else if let Ok((_, _)) = tagged("newsky", rest, statement) {
+        mtl.sky = Some(Sky::default());
+    } else if let Ok((_, x)) = tagged("skysunelevation", float, statement) {
+        last(mtl.sky.as_mut_slice(), "newsky", statement)?.sun_elevation = x;
+    } else if let Ok((_, x)) = tagged("skysunazimuth", float, statement) {
+        last(mtl.sky.as_mut_slice(), "newsky", statement)?.sun_azimuth = x;
+    } else if let Ok((_, x)) = tagged("skysunsize", float, statement) {
+        last(mtl.sky.as_mut_slice(), "newsky", statement)?.sun_size = x;
+    } else if let Ok((_, x)) = tagged("skyturbidity", float, statement) {
+        last(mtl.sky.as_mut_slice(), "newsky", statement)?.turbidity = x;
+    } else if let Ok((_, x)) = tagged("skyintensity", float, statement) {
+        last(mtl.sky.as_mut_slice(), "newsky", statement)?.intensity = x;
+    } else if let Ok((_, name)) = tagged("newmtl", rest, statement) {
+        mtl.materials.push(Material::new(name.to_owned()));
+    } else if let Ok((_, _)) = tagged("illum", float, statement) {
+        // TODO: not supported
+    } else if let Ok((_, _)) = tagged("Ka", float, statement) {
+        // TODO: not supported
+    } else if let Ok((_, x)) = tagged("Kd", vec3, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.diffuse_reflection = x;
+    } else if let Ok((_, x)) = tagged("map_Kd", rest, statement) {
+        x.clone_into(&mut last(&mut mtl.materials, "newmtl", statement)?.diffuse_map);
+    } else if let Ok((_, x)) = tagged("Ks", vec3, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.specular_reflection = x;
+    } else if let Ok((_, x)) = tagged("Ns", float, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.specular_exponent = Some(x);
+    } else if let Ok((_, x)) = tagged("Ke", vec3, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.emittance = x;
+    } else if let Ok((_, x)) = tagged("reflat0deg", float, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.reflection_0_degrees = x;
+    } else if let Ok((_, x)) = tagged("reflat90deg", float, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.reflection_90_degrees = x;
+    } else if let Ok((_, x)) = tagged("Ni", float, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.index_of_refraction = x;
+    } else if let Ok((_, x)) = tagged("d", float, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.transparency = 1.0 - x;
+    } else if let Ok((_, x)) = tagged("Tr", float, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.transparency = x;
+    } else if let Ok((_, x)) = tagged("Pm", float, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.metalness = x;
+    } else if let Ok((_, x)) = tagged("specularroughness", float, statement) {
+        last(&mut mtl.materials, "newmtl", statement)?.specular_roughness = Some(x);
+    } else {
+        return Err(unhandled(statement, KEYWORDS));
+    }
+    Ok(())
+}
+
+pub fn mtl<R>(input: &mut R, mode: ParseMode) -> Result<Mtl, Error>
 where
     R: BufRead,
 {
-    let mut materials: Vec<Material> = Vec::new();
-    let mut lights: Vec<Light> = Vec::new();
-    let mut cameras: Vec<Camera> = Vec::new();
+    let mut mtl = Mtl {
+        materials: Vec::new(),
+        lights: Vec::new(),
+        cameras: Vec::new(),
+        sky: None,
+        warnings: Vec::new(),
+    };
 
     let mut line = String::new();
-    while input.read_line(&mut line)? > 0 {
+    let mut line_number = 0;
+    while read_line(input, &mut line, &mut line_number)? {
         let trimmed = line.trim();
         if trimmed.is_empty() || trimmed.starts_with('#') {
             line.clear();
             continue;
         }
 
-        if let Ok((_, _)) = tagged("newlight", rest, trimmed) {
-            lights.push(Light::default());
-        } else if let Ok((_, x)) = tagged("lightposition", vec3, trimmed) {
-            lights.last_mut().unwrap().position = x;
-        } else if let Ok((_, x)) = tagged("lightcolor", vec3, trimmed) {
-            lights.last_mut().unwrap().color = x;
-        } else if let Ok((_, x)) = tagged("lightradius", float, trimmed) {
-            lights.last_mut().unwrap().radius = x;
-        } else if let Ok((_, x)) = tagged("lightintensity", float, trimmed) {
-            lights.last_mut().unwrap().intensity = x;
-        } else if let Ok((_, _)) = tagged("newcamera", rest, trimmed) {
-            cameras.push(Camera::default());
-        } else if let Ok((_, x)) = tagged("cameraposition", vec3, trimmed) {
-            cameras.last_mut().unwrap().position = x;
-        } else if let Ok((_, x)) = tagged("cameratarget", vec3, trimmed) {
-            cameras.last_mut().unwrap().target = x;
-        } else if let Ok((_, x)) = tagged("cameraup", vec3, trimmed) {
-            cameras.last_mut().unwrap().up = x;
-        } else if let Ok((_, x)) = tagged("camerafov", float, trimmed) {
-            cameras.last_mut().unwrap().fov = x;
-        } else if let Ok((_, name)) = tagged("newmtl", rest, trimmed) {
-            materials.push(Material::new(name.to_owned()));
-        } else if let Ok((_, _)) = tagged("illum", float, trimmed) {
-            // TODO: not supported
-        } else if let Ok((_, _)) = tagged("Ka", float, trimmed) {
-            // TODO: not supported
-        } else if let Ok((_, x)) = tagged("Kd", vec3, trimmed) {
-            materials.last_mut().unwrap().diffuse_reflection = x;
-        } else if let Ok((_, x)) = tagged("map_Kd", rest, trimmed) {
-            x.clone_into(&mut materials.last_mut().unwrap().diffuse_map);
-        } else if let Ok((_, x)) = tagged("Ks", vec3, trimmed) {
-            materials.last_mut().unwrap().specular_reflection = x;
-        } else if let Ok((_, _)) = tagged("Ns", float, trimmed) {
-            // TODO: not supported
-        } else if let Ok((_, _)) = tagged("Ke", float, trimmed) {
-            // TODO: not supported
-        } else if let Ok((_, x)) = tagged("reflat0deg", float, trimmed) {
-            materials.last_mut().unwrap().reflection_0_degrees = x;
-        } else if let Ok((_, x)) = tagged("reflat90deg", float, trimmed) {
-            materials.last_mut().unwrap().reflection_90_degrees = x;
-        } else if let Ok((_, x)) = tagged("Ni", float, trimmed) {
-            materials.last_mut().unwrap().index_of_refraction = x;
-        } else if let Ok((_, x)) = tagged("d", float, trimmed) {
-            materials.last_mut().unwrap().transparency = 1.0 - x;
-        } else if let Ok((_, x)) = tagged("Tr", float, trimmed) {
-            materials.last_mut().unwrap().transparency = x;
-        } else if let Ok((_, x)) = tagged("Pm", float, trimmed) {
-            materials.last_mut().unwrap().metalness = x;
-        } else if let Ok((_, _)) = tagged("specularroughness", float, trimmed) {
-            // TODO: not supported
-        } else {
-            panic!("Unexpected line: \"{line}\"");
+        if let Err((text, kind)) = statement(&mut mtl, trimmed) {
+            let error = Error::at(line_number, &line, text, kind);
+            mode.report(error, &mut mtl.warnings)?;
         }
         line.clear();
     }
 
-    Ok(Mtl {
-        materials,
-        lights,
-        cameras,
-    })
+    Ok(mtl)
 }
 
 #[cfg(test)]
@@ -169,7 +327,44 @@ mod tests {
     }
 
     fn mtl_test(str: &str) -> Mtl {
-        mtl(&mut str.as_bytes()).unwrap()
+        mtl(&mut str.as_bytes(), ParseMode::Strict).unwrap()
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |str: &str| mtl(&mut str.as_bytes(), ParseMode::Strict).unwrap_err();
+        let outside = error("# lights\nlightradius 1.");
+        assert_eq!((outside.line, outside.column), (2, 1));
+        assert_eq!(outside.text, "lightradius 1.");
+        assert_eq!(outside.kind, ErrorKind::OutsideBlock("newlight"));
+        assert_eq!(
+            error("skyturbidity 2").kind,
+            ErrorKind::OutsideBlock("newsky")
+        );
+        let invalid = error("newmtl m\n Kd 1 2");
+        assert_eq!((invalid.line, invalid.column), (2, 5));
+        assert_eq!(invalid.kind, ErrorKind::InvalidValue);
+        let unknown = error("newmtl m\nbump map.png");
+        assert_eq!(unknown.text, "bump");
+        assert_eq!(unknown.kind, ErrorKind::UnknownKeyword);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let mtl = mtl(
+            &mut "Kd 1 1 1\nnewmtl m\nbump map.png\nKd 0.5 0.5 0.5".as_bytes(),
+            ParseMode::Lenient,
+        )
+        .unwrap();
+        assert_eq!(mtl.materials[0].diffuse_reflection, [0.5, 0.5, 0.5]);
+        let warnings: Vec<_> = mtl.warnings.iter().map(|w| (w.line, &w.kind)).collect();
+        assert_eq!(
+            warnings,
+            [
+                (1, &ErrorKind::OutsideBlock("newmtl")),
+                (3, &ErrorKind::UnknownKeyword)
+            ]
+        );
     }
 
     #[test]
@@ -188,6 +383,36 @@ mod tests {
             mtl_test("newlight l1\nlightintensity 1.").lights[0].intensity,
             1.
         );
+        assert_eq!(mtl_test("newlight l1").lights[0].kind, LightKind::Sphere);
+        assert_eq!(
+            mtl_test("newlight l1\nlighttype quad").lights[0].kind,
+            LightKind::Quad
+        );
+        assert_eq!(
+            mtl_test("newlight l1\nlighttype spot").lights[0].kind,
+            LightKind::Spot
+        );
+        assert_eq!(
+            mtl_test("newlight l1\nlightedge1 1. 2. 3.").lights[0].edge1,
+            [1., 2., 3.]
+        );
+        assert_eq!(
+            mtl_test("newlight l1\nlightedge2 1. 2. 3.").lights[0].edge2,
+            [1., 2., 3.]
+        );
+        assert!(mtl_test("newlight l1\nlightonesided 1").lights[0].one_sided);
+        assert_eq!(
+            mtl_test("newlight l1\nlightdirection 1. 2. 3.").lights[0].direction,
+            [1., 2., 3.]
+        );
+        assert_eq!(
+            mtl_test("newlight l1\nlightinnerangle 10.").lights[0].inner_angle,
+            10.
+        );
+        assert_eq!(
+            mtl_test("newlight l1\nlightouterangle 20.").lights[0].outer_angle,
+            20.
+        );
     }
 
     #[test]
@@ -206,6 +431,34 @@ mod tests {
             [1., 2., 3.]
         );
         assert_eq!(mtl_test("newcamera c1\ncamerafov 1.").cameras[0].fov, 1.);
+        assert_eq!(
+            mtl_test("newcamera c1\ncameraaperture 0.1").cameras[0].aperture,
+            0.1
+        );
+        assert_eq!(
+            mtl_test("newcamera c1\ncamerafocus 2.").cameras[0].focus,
+            2.
+        );
+    }
+
+    #[test]
+    fn test_sky() {
+        assert_eq!(mtl_test("newmtl m1").sky, None);
+        assert_eq!(mtl_test("newsky").sky, Some(Sky::default()));
+        assert_eq!(
+            mtl_test(
+                "newsky s1\nskysunelevation 10.\nskysunazimuth 20.\nskysunsize 2.\n\
+                 skyturbidity 4.\nskyintensity 0.5"
+            )
+            .sky,
+            Some(Sky {
+                sun_elevation: 10.,
+                sun_azimuth: 20.,
+                sun_size: 2.,
+                turbidity: 4.,
+                intensity: 0.5,
+            })
+        );
     }
 
     #[test]
@@ -224,6 +477,10 @@ mod tests {
             mtl_test("newmtl m1\nKs 1. 2. 3.").materials[0].specular_reflection,
             [1., 2., 3.]
         );
+        assert_eq!(
+            mtl_test("newmtl m1\nKe 1. 2. 3.").materials[0].emittance,
+            [1., 2., 3.]
+        );
         assert_eq!(
             mtl_test("newmtl m1\nreflat0deg 0.5").materials[0].reflection_0_degrees,
             0.5
@@ -239,8 +496,12 @@ mod tests {
         assert_eq!(mtl_test("newmtl m1\nd 1.0").materials[0].transparency, 0.0);
         assert_eq!(mtl_test("newmtl m1\nTr 0.5").materials[0].transparency, 0.5);
         assert_eq!(
-            mtl_test("newmtl m1\nspecularroughness 1.").materials.len(),
-            1
+            mtl_test("newmtl m1\nspecularroughness 0.5").materials[0].specular_roughness,
+            Some(0.5)
+        );
+        assert_eq!(
+            mtl_test("newmtl m1\nNs 100").materials[0].specular_exponent,
+            Some(100.)
         );
     }
 
@@ -1,12 +1,19 @@
 use nom::{
     IResult, Parser,
+    branch::alt,
     bytes::complete::tag_no_case,
-    character::complete::{char, i32, space0, space1},
-    combinator::{opt, rest},
+    character::complete::{char, i32, space0, space1, u32},
+    combinator::{opt, rest, value},
     multi::separated_list0,
     number::complete::float,
+    sequence::preceded,
+};
+use std::{cmp::Ordering, io::BufRead, ops::Range, path::PathBuf};
+
+use crate::{
+    error::{Error, ErrorKind, ParseMode, read_line, unhandled},
+    triangulate::triangulate,
 };
-use std::{cmp::Ordering, io::BufRead, path::PathBuf};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Point {
@@ -18,19 +25,52 @@ pub struct Point {
 #[derive(Debug, PartialEq, Eq)]
 pub struct Face {
     pub points: Vec<Point>,
+    /// Faces sharing a smoothing group other than zero are shaded smoothly across shared vertices.
+    pub smoothing_group: u32,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Chunk {
     pub faces: Vec<Face>,
     pub material: String,
+    /// Line of the `usemtl` statement.
+    pub line: usize,
 }
 
 impl Chunk {
-    pub const fn new(material: String) -> Self {
+    pub const fn new(material: String, line: usize) -> Self {
         Self {
             faces: Vec::new(),
             material,
+            line,
+        }
+    }
+}
+
+/// Faces following a `g` statement within an object.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    /// Indices of the faces in file order, see [`Obj::faces`].
+    pub faces: Range<usize>,
+}
+
+/// Faces following an `o` statement. Faces before the first `o` statement belong to an object
+/// without a name.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Object {
+    pub name: String,
+    /// Indices of the faces in file order, see [`Obj::faces`].
+    pub faces: Range<usize>,
+    pub groups: Vec<Group>,
+}
+
+impl Object {
+    const fn new(name: String, first_face: usize) -> Self {
+        Self {
+            name,
+            faces: first_face..first_face,
+            groups: Vec::new(),
         }
     }
 }
@@ -43,9 +83,46 @@ pub struct Obj {
     pub normals: Vec<[f32; 3]>,
     pub texcoords: Vec<[f32; 2]>,
     pub chunks: Vec<Chunk>,
+    pub objects: Vec<Object>,
+    /// Problems with statements that were skipped in lenient mode.
+    pub warnings: Vec<Error>,
 }
 
 impl Obj {
+    /// All faces in file order.
+    pub fn faces(&self) -> impl Iterator<Item = &Face> {
+        self.chunks.iter().flat_map(|chunk| &chunk.faces)
+    }
+
+    fn face_count(&self) -> usize {
+        self.objects.last().map_or(0, |object| object.faces.end)
+    }
+
+    /// The current object, starting an unnamed one for faces before the first `o` statement.
+    fn current_object(&mut self) -> &mut Object {
+        if self.objects.is_empty() {
+            self.objects.push(Object::new(String::new(), 0));
+        }
+        self.objects.last_mut().unwrap()
+    }
+
+    /// Add the last face to the current object and group.
+    fn extend_current_object(&mut self) {
+        let object = self.current_object();
+        object.faces.end += 1;
+        if let Some(group) = object.groups.last_mut() {
+            group.faces.end = object.faces.end;
+        }
+    }
+
+    /// Whether the indices of `point` refer to elements read so far.
+    fn is_defined(&self, point: &Point) -> bool {
+        point.v != 0
+            && is_valid_index(point.v, self.vertices.len())
+            && is_valid_index(point.t, self.texcoords.len())
+            && is_valid_index(point.n, self.normals.len())
+    }
+
     pub fn index_vertex(&self, point: &Point) -> [f32; 3] {
         index_wavefront_vec(&self.vertices, point.v)
     }
@@ -57,6 +134,24 @@ impl Obj {
     pub fn index_normal(&self, point: &Point) -> [f32; 3] {
         index_wavefront_vec(&self.normals, point.n)
     }
+
+    /// Split `face` into triangles, each given by the points of the face so that the normal and
+    /// texture coordinate indices follow the vertices. Faces with fewer than three points have
+    /// no triangles.
+    pub fn triangulate<'a>(
+        &self,
+        face: &'a Face,
+    ) -> impl Iterator<Item = [&'a Point; 3]> + use<'a> {
+        let vertices: Vec<[f32; 3]> = face.points.iter().map(|p| self.index_vertex(p)).collect();
+        triangulate(&vertices)
+            .into_iter()
+            .map(|triangle| triangle.map(|i| &face.points[i]))
+    }
+}
+
+/// Whether `i` refers to one of `len` elements, zero is a missing index.
+fn is_valid_index(i: i32, len: usize) -> bool {
+    i == 0 || i.unsigned_abs() as usize <= len
 }
 
 fn index_wavefront_vec<T: Default + Copy>(v: &[T], i: i32) -> T {
@@ -99,70 +194,130 @@ fn i32_or_zero(input: &str) -> IResult<&str, i32> {
         .map(|(input, n)| (input, n.unwrap_or(0)))
 }
 
+/// Vertex reference of a face in any of the forms `v`, `v/t`, `v//n` or `v/t/n`, missing indices
+/// are zero.
 fn point(input: &str) -> IResult<&str, Point> {
     let (input, v) = i32(input)?;
-    let (input, _) = char('/')(input)?;
-    let (input, t) = i32_or_zero(input)?;
-    let (input, _) = char('/')(input)?;
-    let (input, n) = i32_or_zero(input)?;
-    Ok((input, Point { v, t, n }))
+    let (input, t) = opt(preceded(char('/'), i32_or_zero)).parse(input)?;
+    let (input, n) = match t {
+        Some(_) => opt(preceded(char('/'), i32_or_zero)).parse(input)?,
+        None => (input, None),
+    };
+    Ok((
+        input,
+        Point {
+            v,
+            t: t.unwrap_or(0),
+            n: n.unwrap_or(0),
+        },
+    ))
 }
 
-fn face(input: &str) -> IResult<&str, Face> {
-    separated_list0(space1, point)
-        .parse(input)
-        .map(|(input, points)| (input, Face { points }))
+fn points(input: &str) -> IResult<&str, Vec<Point>> {
+    separated_list0(space1, point).parse(input)
+}
+
+/// Smoothing group number where `off` is the same as zero.
+fn smoothing_group(input: &str) -> IResult<&str, u32> {
+    alt((value(0, tag_no_case("off")), u32)).parse(input)
+}
+
+const KEYWORDS: &[&str] = &["mtllib", "usemtl", "v", "vn", "vt", "f", "g", "o", "s"];
+
+fn statement<'a>(
+    obj: &mut Obj,
+    group: &mut u32,
+    line: usize,
+    statement: &'a str,
+) -> Result<(), (&'a str, ErrorKind)> {
+    if let Ok((_, x)) = tagged("mtllib", rest, statement) {
+        obj.mtl_lib = PathBuf::from(x);
+    } else if let Ok((_, x)) = tagged("usemtl", rest, statement) {
+        obj.chunks.push(Chunk::new(x.to_owned(), line));
+    } else if let Ok((_, x)) = tagged("v", vec3, statement) {
+        obj.vertices.push(x);
+    } else if let Ok((_, x)) = tagged("vn", vec3, statement) {
+        obj.normals.push(x);
+    } else if let Ok((_, x)) = tagged("vt", vec2, statement) {
+        obj.texcoords.push(x);
+    } else if let Ok((_, points)) = tagged("f", points, statement) {
+        if obj.chunks.is_empty() {
+            return Err((statement, ErrorKind::OutsideBlock("usemtl")));
+        }
+        let undefined = statement[1..]
+            .split_whitespace()
+            .zip(&points)
+            .find(|(_, point)| !obj.is_defined(point));
+        if let Some((text, _)) = undefined {
+            return Err((text, ErrorKind::InvalidValue));
+        }
+        obj.chunks.last_mut().unwrap().faces.push(Face {
+            points,
+            smoothing_group: *group,
+        });
+        obj.extend_current_object();
+    } else if let Ok((_, name)) = tagged("g", rest, statement) {
+        let face = obj.face_count();
+        obj.current_object().groups.push(Group {
+            name: name.to_owned(),
+            faces: face..face,
+        });
+    } else if let Ok((_, name)) = tagged("o", rest, statement) {
+        let face = obj.face_count();
+        obj.objects.push(Object::new(name.to_owned(), face));
+    } else if let Ok((_, x)) = tagged("s", smoothing_group, statement) {
+        *group = x;
+    } else {
+        return Err(unhandled(statement, KEYWORDS));
+    }
+    Ok(())
 }
 
-pub fn obj<R>(input: &mut R) -> std::io::Result<Obj>
+pub fn obj<R>(input: &mut R, mode: ParseMode) -> Result<Obj, Error>
 where
     R: BufRead,
 {
-    let mut mtl_lib = PathBuf::new();
-    let mut chunks: Vec<Chunk> = Vec::new();
-    let mut vertices: Vec<[f32; 3]> = Vec::new();
-    let mut normals: Vec<[f32; 3]> = Vec::new();
-    let mut texcoords: Vec<[f32; 2]> = Vec::new();
-
+    let mut obj = Obj {
+        mtl_lib: PathBuf::new(),
+        vertices: Vec::new(),
+        normals: Vec::new(),
+        texcoords: Vec::new(),
+        chunks: Vec::new(),
+        objects: Vec::new(),
+        warnings: Vec::new(),
+    };
+
+    let mut group = 0;
     let mut line = String::new();
-    while input.read_line(&mut line)? > 0 {
+    let mut line_number = 0;
+    while read_line(input, &mut line, &mut line_number)? {
+        let statement_line = line_number;
+        // Start of each physical line in `line` and its line number, for locating errors.
+        let mut physical_lines = vec![(0, line_number)];
+        // A trailing backslash continues the statement on the next line.
+        while line.trim_end().ends_with('\\') {
+            line.truncate(line.trim_end().len() - 1);
+            line.push(' ');
+            let start = line.len();
+            if !read_line(input, &mut line, &mut line_number)? {
+                break;
+            }
+            physical_lines.push((start, line_number));
+        }
         let trimmed = line.trim();
         if trimmed.is_empty() || trimmed.starts_with('#') {
             line.clear();
             continue;
         }
 
-        if let Ok((_, x)) = tagged("mtllib", rest, trimmed) {
-            mtl_lib = PathBuf::from(x);
-        } else if let Ok((_, x)) = tagged("usemtl", rest, trimmed) {
-            chunks.push(Chunk::new(x.to_owned()));
-        } else if let Ok((_, x)) = tagged("v", vec3, trimmed) {
-            vertices.push(x);
-        } else if let Ok((_, x)) = tagged("vn", vec3, trimmed) {
-            normals.push(x);
-        } else if let Ok((_, x)) = tagged("vt", vec2, trimmed) {
-            texcoords.push(x);
-        } else if let Ok((_, x)) = tagged("f", face, trimmed) {
-            chunks.last_mut().unwrap().faces.push(x);
-        } else if let Ok((_, _)) = tagged("g", rest, trimmed) {
-            // TODO: not supported
-        } else if let Ok((_, _)) = tagged("o", rest, trimmed) {
-            // TODO: not supported
-        } else if let Ok((_, _)) = tagged("s", rest, trimmed) {
-            // TODO: not supported
-        } else {
-            panic!("Unexpected line: \"{line}\"");
+        if let Err((text, kind)) = statement(&mut obj, &mut group, statement_line, trimmed) {
+            let error = Error::at_joined(&physical_lines, &line, text, kind);
+            mode.report(error, &mut obj.warnings)?;
         }
         line.clear();
     }
 
-    Ok(Obj {
-        mtl_lib,
-        vertices,
-        normals,
-        texcoords,
-        chunks,
-    })
+    Ok(obj)
 }
 
 #[cfg(test)]
@@ -170,7 +325,7 @@ mod tests {
     use super::*;
 
     fn obj_test(str: &str) -> Obj {
-        obj(&mut str.as_bytes()).unwrap()
+        obj(&mut str.as_bytes(), ParseMode::Strict).unwrap()
     }
 
     #[test]
@@ -198,8 +353,60 @@ mod tests {
 
     #[test]
     fn test_point() {
-        assert_eq!(point("1/2/3"), Ok(("", Point { v: 1, t: 2, n: 3 })));
+        assert_eq!(point("1"), Ok(("", Point { v: 1, t: 0, n: 0 })));
+        assert_eq!(point("1/2"), Ok(("", Point { v: 1, t: 2, n: 0 })));
         assert_eq!(point("1//3"), Ok(("", Point { v: 1, t: 0, n: 3 })));
+        assert_eq!(point("1/2/3"), Ok(("", Point { v: 1, t: 2, n: 3 })));
+        assert_eq!(
+            point("-1/-2/-3"),
+            Ok((
+                "",
+                Point {
+                    v: -1,
+                    t: -2,
+                    n: -3
+                }
+            ))
+        );
+        assert_eq!(point("1 2"), Ok((" 2", Point { v: 1, t: 0, n: 0 })));
+    }
+
+    #[test]
+    fn test_face_forms() {
+        let points = |line: &str| {
+            obj_test(&with_elements(&format!("usemtl m1\n{line}"))).chunks[0].faces[0]
+                .points
+                .iter()
+                .map(|p| (p.v, p.t, p.n))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(points("f 1 2 3"), [(1, 0, 0), (2, 0, 0), (3, 0, 0)]);
+        assert_eq!(points("f 1/4 2/5 3/6"), [(1, 4, 0), (2, 5, 0), (3, 6, 0)]);
+        assert_eq!(
+            points("f 1//7 2//8 3//9"),
+            [(1, 0, 7), (2, 0, 8), (3, 0, 9)]
+        );
+        assert_eq!(
+            points("f 1/4/7 2/5/8 3/6/9"),
+            [(1, 4, 7), (2, 5, 8), (3, 6, 9)]
+        );
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let obj = obj_test(&format!(
+            "v 1 \\\n 2 3\n{}usemtl m1\nf 1/1 \\\n2/2 \\\r\n 3/3\n",
+            elements()
+        ));
+        assert_eq!(obj.vertices[0], [1., 2., 3.]);
+        assert_eq!(
+            obj.chunks[0].faces[0].points,
+            [
+                Point { v: 1, t: 1, n: 0 },
+                Point { v: 2, t: 2, n: 0 },
+                Point { v: 3, t: 3, n: 0 }
+            ]
+        );
     }
 
     #[test]
@@ -209,50 +416,69 @@ mod tests {
         assert_eq!(obj_test("vn 1 2 3").normals, [[1., 2., 3.]]);
     }
 
+    /// A dozen vertices, texture coordinates and normals for faces to refer to.
+    fn elements() -> String {
+        "v 0 0 0\nvt 0 0\nvn 0 0 1\n".repeat(12)
+    }
+
+    /// Insert [`elements`] after the first line.
+    fn with_elements(str: &str) -> String {
+        let (first, rest) = str.split_once('\n').unwrap();
+        format!("{first}\n{}{rest}", elements())
+    }
+
     #[test]
     fn test_faces() {
         assert_eq!(
-            obj_test("usemtl m1\nf 1/2/3").chunks,
+            obj_test(&with_elements("usemtl m1\nf 1/2/3")).chunks,
             [Chunk {
                 faces: vec![Face {
-                    points: vec![Point { v: 1, t: 2, n: 3 },]
+                    points: vec![Point { v: 1, t: 2, n: 3 },],
+                    smoothing_group: 0,
                 }],
-                material: "m1".to_string()
+                material: "m1".to_string(),
+                line: 1,
             }]
         );
         assert_eq!(
-            obj_test("usemtl m1\nf 1//3").chunks,
+            obj_test(&with_elements("usemtl m1\nf 1//3")).chunks,
             [Chunk {
                 faces: vec![Face {
-                    points: vec![Point { v: 1, t: 0, n: 3 },]
+                    points: vec![Point { v: 1, t: 0, n: 3 },],
+                    smoothing_group: 0,
                 }],
-                material: "m1".to_string()
+                material: "m1".to_string(),
+                line: 1,
             }]
         );
         assert_eq!(
-            obj_test("usemtl m1\nf 1//").chunks,
+            obj_test(&with_elements("usemtl m1\nf 1//")).chunks,
             [Chunk {
                 faces: vec![Face {
-                    points: vec![Point { v: 1, t: 0, n: 0 },]
+                    points: vec![Point { v: 1, t: 0, n: 0 },],
+                    smoothing_group: 0,
                 }],
-                material: "m1".to_string()
+                material: "m1".to_string(),
+                line: 1,
             }]
         );
         assert_eq!(
-            obj_test("usemtl m1\nf 1/2/3 4/5/6 7/8/9").chunks,
+            obj_test(&with_elements("usemtl m1\nf 1/2/3 4/5/6 7/8/9")).chunks,
             [Chunk {
                 faces: vec![Face {
                     points: vec![
                         Point { v: 1, t: 2, n: 3 },
                         Point { v: 4, t: 5, n: 6 },
                         Point { v: 7, t: 8, n: 9 }
-                    ]
+                    ],
+                    smoothing_group: 0,
                 }],
-                material: "m1".to_string()
+                material: "m1".to_string(),
+                line: 1,
             }]
         );
         assert_eq!(
-            obj_test("usemtl m1\nf 1/2/3 4/5/6 7/8/9 10/11/12").chunks,
+            obj_test(&with_elements("usemtl m1\nf 1/2/3 4/5/6 7/8/9 10/11/12")).chunks,
             [Chunk {
                 faces: vec![Face {
                     points: vec![
@@ -264,13 +490,33 @@ mod tests {
                             t: 11,
                             n: 12
                         }
-                    ]
+                    ],
+                    smoothing_group: 0,
                 }],
-                material: "m1".to_string()
+                material: "m1".to_string(),
+                line: 1,
             }]
         );
     }
 
+    #[test]
+    fn test_triangulate() {
+        let obj = obj_test(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\n\
+             usemtl m1\nf 1/1/1 2/2/1 3/3/1 4/4/1",
+        );
+        let face = &obj.chunks[0].faces[0];
+        let triangles: Vec<_> = obj.triangulate(face).collect();
+        assert_eq!(
+            triangles,
+            [
+                [&face.points[0], &face.points[1], &face.points[2]],
+                [&face.points[0], &face.points[2], &face.points[3]],
+            ]
+        );
+        assert_eq!(triangles[1][2], &Point { v: 4, t: 4, n: 1 });
+    }
+
     #[test]
     fn test_usemtl() {
         assert_eq!(
@@ -278,6 +524,7 @@ mod tests {
             [Chunk {
                 faces: vec![],
                 material: "m1".to_string(),
+                line: 1,
             }]
         );
     }
@@ -296,9 +543,117 @@ mod tests {
     }
 
     #[test]
-    fn test_unsupported() {
-        assert_eq!(obj_test("g mesh1 model").chunks.len(), 0);
-        assert_eq!(obj_test("o todo").chunks.len(), 0);
-        assert_eq!(obj_test("s todo").chunks.len(), 0);
+    fn test_errors() {
+        let error = |str: &str| obj(&mut str.as_bytes(), ParseMode::Strict).unwrap_err();
+        let unknown = error("v 1 2 3\n\n  curv 0 1 2\n");
+        assert_eq!((unknown.line, unknown.column), (3, 3));
+        assert_eq!(unknown.text, "curv");
+        assert_eq!(unknown.kind, ErrorKind::UnknownKeyword);
+        let invalid = error("vt 1 x");
+        assert_eq!((invalid.line, invalid.column), (1, 4));
+        assert_eq!(invalid.text, "1 x");
+        assert_eq!(invalid.kind, ErrorKind::InvalidValue);
+        let outside = error("# comment\nf 1 2 3");
+        assert_eq!((outside.line, outside.column), (2, 1));
+        assert_eq!(outside.kind, ErrorKind::OutsideBlock("usemtl"));
+        let continued = error("v 1 \\\n 2 3\nbad");
+        assert_eq!(continued.line, 3);
+        let continued_face = error("v 0 0 0\nusemtl m1\nf 1 \\\n  1 \\\n 1 -2\n");
+        assert_eq!(
+            (continued_face.line, continued_face.column),
+            (5, 4),
+            "{continued_face}"
+        );
+        assert_eq!(continued_face.text, "-2");
+        let out_of_range = error("v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl m1\nf 1 2 99");
+        assert_eq!((out_of_range.line, out_of_range.column), (5, 7));
+        assert_eq!(out_of_range.text, "99");
+        assert_eq!(out_of_range.kind, ErrorKind::InvalidValue);
+        let texcoord = error("v 0 0 0\nusemtl m1\nf 1 1/1 -1");
+        assert_eq!((texcoord.column, texcoord.text.as_str()), (5, "1/1"));
+        let normal = error("v 0 0 0\nusemtl m1\nf 1 1 -1//-1");
+        assert_eq!((normal.column, normal.text.as_str()), (7, "-1//-1"));
+        assert_eq!(error("v 0 0 0\nusemtl m1\nf 0 1 1").text, "0");
+        assert_eq!(error("v 0 0 0\nusemtl m1\nf 1 1 -2").text, "-2");
+    }
+
+    #[test]
+    fn test_warnings() {
+        let obj = obj(
+            &mut "f 1 2 3\nv 1 2 3\ncurv 0 1\nusemtl m1\nf 1 1 1".as_bytes(),
+            ParseMode::Lenient,
+        )
+        .unwrap();
+        assert_eq!(obj.vertices.len(), 1);
+        assert_eq!(obj.chunks[0].faces.len(), 1);
+        let warnings: Vec<_> = obj.warnings.iter().map(|w| (w.line, &w.kind)).collect();
+        assert_eq!(
+            warnings,
+            [
+                (1, &ErrorKind::OutsideBlock("usemtl")),
+                (3, &ErrorKind::UnknownKeyword)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_smoothing_groups() {
+        let groups = |str: &str| {
+            obj_test(&with_elements(str)).chunks[0]
+                .faces
+                .iter()
+                .map(|f| f.smoothing_group)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            groups(
+                "usemtl m1\nf 1 2 3\ns 1\nf 1 2 3\ns 2\nf 1 2 3\ns off\nf 1 2 3\ns 3\ns 0\nf 1 2 3"
+            ),
+            [0, 1, 2, 0, 0]
+        );
+        assert!(obj(&mut "s todo".as_bytes(), ParseMode::Strict).is_err());
+    }
+
+    #[test]
+    fn test_objects_and_groups() {
+        let obj = obj_test(&with_elements(
+            "usemtl m1\nf 1 2 3\no first\ng a b\nf 1 2 3\nusemtl m2\nf 1 2 3\ng c\nf 1 2 3\n\
+             o second\nf 1 2 3\no empty\n",
+        ));
+        assert_eq!(obj.faces().count(), 5);
+        assert_eq!(
+            obj.objects,
+            [
+                Object {
+                    name: String::new(),
+                    faces: 0..1,
+                    groups: vec![],
+                },
+                Object {
+                    name: "first".to_string(),
+                    faces: 1..4,
+                    groups: vec![
+                        Group {
+                            name: "a b".to_string(),
+                            faces: 1..3,
+                        },
+                        Group {
+                            name: "c".to_string(),
+                            faces: 3..4,
+                        },
+                    ],
+                },
+                Object {
+                    name: "second".to_string(),
+                    faces: 4..5,
+                    groups: vec![],
+                },
+                Object {
+                    name: "empty".to_string(),
+                    faces: 5..5,
+                    groups: vec![],
+                },
+            ]
+        );
     }
 }
@@ -0,0 +1,170 @@
+/// Split a simple polygon into triangles given as indices into `vertices`, keeping the winding
+/// order of the polygon. Convex polygons are split into a fan and concave polygons by ear
+/// clipping in the plane of the polygon.
+pub fn triangulate(vertices: &[[f32; 3]]) -> Vec<[usize; 3]> {
+    if vertices.len() < 3 {
+        return Vec::new();
+    }
+    let points = project(vertices);
+    if is_convex(&points) {
+        (1..vertices.len() - 1).map(|i| [0, i, i + 1]).collect()
+    } else {
+        ear_clip(&points)
+    }
+}
+
+/// Normal of a possibly non-planar polygon using Newell's method, its length is twice the area.
+fn newell_normal(vertices: &[[f32; 3]]) -> [f32; 3] {
+    let mut normal = [0.0; 3];
+    for (i, a) in vertices.iter().enumerate() {
+        let b = vertices[(i + 1) % vertices.len()];
+        normal[0] += (a[1] - b[1]) * (a[2] + b[2]);
+        normal[1] += (a[2] - b[2]) * (a[0] + b[0]);
+        normal[2] += (a[0] - b[0]) * (a[1] + b[1]);
+    }
+    normal
+}
+
+/// Project the polygon onto the axis aligned plane where it has the largest area, oriented so
+/// that the polygon winds counter-clockwise.
+fn project(vertices: &[[f32; 3]]) -> Vec<[f32; 2]> {
+    let normal = newell_normal(vertices);
+    let axis = (0..3)
+        .max_by(|a, b| normal[*a].abs().total_cmp(&normal[*b].abs()))
+        .unwrap();
+    let (u, v) = if normal[axis] >= 0.0 {
+        ((axis + 1) % 3, (axis + 2) % 3)
+    } else {
+        ((axis + 2) % 3, (axis + 1) % 3)
+    };
+    vertices.iter().map(|p| [p[u], p[v]]).collect()
+}
+
+/// Twice the signed area of the triangle `abc`, positive when counter-clockwise.
+fn orient(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+fn is_convex(points: &[[f32; 2]]) -> bool {
+    let n = points.len();
+    (0..n).all(|i| orient(points[(i + n - 1) % n], points[i], points[(i + 1) % n]) >= 0.0)
+}
+
+fn contains(triangle: [[f32; 2]; 3], p: [f32; 2]) -> bool {
+    let [a, b, c] = triangle;
+    orient(a, b, p) >= 0.0 && orient(b, c, p) >= 0.0 && orient(c, a, p) >= 0.0
+}
+
+fn ear_clip(points: &[[f32; 2]]) -> Vec<[usize; 3]> {
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let corners = |i: usize| {
+            [
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            ]
+        };
+        let is_ear = |i: usize| {
+            let indices = corners(i);
+            let triangle = indices.map(|j| points[j]);
+            orient(triangle[0], triangle[1], triangle[2]) > 0.0
+                && remaining
+                    .iter()
+                    .filter(|j| !indices.contains(j) && !triangle.contains(&points[**j]))
+                    .all(|j| !contains(triangle, points[*j]))
+        };
+        // Self-intersecting polygons may have no ears, clip an arbitrary vertex to make progress.
+        let ear = (0..n).find(|i| is_ear(*i)).unwrap_or(0);
+        triangles.push(corners(ear));
+        remaining.remove(ear);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(vertices: &[[f32; 3]], triangles: &[[usize; 3]]) -> f32 {
+        triangles
+            .iter()
+            .map(|t| {
+                let n = newell_normal(&t.map(|i| vertices[i]));
+                (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt() / 2.0
+            })
+            .sum()
+    }
+
+    fn assert_same_winding(vertices: &[[f32; 3]], triangles: &[[usize; 3]]) {
+        let polygon = newell_normal(vertices);
+        for t in triangles {
+            let n = newell_normal(&t.map(|i| vertices[i]));
+            let dot = n[0] * polygon[0] + n[1] * polygon[1] + n[2] * polygon[2];
+            assert!(dot > 0.0, "{t:?} winds opposite to the polygon");
+        }
+    }
+
+    #[test]
+    fn degenerate_faces_have_no_triangles() {
+        assert_eq!(triangulate(&[]), Vec::<[usize; 3]>::new());
+        assert_eq!(triangulate(&[[0.0; 3], [1.0; 3]]), Vec::<[usize; 3]>::new());
+    }
+
+    #[test]
+    fn triangle_is_unchanged() {
+        let vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        assert_eq!(triangulate(&vertices), [[0, 1, 2]]);
+    }
+
+    #[test]
+    fn convex_polygon_is_fanned() {
+        let vertices = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [2.0, 0.0, 1.0],
+            [1.0, 0.0, 2.0],
+            [0.0, 0.0, 1.0],
+        ];
+        assert_eq!(triangulate(&vertices), [[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn concave_polygon_is_ear_clipped() {
+        // An L shape in the xz-plane with the reflex corner at index 3, which a fan from index 0
+        // would cover outside the polygon.
+        let vertices = [
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, 2.0],
+            [1.0, 0.0, 2.0],
+            [1.0, 0.0, 1.0],
+            [2.0, 0.0, 1.0],
+            [2.0, 0.0, 0.0],
+        ];
+        let triangles = triangulate(&vertices);
+        assert_eq!(triangles.len(), 4);
+        assert!((area(&vertices, &triangles) - 3.0).abs() < 1e-6);
+        assert_same_winding(&vertices, &triangles);
+    }
+
+    #[test]
+    fn concave_polygon_with_either_winding() {
+        let mut vertices = vec![
+            [0.0, 0.0, 0.0],
+            [4.0, 0.0, 0.0],
+            [4.0, 4.0, 0.0],
+            [2.0, 1.0, 0.0],
+            [0.0, 4.0, 0.0],
+        ];
+        for _ in 0..2 {
+            let triangles = triangulate(&vertices);
+            assert_eq!(triangles.len(), 3);
+            assert!((area(&vertices, &triangles) - 10.0).abs() < 1e-5);
+            assert_same_winding(&vertices, &triangles);
+            vertices.reverse();
+        }
+    }
+}